@@ -1,75 +1,354 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use clap::ValueEnum;
+use ratatui::layout::Direction;
 use ratatui::prelude::Stylize;
 use ratatui::text::{Line, Span};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 use tokio::sync::Mutex;
+use tracing::Level;
+
+use crate::attachment::Attachment;
+use crate::conversation::Conversation;
+use crate::debug_log::DebugLog;
+use crate::error_watch::Failure;
+use crate::keymap::Keymap;
+use crate::markdown::{self, CodeBlock, SelectedCodeBlock};
+use crate::recording::Recorder;
+use crate::services::chat_service::{estimate_tokens, Parameters, Role};
+use crate::session::{self, Session};
+use crate::terminal_utils::PtySizer;
+use crate::theme::Theme;
+use crate::usage::{Prices, Usage};
 
 pub enum Mode {
     Terminal,
     Chat,
 }
-#[derive(PartialEq)]
+
+/// How the terminal and the chat are arranged on the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PaneLayout {
+    // The terminal on the left, the chat on the right
+    #[default]
+    SideBySide,
+    // The terminal on top, the chat below it
+    Stacked,
+}
+
+impl PaneLayout {
+    pub fn direction(&self) -> Direction {
+        match self {
+            PaneLayout::SideBySide => Direction::Horizontal,
+            PaneLayout::Stacked => Direction::Vertical,
+        }
+    }
+
+    /// The columns and rows the shell gets to draw on, for a screen of `width` x `height`.
+    pub fn terminal_size(&self, width: u16, height: u16) -> (u16, u16) {
+        match self {
+            PaneLayout::SideBySide => (
+                (width as f32 * 0.57).floor() as u16,
+                height.saturating_sub(5),
+            ),
+            // The terminal gets 60% of what's above the footer, minus its borders
+            PaneLayout::Stacked => (
+                width.saturating_sub(2),
+                ((height.saturating_sub(1) as f32 * 0.6).floor() as u16).saturating_sub(2),
+            ),
+        }
+    }
+}
+
+/// What the debug pane shows.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DebugView {
+    Log,
+    // The raw request and response of the last call to the AI
+    LastCall,
+}
+
+/// The debug pane, while it is open.
+pub struct DebugPane {
+    pub view: DebugView,
+    // The least severe level of the log records shown
+    pub level: Level,
+    // How far the log is scrolled up from the latest record, or the last call down from the top
+    pub scroll: u16,
+}
+
+impl Default for DebugPane {
+    fn default() -> Self {
+        Self {
+            view: DebugView::Log,
+            level: Level::TRACE,
+            scroll: 0,
+        }
+    }
+}
+
+/// The list of models to switch to, while it is open.
+#[derive(Default)]
+pub struct ModelPicker {
+    // Empty while the provider is asked which models there are
+    pub models: Vec<String>,
+    pub selected: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum MessageSender {
     Assistant,
     User,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Message {
     pub sender: MessageSender,
     pub message: String,
+    // The terminal output the user sent along with the message, which isn't shown in the chat
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terminal_context: Option<String>,
+    // When the message was sent, or the answer started. Sessions saved before we kept track don't have it.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub timestamp: Option<OffsetDateTime>,
+    // The tokens the answer took, for answers from the AI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    // Whether the answer stopped at the token limit, rather than where the AI meant to stop
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    // The docs the excerpts given to the AI for the answer came from, like `docs/runbooks/bgp.md:12-40`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+    // The files the user attached to the message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
 }
 
 impl Message {
-    pub fn style(&self, width: usize, role: String) -> Vec<Line> {
+    pub fn user(message: String, terminal_context: Option<String>) -> Self {
+        Self {
+            sender: MessageSender::User,
+            message,
+            terminal_context,
+            timestamp: Some(OffsetDateTime::now_utc()),
+            usage: None,
+            truncated: false,
+            sources: vec![],
+            attachments: vec![],
+        }
+    }
+
+    pub fn assistant(message: String) -> Self {
+        Self {
+            sender: MessageSender::Assistant,
+            message,
+            terminal_context: None,
+            timestamp: Some(OffsetDateTime::now_utc()),
+            usage: None,
+            truncated: false,
+            sources: vec![],
+            attachments: vec![],
+        }
+    }
+
+    /// What the AI gets to see of the message, including the terminal output and the files sent with it.
+    pub fn prompt(&self) -> String {
+        let mut prompt = match &self.terminal_context {
+            Some(terminal_context) => format!(
+                "This is my terminal output: \n\n ```\n{}\n```\n\n",
+                terminal_context,
+            ),
+            None => String::new(),
+        };
+        for attachment in &self.attachments {
+            prompt.push_str(&attachment.prompt());
+            prompt.push('\n');
+        }
+        prompt.push_str(&self.message);
+        prompt
+    }
+
+    /// Roughly how many tokens the message takes up when sent to the AI.
+    pub fn estimated_tokens(&self) -> usize {
+        estimate_tokens(&self.message)
+            + self.terminal_context.as_deref().map_or(0, estimate_tokens)
+            + self
+                .attachments
+                .iter()
+                .map(|attachment| estimate_tokens(&attachment.content))
+                .sum::<usize>()
+    }
+
+    /// Styles the message for the chat. Code blocks in assistant messages are numbered from `first_code_block`.
+    /// `branch` is which of its alternatives the message is, and how many there are. Answers are headed with the
+    /// role of the `chat` and the model that gave them, or the model of the chat while that isn't known yet.
+    pub fn style(
+        &self,
+        width: usize,
+        chat: &Chat,
+        first_code_block: usize,
+        selected_code_block: Option<SelectedCodeBlock>,
+        branch: Option<(usize, usize)>,
+        theme: &Theme,
+    ) -> Vec<Line<'_>> {
         let mut lines: Vec<Line> = vec![];
+        // Only shown when there is something to switch to
+        let branch = match branch {
+            Some((position, count)) if count > 1 => format!(" ‹ {}/{} › ", position + 1, count),
+            _ => String::new(),
+        };
+        let branch_width = branch.chars().count();
 
         match self.sender {
             MessageSender::User => {
+                let label = match theme.emoji {
+                    true => Span::raw("💻 You 💻┐"),
+                    false => Span::raw("─ You ─┐"),
+                };
                 lines.push(
-                    Line::raw(format!(
-                        "{}💻 You 💻┐",
-                        "─".repeat(width.saturating_sub(12)),
-                    ))
+                    Line::from(vec![
+                        Span::raw(
+                            "─".repeat(width.saturating_sub(label.width() + 2 + branch_width)),
+                        ),
+                        Span::raw(branch),
+                        label,
+                    ])
                     .right_aligned()
                     .bold()
-                    .yellow(),
+                    .fg(theme.user_message),
                 );
                 lines.extend(
                     self.message
                         .lines()
-                        .map(|m| Line::from(m).right_aligned().yellow())
+                        .map(|m| Line::from(m).right_aligned().fg(theme.user_message))
                         .collect::<Vec<Line>>(),
-                )
+                );
+                if !self.attachments.is_empty() {
+                    lines.push(attachment_chips(&self.attachments, theme).right_aligned());
+                }
             }
             MessageSender::Assistant => {
+                let role = chat.role.name();
+                let model = self
+                    .usage
+                    .as_ref()
+                    .map_or(chat.parameters.model.as_str(), |usage| usage.model.as_str());
+                let (start, end) = match theme.emoji {
+                    true => ("┌🤖 ", " 🤖"),
+                    false => ("┌─ ", " ─"),
+                };
                 let mut header_spans = vec![];
-                header_spans.push(Span::raw("┌🤖 GPT"));
+                header_spans.push(Span::raw(start));
+                header_spans.push(Span::raw(model.to_string()));
                 if role.is_empty() {
-                    header_spans.push(Span::raw(" 🤖"))
+                    header_spans.push(Span::raw(end))
                 } else {
                     header_spans.push(Span::raw(" - "));
-                    header_spans.push(Span::raw(format!("{}", role)).on_dark_gray());
-                    header_spans.push(Span::raw(" 🤖"));
+                    header_spans.push(Span::raw(role).bg(theme.role_background));
+                    header_spans.push(Span::raw(end));
+                }
+                header_spans.push(Span::raw(branch));
+                // The width includes the borders of the chat block
+                let header_width: usize = header_spans.iter().map(Span::width).sum();
+                header_spans.push(Span::raw(
+                    "─".repeat(width.saturating_sub(header_width + 2)),
+                ));
+
+                lines.push(
+                    Line::from(header_spans)
+                        .left_aligned()
+                        .bold()
+                        .fg(theme.assistant_header),
+                );
+                // The width includes the borders of the chat block
+                lines.extend(markdown::render(
+                    &self.message,
+                    width.saturating_sub(2),
+                    first_code_block,
+                    selected_code_block,
+                    theme,
+                ));
+                if self.truncated {
+                    lines.push(
+                        Line::from("… cut off at the token limit, /continue for the rest")
+                            .fg(theme.error_border),
+                    );
+                }
+                if !self.sources.is_empty() {
+                    lines.push(
+                        Line::from(format!("Sources: {}", self.sources.join(", "))).fg(theme.muted),
+                    );
+                }
+                if let Some(usage) = &self.usage {
+                    lines.push(Line::from(usage.describe()).right_aligned().fg(theme.muted));
                 }
-                header_spans.push(Span::raw(format!(
-                    "{}",
-                    "─"
-                        .to_string()
-                        .repeat(width.saturating_sub(15 + role.len()))
-                )));
-
-                lines.push(Line::from(header_spans).left_aligned().bold().light_green());
-                lines.extend(
-                    self.message
-                        .lines()
-                        .map(|m| Line::from(m).left_aligned())
-                        .collect::<Vec<Line>>(),
-                )
             }
         }
         lines.push(Line::from("").centered());
         lines
     }
+
+    /// The code blocks the user can act on, which is only those the AI wrote.
+    pub fn code_blocks(&self) -> Vec<CodeBlock> {
+        match self.sender {
+            MessageSender::User => vec![],
+            MessageSender::Assistant => markdown::code_blocks(&self.message),
+        }
+    }
+}
+
+/// One of the conversations the user has open, with its own name, role, model and session.
+pub struct Chat {
+    pub name: String,
+    pub role: Role,
+    // The model and how it is asked, which can be changed with commands
+    pub parameters: Parameters,
+    pub conversation: Conversation,
+    // Where the conversation is saved, so it can be resumed later
+    pub session_path: PathBuf,
+}
+
+impl Chat {
+    /// A new chat in a new session, greeted by the AI in its role.
+    pub fn new(name: String, role: Role, parameters: Parameters) -> Self {
+        let mut conversation = Conversation::default();
+        if let Some(greeting) = role.greeting() {
+            conversation.push(Message::assistant(greeting.to_string()));
+        }
+        Self {
+            name,
+            role,
+            parameters,
+            conversation,
+            session_path: session::new_path(),
+        }
+    }
+
+    /// A chat picking up a saved session where it was left off. Sessions from before chats had a name or model get
+    /// the name of their role, and `parameters`. A `model` the user asked for wins over the one of the session.
+    pub fn resume(
+        session: Session,
+        session_path: PathBuf,
+        parameters: Parameters,
+        model: Option<String>,
+    ) -> Self {
+        Self {
+            name: session
+                .name
+                .unwrap_or_else(|| session.role.title().to_string()),
+            role: session.role,
+            parameters: Parameters {
+                model: model.or(session.model).unwrap_or(parameters.model),
+                ..parameters
+            },
+            conversation: session.conversation,
+            session_path,
+        }
+    }
 }
 
 pub struct AppState {
@@ -77,11 +356,61 @@ pub struct AppState {
     pub current_mode: Mode,
     pub tick: i64,
     pub terminal_context: Arc<Mutex<String>>,
+    // Records the terminal output, also after restarting the shell
+    pub recorder: Option<Arc<Mutex<Recorder>>>,
+    // Resizes the pseudo terminal of the shell, which there isn't while a recording is replayed
+    pub pty_sizer: Option<PtySizer>,
     pub user_chat_to_send_to_gpt: String,
-    pub chat_history: Vec<Message>,
+    // The files attached with /attach to the message being typed
+    pub attachments: Vec<Attachment>,
+    // The chat that is shown
+    pub chat: Chat,
+    // The chats in the background, in the order they were opened
+    pub other_chats: Vec<Chat>,
+    // Where the chat that is shown goes among the others
+    pub chat_index: usize,
+    // The selected chat in the list of chats, while it is open
+    pub chat_list: Option<usize>,
+    // The models to pick from, from the config. Without any, the provider is asked.
+    pub models: Vec<String>,
+    pub model_picker: Option<ModelPicker>,
+    pub layout: PaneLayout,
+    // The shell to run in the terminal, instead of the user's default shell
+    pub shell: Option<String>,
+    // Which keys are commands for the app, rather than input for the shell or the chat
+    pub keymap: Keymap,
+    pub theme: Theme,
+    // What each model costs, to show what the session has cost so far
+    pub prices: Prices,
+    pub debug_log: Arc<DebugLog>,
+    pub debug_pane: Option<DebugPane>,
     pub disable_chat: bool,
+    // How many (wrapped) lines the chat is scrolled down from the top
     pub scroll: u16,
+    // Keep the chat scrolled to the bottom as new content streams in, until the user scrolls up
+    pub follow_chat: bool,
+    // Updated on every render, as both depend on the size of the chat and how its lines wrap
+    pub max_scroll: u16,
+    pub chat_height: u16,
     pub terminal_has_been_active: bool,
+    // Set when the shell has exited or the pseudo terminal failed, describing what happened
+    pub terminal_exit: Option<String>,
+    // The code block in the chat the user has selected, counting from the first message
+    pub selected_code_block: Option<usize>,
+    // A command waiting for the user to confirm it should run in the terminal
+    pub pending_run: Option<String>,
+    // A short message for the user, shown in the footer until the next key press
+    pub status_message: Option<String>,
+    // The message in the chat the user has selected to act on
+    pub selected_message: Option<usize>,
+    // The user message being edited in the input box, which is resent from that point
+    pub editing_message: Option<usize>,
+    // Whether the help, with all keys and commands, is shown over everything else
+    pub show_help: bool,
+    // Whether to watch the terminal for failed commands, and offer to explain them
+    pub watch_errors: bool,
+    // The last command that failed in the terminal, until it is explained or a command succeeds
+    pub error_hint: Option<Failure>,
 }
 
 impl AppState {
@@ -90,12 +419,43 @@ impl AppState {
             running: true,
             current_mode: Mode::Chat,
             terminal_context,
+            recorder: None,
+            pty_sizer: None,
             tick: 0,
             user_chat_to_send_to_gpt: String::new(),
-            chat_history: Vec::new(),
+            attachments: vec![],
+            chat: Chat::new(
+                Role::default().title().to_string(),
+                Role::default(),
+                Parameters::default(),
+            ),
+            other_chats: vec![],
+            chat_index: 0,
+            chat_list: None,
+            models: vec![],
+            model_picker: None,
+            layout: PaneLayout::default(),
+            shell: None,
+            keymap: Keymap::default(),
+            theme: Theme::default(),
+            prices: Prices::default(),
+            debug_log: Arc::default(),
+            debug_pane: None,
             disable_chat: false,
             scroll: 0,
+            follow_chat: true,
+            max_scroll: 0,
+            chat_height: 0,
             terminal_has_been_active: false,
+            terminal_exit: None,
+            selected_code_block: None,
+            pending_run: None,
+            status_message: None,
+            selected_message: None,
+            editing_message: None,
+            show_help: false,
+            watch_errors: true,
+            error_hint: None,
         }
     }
 
@@ -109,6 +469,138 @@ impl AppState {
         }
     }
 
+    /// All code blocks in the chat, numbered the way they are rendered.
+    pub fn code_blocks(&self) -> Vec<CodeBlock> {
+        self.chat
+            .conversation
+            .messages()
+            .into_iter()
+            .flat_map(Message::code_blocks)
+            .collect()
+    }
+
+    /// Saves the conversation to the session file. Failing to do so isn't worth interrupting the user over,
+    /// so it is only logged.
+    pub fn save_session(&self) {
+        if self.chat.conversation.is_empty() {
+            return;
+        }
+        if let Err(err) = session::save(&self.chat) {
+            tracing::warn!("Failed to save session: {:?}", err);
+        }
+    }
+
+    /// All open chats, in the order they were opened.
+    pub fn chats(&self) -> Vec<&Chat> {
+        let mut chats: Vec<&Chat> = self.other_chats.iter().collect();
+        chats.insert(self.chat_index, &self.chat);
+        chats
+    }
+
+    /// A name for a new chat that no open chat has yet, like `base` or `base 2`.
+    pub fn unique_chat_name(&self, base: &str) -> String {
+        let names: Vec<&str> = self.chats().iter().map(|chat| chat.name.as_str()).collect();
+        (1..)
+            .map(|number| match number {
+                1 => base.to_string(),
+                number => format!("{} {}", base, number),
+            })
+            .find(|name| !names.contains(&name.as_str()))
+            .unwrap_or_default()
+    }
+
+    /// Shows the chat at `index` among all open chats, keeping the one shown now in the background.
+    pub fn switch_chat(&mut self, index: usize) {
+        if index == self.chat_index || index > self.other_chats.len() {
+            return;
+        }
+        self.save_session();
+        let previous_index = self.chat_index;
+        let next = self.other_chats.remove(self.other_chat_index(index));
+        let previous = std::mem::replace(&mut self.chat, next);
+        self.chat_index = index;
+        self.other_chats
+            .insert(self.other_chat_index(previous_index), previous);
+        self.reset_chat_view();
+    }
+
+    /// Where the chat at `index` among all open chats is among the others, which skip the chat shown.
+    fn other_chat_index(&self, index: usize) -> usize {
+        match index > self.chat_index {
+            true => index - 1,
+            false => index,
+        }
+    }
+
+    /// Opens `chat` after all the others, and shows it.
+    pub fn open_chat(&mut self, chat: Chat) {
+        self.save_session();
+        let previous = std::mem::replace(&mut self.chat, chat);
+        self.other_chats.insert(self.chat_index, previous);
+        self.chat_index = self.other_chats.len();
+        self.reset_chat_view();
+    }
+
+    /// Closes the chat that is shown, showing the one before it. Its session is kept, so it can be resumed.
+    /// Closing the last chat leaves a new one in its place.
+    pub fn close_chat(&mut self) {
+        self.save_session();
+        if self.other_chats.is_empty() {
+            self.chat = Chat::new(
+                self.chat.role.title().to_string(),
+                self.chat.role,
+                self.chat.parameters.clone(),
+            );
+        } else {
+            self.chat_index = self.chat_index.saturating_sub(1);
+            self.chat = self.other_chats.remove(self.chat_index);
+        }
+        self.reset_chat_view();
+    }
+
+    /// Forgets what was selected and scrolled in the chat shown before.
+    fn reset_chat_view(&mut self) {
+        self.selected_code_block = None;
+        self.selected_message = None;
+        self.editing_message = None;
+        self.scroll_to_bottom();
+    }
+
+    /// Moves the code block selection by `offset`, starting from the latest code block if none is selected.
+    pub fn select_code_block(&mut self, offset: isize) {
+        let count = self.code_blocks().len();
+        if count == 0 {
+            self.selected_code_block = None;
+            return;
+        }
+        let selected = match self.selected_code_block {
+            Some(selected) => selected.saturating_add_signed(offset),
+            None => count - 1,
+        };
+        self.selected_code_block = Some(selected.min(count - 1));
+    }
+
+    pub fn scroll_up(&mut self, lines: u16) {
+        self.scroll = self.scroll.min(self.max_scroll).saturating_sub(lines);
+        self.follow_chat = false;
+    }
+
+    pub fn scroll_down(&mut self, lines: u16) {
+        self.scroll = self.scroll.saturating_add(lines).min(self.max_scroll);
+        // Scrolling all the way down starts following new content again
+        self.follow_chat = self.scroll == self.max_scroll;
+    }
+
+    pub fn scroll_to_top(&mut self) {
+        self.scroll = 0;
+        self.follow_chat = false;
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll = self.max_scroll;
+        self.follow_chat = true;
+    }
+
     pub fn tick(&mut self) {
         self.tick += 1;
     }
@@ -117,3 +609,21 @@ impl AppState {
         self.running = false;
     }
 }
+
+/// The attached files as chips, like `📎 nginx.conf 2.1 KB`.
+pub fn attachment_chips(attachments: &[Attachment], theme: &Theme) -> Line<'static> {
+    let mut spans = vec![];
+    for attachment in attachments {
+        if !spans.is_empty() {
+            spans.push(Span::raw(" "));
+        }
+        spans.push(
+            Span::raw(match theme.emoji {
+                true => format!(" 📎 {} ", attachment.label()),
+                false => format!(" [{}] ", attachment.label()),
+            })
+            .fg(theme.accent),
+        );
+    }
+    Line::from(spans)
+}
@@ -0,0 +1,265 @@
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::export::fence_for;
+
+/// The most of a file that is sent along with a message. Bigger files are cut off, and the AI is told so.
+pub const MAX_ATTACHMENT_SIZE: usize = 32 * 1024;
+/// The most files a message can have, so a few big configs don't crowd out the conversation.
+pub const MAX_ATTACHMENTS: usize = 8;
+/// How much of the start of a file is checked for NUL bytes, which text files don't have.
+const BINARY_CHECK_SIZE: usize = 8 * 1024;
+
+/// A file attached to a message, as it was when it was attached.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Attachment {
+    // The path as the user gave it, which is what the chat shows and the AI sees
+    pub path: String,
+    pub content: String,
+    // The size of the whole file, in bytes
+    pub size: u64,
+    // Whether only the start of the file is in `content`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+impl Attachment {
+    /// Reads the file at `path`. Folders and binary files can't be attached, and of big files only the start is.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let failed = |err: std::io::Error| format!("Failed to read {}: {}", path, err);
+        let file = fs::File::open(resolve(path)).map_err(failed)?;
+        let metadata = file.metadata().map_err(failed)?;
+        if metadata.is_dir() {
+            return Err(format!("{} is a folder, not a file", path));
+        }
+        let mut bytes = vec![];
+        file.take(MAX_ATTACHMENT_SIZE as u64 + 1)
+            .read_to_end(&mut bytes)
+            .map_err(failed)?;
+        if bytes[..bytes.len().min(BINARY_CHECK_SIZE)].contains(&0) {
+            return Err(format!("{} is a binary file", path));
+        }
+
+        let truncated = bytes.len() > MAX_ATTACHMENT_SIZE;
+        if truncated {
+            // End on a whole line, if there is one
+            let end = bytes[..MAX_ATTACHMENT_SIZE]
+                .iter()
+                .rposition(|byte| *byte == b'\n')
+                .map_or(MAX_ATTACHMENT_SIZE, |newline| newline + 1);
+            bytes.truncate(end);
+        }
+        let content = match String::from_utf8(bytes) {
+            Ok(content) => content,
+            // Cutting the file off can split a character in two, which is all right
+            Err(err) if truncated && err.utf8_error().error_len().is_none() => {
+                let valid = err.utf8_error().valid_up_to();
+                String::from_utf8_lossy(&err.into_bytes()[..valid]).into_owned()
+            }
+            Err(_) => return Err(format!("{} isn't a text file", path)),
+        };
+        Ok(Self {
+            path: path.to_string(),
+            content,
+            size: metadata.len(),
+            truncated,
+        })
+    }
+
+    /// Like `nginx.conf 2.1 KB`, for the chip on the message.
+    pub fn label(&self) -> String {
+        let name = Path::new(&self.path)
+            .file_name()
+            .map_or(self.path.clone(), |name| {
+                name.to_string_lossy().into_owned()
+            });
+        match self.truncated {
+            true => format!("{} {}, cut off", name, format_size(self.size)),
+            false => format!("{} {}", name, format_size(self.size)),
+        }
+    }
+
+    /// What the AI gets to see of the file, saying so if it was cut off.
+    pub fn prompt(&self) -> String {
+        let fence = fence_for(&self.content);
+        let mut prompt = format!(
+            "This is the file {}:\n\n{}\n{}\n{}\n",
+            self.path,
+            fence,
+            self.content.trim_end(),
+            fence
+        );
+        if self.truncated {
+            prompt.push_str(&format!(
+                "\nThe file is {}, and only the first {} of it are included.\n",
+                format_size(self.size),
+                format_size(self.content.len() as u64)
+            ));
+        }
+        prompt
+    }
+}
+
+/// The path to open for `path`, which can start with `~/` for the home directory. Other paths are relative to the
+/// directory the app was started in, which the shell starts in too.
+pub fn resolve(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The files mentioned in `text` with an `@` in front, like `@nginx.conf` or `@~/.bashrc`. Mentions of files that
+/// don't exist are left as they are, as the `@` could mean something else, like a user or a BGP peer group.
+pub fn mentions(text: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = vec![];
+    for word in text.split_whitespace() {
+        let Some(path) = word.strip_prefix('@') else {
+            continue;
+        };
+        // Punctuation after the path belongs to the sentence, unless the file really has it in its name
+        let path = match resolve(path).exists() {
+            true => path,
+            false => path.trim_end_matches([',', '.', ';', ':', '?', '!', ')', '"', '\'']),
+        };
+        if !path.is_empty() && resolve(path).is_file() && !mentions.iter().any(|m| m == path) {
+            mentions.push(path.to_string());
+        }
+    }
+    mentions
+}
+
+/// The paths that start with `partial`, with a `/` after folders, for completing paths as they are typed.
+/// Hidden files are only completed once the name starts with a dot.
+pub fn complete(partial: &str) -> Vec<String> {
+    let (folder, prefix) = match partial.rfind('/') {
+        Some(slash) => partial.split_at(slash + 1),
+        None => ("", partial),
+    };
+    let folder_path = match folder {
+        "" => PathBuf::from("."),
+        folder => resolve(folder),
+    };
+    let Ok(entries) = fs::read_dir(folder_path) else {
+        return vec![];
+    };
+    let mut completions: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", folder, name, slash))
+        })
+        .collect();
+    completions.sort();
+    completions
+}
+
+/// What all of `completions` start with, which is as far as a path can be completed without picking one.
+pub fn common_prefix(completions: &[String]) -> String {
+    let Some((first, rest)) = completions.split_first() else {
+        return String::new();
+    };
+    let mut length = first.len();
+    for completion in rest {
+        length = first
+            .char_indices()
+            .zip(completion.chars())
+            .take_while(|((index, a), b)| *index < length && a == b)
+            .map(|((index, a), _)| index + a.len_utf8())
+            .last()
+            .unwrap_or(0);
+    }
+    first[..length].to_string()
+}
+
+/// Like `2.1 KB`, in the units people expect for files.
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty folder of its own for each test, as they run at the same time.
+    fn folder(test: &str) -> String {
+        let folder =
+            std::env::temp_dir().join(format!("terminal-ai-ops-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn finds_mentions_of_files() {
+        let folder = folder("mentions");
+        fs::write(format!("{}/nginx.conf", folder), "").unwrap();
+        fs::create_dir(format!("{}/logs", folder)).unwrap();
+        let text = format!(
+            "Why does @{0}/nginx.conf, unlike @{0}/nginx.conf and @{0}/logs, fail for @{0}/missing or @admins?",
+            folder
+        );
+        assert_eq!(mentions(&text), [format!("{}/nginx.conf", folder)]);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn completes_paths() {
+        let folder = folder("complete");
+        fs::write(format!("{}/notes.md", folder), "").unwrap();
+        fs::write(format!("{}/.hidden", folder), "").unwrap();
+        fs::create_dir(format!("{}/nodes", folder)).unwrap();
+
+        assert_eq!(
+            complete(&format!("{}/no", folder)),
+            [format!("{}/nodes/", folder), format!("{}/notes.md", folder)]
+        );
+        assert_eq!(
+            complete(&format!("{}/", folder)),
+            [format!("{}/nodes/", folder), format!("{}/notes.md", folder)]
+        );
+        assert_eq!(
+            complete(&format!("{}/.", folder)),
+            [format!("{}/.hidden", folder)]
+        );
+        assert!(complete(&format!("{}/missing/", folder)).is_empty());
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|string| string.to_string()).collect()
+    }
+
+    #[test]
+    fn finds_the_common_prefix() {
+        assert_eq!(common_prefix(&[]), "");
+        assert_eq!(common_prefix(&strings(&["nodes/"])), "nodes/");
+        assert_eq!(common_prefix(&strings(&["nodes/", "notes.md"])), "no");
+        assert_eq!(common_prefix(&strings(&["a", "b"])), "");
+        assert_eq!(
+            common_prefix(&strings(&["notes.md", "notes.md.bak", "notes"])),
+            "notes"
+        );
+    }
+
+    #[test]
+    fn stops_the_common_prefix_at_whole_characters() {
+        // `é` and `è` start with the same byte, which mustn't be split
+        assert_eq!(common_prefix(&strings(&["café", "cafè"])), "caf");
+        assert_eq!(common_prefix(&strings(&["日本語", "日本"])), "日本");
+        assert_eq!(common_prefix(&strings(&["日本", "日曜"])), "日");
+    }
+}
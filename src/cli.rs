@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use tracing::Level;
+
+use crate::{
+    app_state::PaneLayout,
+    headless,
+    services::chat_service::{parse_temperature, Provider, Role},
+    settings::Settings,
+};
+
+/// An AI assistant that can see your terminal.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// The config file to use, instead of config.toml in your config directory
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Which API to reach the AI through
+    #[arg(long, value_enum)]
+    pub provider: Option<Provider>,
+    /// The model to use
+    #[arg(long)]
+    pub model: Option<String>,
+    /// From 0 to 2, where higher makes the answers more random
+    #[arg(long, value_parser = parse_temperature)]
+    pub temperature: Option<f32>,
+    /// The longest an answer can be, in tokens
+    #[arg(long, value_name = "TOKENS")]
+    pub max_tokens: Option<u32>,
+    /// The role the AI starts in
+    #[arg(long, value_enum)]
+    pub role: Option<Role>,
+    /// The shell to run in the terminal, instead of your default shell
+    #[arg(long)]
+    pub shell: Option<String>,
+    /// How much to log: error, warn, info, debug or trace
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<Level>,
+    /// A file to append the log to, instead of rotated files in the log directory
+    #[arg(long, value_name = "FILE", conflicts_with = "log_dir")]
+    pub log_file: Option<PathBuf>,
+    /// Where to write the rotated log files
+    #[arg(long, value_name = "DIR")]
+    pub log_dir: Option<PathBuf>,
+    /// Never log prompts, answers, keys or terminal output
+    #[arg(long)]
+    pub private_logs: bool,
+    /// Resume the latest session, or the given session file
+    #[arg(long, value_name = "SESSION")]
+    pub resume: Option<Option<PathBuf>>,
+    /// How to arrange the terminal and the chat
+    #[arg(long, value_enum)]
+    pub layout: Option<PaneLayout>,
+    /// The colors to use: dark, light, high-contrast, or the name or path of a theme file
+    #[arg(long)]
+    pub theme: Option<String>,
+    /// Don't use emoji, for terminals that render them badly
+    #[arg(long)]
+    pub no_emoji: bool,
+    /// Record the terminal to an asciicast v2 file
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Replay an asciicast v2 file instead of starting a shell
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<headless::Command>,
+}
+
+impl Cli {
+    /// The settings from the config file, with whatever was given on the command line taking precedence.
+    pub fn settings(&self) -> Result<Settings, String> {
+        let settings = Settings::load(self.config.as_deref())?;
+        Ok(Settings {
+            provider: self.provider.or(settings.provider),
+            model: self.model.clone().or(settings.model),
+            models: settings.models,
+            temperature: self.temperature.or(settings.temperature),
+            max_tokens: self.max_tokens.or(settings.max_tokens),
+            stream_usage: settings.stream_usage,
+            role: self.role.or(settings.role),
+            shell: self.shell.clone().or(settings.shell),
+            log_level: self
+                .log_level
+                .map(|level| level.to_string())
+                .or(settings.log_level),
+            log_file: self.log_file.clone().or(settings.log_file),
+            log_dir: self.log_dir.clone().or(settings.log_dir),
+            log_rotation: settings.log_rotation,
+            log_max_files: settings.log_max_files,
+            private_logs: match self.private_logs {
+                true => Some(true),
+                false => settings.private_logs,
+            },
+            layout: self.layout.or(settings.layout),
+            theme: self.theme.clone().or(settings.theme),
+            emoji: match self.no_emoji {
+                true => Some(false),
+                false => settings.emoji,
+            },
+            watch_errors: settings.watch_errors,
+            keys: settings.keys,
+            prices: settings.prices,
+            knowledge: settings.knowledge,
+        })
+    }
+}
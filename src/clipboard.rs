@@ -0,0 +1,13 @@
+use std::io::{self, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Copies text to the system clipboard using the OSC 52 escape sequence.
+///
+/// The terminal emulator does the copying, so this works over SSH and without any clipboard tools installed,
+/// as long as the terminal supports OSC 52 (most do, although some need it enabled first).
+pub fn copy(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+    stdout.flush()
+}
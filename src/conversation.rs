@@ -0,0 +1,385 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{app_state::Message, usage::Usage};
+
+/// A conversation with the AI, kept as a tree rather than a list.
+///
+/// Editing a question or regenerating an answer doesn't throw away what was there, but adds a sibling next to it.
+/// The user can flip between siblings, and whichever are selected make up the active branch: the messages shown in
+/// the chat and sent to the AI. Most functions here take an index into that branch, which is also the index of the
+/// message in the chat.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedConversation")]
+pub struct Conversation {
+    nodes: Vec<Node>,
+    // The alternatives for the first message
+    roots: Vec<usize>,
+    selected_root: usize,
+    // The usage of answers that were deleted, which were paid for all the same
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed_usage: Vec<Usage>,
+}
+
+// A conversation as it is in a session file, which could have been edited or cut short, so its nodes are checked
+// before they are used
+#[derive(Deserialize)]
+struct UncheckedConversation {
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    selected_root: usize,
+    #[serde(default)]
+    removed_usage: Vec<Usage>,
+}
+
+impl TryFrom<UncheckedConversation> for Conversation {
+    type Error = String;
+
+    /// Fails unless the nodes make up a tree: every id is that of a node, and every node has the parent it says
+    /// it has, and is reached once. Otherwise walking the tree could panic or never end.
+    fn try_from(unchecked: UncheckedConversation) -> Result<Self, Self::Error> {
+        let nodes = &unchecked.nodes;
+        let mut reached = vec![false; nodes.len()];
+        let mut stack: Vec<(usize, Option<usize>)> =
+            unchecked.roots.iter().map(|&root| (root, None)).collect();
+        while let Some((id, parent)) = stack.pop() {
+            let Some(node) = nodes.get(id) else {
+                return Err(format!(
+                    "the conversation refers to message {}, but has only {}",
+                    id,
+                    nodes.len()
+                ));
+            };
+            if std::mem::replace(&mut reached[id], true) {
+                return Err(format!(
+                    "message {} of the conversation is in more than one place",
+                    id
+                ));
+            }
+            if node.parent != parent {
+                return Err(format!(
+                    "message {} of the conversation has the wrong parent",
+                    id
+                ));
+            }
+            stack.extend(node.children.iter().map(|&child| (child, Some(id))));
+        }
+        Ok(Self {
+            nodes: unchecked.nodes,
+            roots: unchecked.roots,
+            selected_root: unchecked.selected_root,
+            removed_usage: unchecked.removed_usage,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Node {
+    message: Message,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    selected_child: usize,
+}
+
+impl Conversation {
+    /// The node ids of the active branch, from the first message to the last.
+    fn path(&self) -> Vec<usize> {
+        let mut path = vec![];
+        let mut next = self.roots.get(self.selected_root).copied();
+        while let Some(id) = next {
+            path.push(id);
+            let node = &self.nodes[id];
+            next = node.children.get(node.selected_child).copied();
+        }
+        path
+    }
+
+    /// The messages of the active branch.
+    pub fn messages(&self) -> Vec<&Message> {
+        self.path()
+            .into_iter()
+            .map(|id| &self.nodes[id].message)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.path().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Message> {
+        self.path().get(index).map(|id| &self.nodes[*id].message)
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut Message> {
+        let id = *self.path().last()?;
+        Some(&mut self.nodes[id].message)
+    }
+
+    /// Adds a message to the end of the active branch.
+    pub fn push(&mut self, message: Message) {
+        let parent = self.path().last().copied();
+        self.add_child(parent, message);
+    }
+
+    /// Adds an alternative to the message at `index`, and makes it part of the active branch.
+    /// Whatever came after the message at `index` stays on the old branch.
+    pub fn branch(&mut self, index: usize, message: Message) {
+        let Some(&id) = self.path().get(index) else {
+            return self.push(message);
+        };
+        self.add_child(self.nodes[id].parent, message);
+    }
+
+    /// Which of its siblings the message at `index` is, and how many there are.
+    pub fn siblings(&self, index: usize) -> Option<(usize, usize)> {
+        let id = *self.path().get(index)?;
+        let siblings = self.siblings_of(self.nodes[id].parent);
+        let position = siblings.iter().position(|sibling| *sibling == id)?;
+        Some((position, siblings.len()))
+    }
+
+    /// Replaces the message at `index` in the active branch with one of its siblings.
+    pub fn select_sibling(&mut self, index: usize, offset: isize) {
+        let Some((position, count)) = self.siblings(index) else {
+            return;
+        };
+        let position = position.saturating_add_signed(offset).min(count - 1);
+        let parent = self.nodes[self.path()[index]].parent;
+        match parent {
+            Some(parent) => self.nodes[parent].selected_child = position,
+            None => self.selected_root = position,
+        }
+    }
+
+    /// Removes the messages from `start` to `end` (exclusive) in the active branch.
+    /// The messages following them are kept, along with their alternatives, and take their place.
+    pub fn remove(&mut self, start: usize, end: usize) {
+        let path = self.path();
+        let Some(&first) = path.get(start) else {
+            return;
+        };
+        let parent = self.nodes[first].parent;
+        // The last removed message holds what follows, and which of it is selected
+        let (following, selected_following) =
+            match end.checked_sub(1).and_then(|last| path.get(last)) {
+                Some(&last) if end < path.len() => {
+                    let last = &self.nodes[last];
+                    (last.children.clone(), last.selected_child)
+                }
+                _ => (vec![], 0),
+            };
+        for id in &following {
+            self.nodes[*id].parent = parent;
+        }
+
+        let siblings = match parent {
+            Some(parent) => &mut self.nodes[parent].children,
+            None => &mut self.roots,
+        };
+        let position = siblings
+            .iter()
+            .position(|sibling| *sibling == first)
+            .unwrap_or(0);
+        let selected = match following.is_empty() {
+            true => position,
+            false => position + selected_following,
+        };
+        siblings.splice(position..=position, following);
+        let selected = selected.min(siblings.len().saturating_sub(1));
+        match parent {
+            Some(parent) => self.nodes[parent].selected_child = selected,
+            None => self.selected_root = selected,
+        }
+        // The removed nodes are unreachable now, so drop them rather than keep them around in the session
+        self.compact();
+    }
+
+    /// The usage of every answer in the conversation, including alternatives that aren't selected and answers
+    /// that were deleted.
+    pub fn usage(&self) -> impl Iterator<Item = &Usage> {
+        self.nodes
+            .iter()
+            .filter_map(|node| node.message.usage.as_ref())
+            .chain(&self.removed_usage)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn siblings_of(&self, parent: Option<usize>) -> &[usize] {
+        match parent {
+            Some(parent) => &self.nodes[parent].children,
+            None => &self.roots,
+        }
+    }
+
+    fn add_child(&mut self, parent: Option<usize>, message: Message) {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            message,
+            parent,
+            children: vec![],
+            selected_child: 0,
+        });
+        match parent {
+            Some(parent) => {
+                let parent = &mut self.nodes[parent];
+                parent.children.push(id);
+                parent.selected_child = parent.children.len() - 1;
+            }
+            None => {
+                self.roots.push(id);
+                self.selected_root = self.roots.len() - 1;
+            }
+        }
+    }
+
+    /// Rebuilds the tree with only the nodes reachable from the roots.
+    fn compact(&mut self) {
+        let mut old_nodes: Vec<Option<Node>> = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let roots = std::mem::take(&mut self.roots);
+        for root in roots {
+            let id = Self::copy_subtree(&mut old_nodes, &mut self.nodes, root, None);
+            self.roots.push(id);
+        }
+        self.removed_usage.extend(
+            old_nodes
+                .into_iter()
+                .flatten()
+                .filter_map(|node| node.message.usage),
+        );
+    }
+
+    fn copy_subtree(
+        old_nodes: &mut [Option<Node>],
+        nodes: &mut Vec<Node>,
+        old_id: usize,
+        parent: Option<usize>,
+    ) -> usize {
+        let mut node = old_nodes[old_id].take().expect("a node has one parent");
+        let id = nodes.len();
+        let children = std::mem::take(&mut node.children);
+        node.parent = parent;
+        nodes.push(node);
+        for child in children {
+            let child = Self::copy_subtree(old_nodes, nodes, child, Some(id));
+            nodes[id].children.push(child);
+        }
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(conversation: &Conversation) -> Vec<&str> {
+        conversation
+            .messages()
+            .iter()
+            .map(|message| message.message.as_str())
+            .collect()
+    }
+
+    fn conversation(texts: &[&str]) -> Conversation {
+        let mut conversation = Conversation::default();
+        for text in texts {
+            conversation.push(Message::user(text.to_string(), None));
+        }
+        conversation
+    }
+
+    #[test]
+    fn branches_and_flips_between_siblings() {
+        let mut conversation = conversation(&["question", "answer", "follow-up"]);
+        conversation.branch(1, Message::assistant("regenerated".to_string()));
+        assert_eq!(texts(&conversation), ["question", "regenerated"]);
+        assert_eq!(conversation.siblings(1), Some((1, 2)));
+
+        conversation.select_sibling(1, -1);
+        assert_eq!(texts(&conversation), ["question", "answer", "follow-up"]);
+        assert_eq!(conversation.siblings(1), Some((0, 2)));
+        // Flipping past either end stays at that end
+        conversation.select_sibling(1, -1);
+        assert_eq!(conversation.siblings(1), Some((0, 2)));
+        conversation.select_sibling(1, 5);
+        assert_eq!(texts(&conversation), ["question", "regenerated"]);
+    }
+
+    #[test]
+    fn branches_the_first_message() {
+        let mut conversation = conversation(&["question", "answer"]);
+        conversation.branch(0, Message::user("edited".to_string(), None));
+        assert_eq!(texts(&conversation), ["edited"]);
+        assert_eq!(conversation.siblings(0), Some((1, 2)));
+        conversation.select_sibling(0, -1);
+        assert_eq!(texts(&conversation), ["question", "answer"]);
+    }
+
+    #[test]
+    fn removes_messages_and_keeps_what_follows() {
+        let mut conversation = conversation(&["one", "two", "three", "four"]);
+        conversation.branch(3, Message::assistant("four again".to_string()));
+        conversation.remove(1, 3);
+        assert_eq!(texts(&conversation), ["one", "four again"]);
+        // The alternatives of the message that followed are kept with it
+        assert_eq!(conversation.siblings(1), Some((1, 2)));
+
+        conversation.remove(1, 2);
+        assert_eq!(texts(&conversation), ["one", "four"]);
+        conversation.remove(0, 1);
+        assert_eq!(texts(&conversation), ["four"]);
+    }
+
+    #[test]
+    fn keeps_the_usage_of_removed_answers() {
+        let mut conversation = conversation(&["question"]);
+        let mut answer = Message::assistant("answer".to_string());
+        answer.usage = Some(Usage {
+            model: "gpt-4o".to_string(),
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            estimated: false,
+            cost: None,
+        });
+        conversation.push(answer);
+        conversation.remove(1, 2);
+        assert_eq!(texts(&conversation), ["question"]);
+        assert_eq!(conversation.usage().count(), 1);
+    }
+
+    #[test]
+    fn loads_what_it_saved() {
+        let mut conversation = conversation(&["question", "answer"]);
+        conversation.branch(1, Message::assistant("regenerated".to_string()));
+        let json = serde_json::to_string(&conversation).unwrap();
+        let loaded: Conversation = serde_json::from_str(&json).unwrap();
+        assert_eq!(texts(&loaded), ["question", "regenerated"]);
+        assert_eq!(loaded.siblings(1), Some((1, 2)));
+    }
+
+    #[test]
+    fn rejects_a_corrupt_tree() {
+        let json = serde_json::to_value(conversation(&["question", "answer"])).unwrap();
+        let corrupt = |change: fn(&mut serde_json::Value)| {
+            let mut json = json.clone();
+            change(&mut json);
+            serde_json::from_value::<Conversation>(json).is_err()
+        };
+        assert!(!corrupt(|_| {}));
+        assert!(corrupt(|json| json["roots"][0] = 7.into()));
+        assert!(corrupt(|json| json["nodes"][0]["children"][0] = 7.into()));
+        // A loop back to the first message
+        assert!(corrupt(|json| json["nodes"][1]["children"] = vec![0].into()));
+        assert!(corrupt(
+            |json| json["nodes"][1]["parent"] = serde_json::Value::Null
+        ));
+    }
+}
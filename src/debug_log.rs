@@ -0,0 +1,129 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use time::OffsetDateTime;
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// How many log records are kept for the debug pane, dropping the oldest.
+const MAX_RECORDS: usize = 1000;
+
+/// A log record, as shown in the debug pane.
+#[derive(Clone)]
+pub struct Record {
+    pub time: OffsetDateTime,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// The raw request and response of the last call to the AI.
+#[derive(Clone, Default)]
+pub struct Exchange {
+    // The request as JSON
+    pub request: String,
+    // Each streamed chunk of the response as JSON, and whatever error ended it
+    pub response: Vec<String>,
+}
+
+/// What the debug pane shows: the latest log records, and the last call to the AI.
+/// Shared between the tracing layer, the chat service and the UI.
+#[derive(Default)]
+pub struct DebugLog {
+    records: Mutex<VecDeque<Record>>,
+    exchange: Mutex<Exchange>,
+}
+
+impl DebugLog {
+    /// The records at `level` or more severe, oldest first.
+    pub fn records(&self, level: Level) -> Vec<Record> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| record.level <= level)
+            .cloned()
+            .collect()
+    }
+
+    fn push(&self, record: Record) {
+        let mut records = self.records.lock().unwrap();
+        if records.len() == MAX_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Starts a new exchange with `request`, forgetting the previous one.
+    pub fn start_exchange(&self, request: String) {
+        *self.exchange.lock().unwrap() = Exchange {
+            request,
+            response: vec![],
+        };
+    }
+
+    /// Adds a chunk of the response, or an error, to the current exchange.
+    pub fn push_response(&self, response: String) {
+        self.exchange.lock().unwrap().response.push(response);
+    }
+
+    pub fn exchange(&self) -> Exchange {
+        self.exchange.lock().unwrap().clone()
+    }
+}
+
+/// A tracing layer that keeps recent log records in a `DebugLog`, next to whatever else is logging them.
+pub struct DebugLayer {
+    debug_log: Arc<DebugLog>,
+}
+
+impl DebugLayer {
+    pub fn new(debug_log: Arc<DebugLog>) -> Self {
+        Self { debug_log }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for DebugLayer {
+    fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        self.debug_log.push(Record {
+            time: OffsetDateTime::now_utc(),
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_string(),
+            message: visitor.message,
+        });
+    }
+}
+
+/// Formats the fields of an event into one line, with the message first.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message.insert_str(0, value),
+            name => {
+                let _ = write!(self.message, " {}={}", name, value);
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message.insert_str(0, &format!("{:?}", value)),
+            name => {
+                let _ = write!(self.message, " {}={:?}", name, value);
+            }
+        }
+    }
+}
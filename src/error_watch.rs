@@ -0,0 +1,421 @@
+use std::time::{Duration, Instant};
+
+use crate::export::strip_escape_sequences;
+
+/// The most of a failed command's output that is kept, from the end, where the error usually is.
+pub const MAX_FAILURE_OUTPUT: usize = 8 * 1024;
+/// Without shell integration, errors are spotted in the output, which can have many of them in a row. One hint
+/// for them is enough.
+const PATTERN_COOLDOWN: Duration = Duration::from_secs(10);
+/// An escape sequence that is still incomplete after this many bytes isn't one we care about.
+const MAX_PENDING_SEQUENCE: usize = 512;
+/// The most of a command line that is kept, for the hint.
+const MAX_COMMAND_LENGTH: usize = 200;
+
+/// What the output says when something went wrong, and what to call it.
+const PATTERNS: [(&str, &str); 17] = [
+    ("command not found", "Command not found"),
+    ("Permission denied", "Permission denied"),
+    ("No such file or directory", "No such file or directory"),
+    ("Traceback (most recent call last)", "Python traceback"),
+    ("panicked at", "Rust panic"),
+    ("Segmentation fault", "Segmentation fault"),
+    ("Exception in thread", "Java exception"),
+    ("npm ERR!", "npm error"),
+    ("error[E", "Compiler error"),
+    (": error: ", "Compiler error"),
+    ("undefined reference to", "Linker error"),
+    ("fatal: ", "Fatal error"),
+    ("Connection refused", "Connection refused"),
+    ("Could not resolve host", "Could not resolve host"),
+    ("% Invalid input detected", "Invalid command"),
+    ("% Incomplete command", "Incomplete command"),
+    ("% Ambiguous command", "Ambiguous command"),
+];
+
+/// A command in the terminal that failed, for the AI to explain.
+#[derive(Clone, Debug)]
+pub struct Failure {
+    // What went wrong, like "`make test` exited with 2" or "Permission denied"
+    pub summary: String,
+    // The output of the command, if shell integration says where it started. Without it, the latest terminal output
+    // is the best guess.
+    pub output: Option<String>,
+}
+
+/// What the watcher saw in a piece of output.
+#[derive(Debug)]
+pub enum Watched {
+    Failed(Failure),
+    // A command exited with 0, so an earlier failure has been dealt with
+    Succeeded,
+}
+
+// Where in the prompt and command the shell is, from its OSC 133 marks
+#[derive(PartialEq)]
+enum Stage {
+    // The shell is starting up, and nothing has been typed yet, so what it prints is no command's output
+    Starting,
+    Prompt,
+    Command,
+    Output,
+}
+
+/// Watches the output of the shell for commands that fail.
+///
+/// Shells set up for shell integration mark the prompt, the command and its output with OSC 133 sequences, ending
+/// with the exit code of the command, which is how failures are best told apart. Without it, the output is searched
+/// for common errors instead.
+pub struct ErrorWatcher {
+    // Whether the shell has sent any OSC 133 marks, after which their exit codes are all that count
+    integrated: bool,
+    stage: Stage,
+    // The command line being typed, between the end of the prompt and the start of the output
+    command: String,
+    // The output of the running command, without escape sequences
+    output: String,
+    // The first error in the output of the running command
+    pattern: Option<&'static str>,
+    // The end of the last output, if it stopped in the middle of an escape sequence or a line
+    pending: String,
+    line: String,
+    last_pattern_hint: Option<Instant>,
+}
+
+impl Default for ErrorWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorWatcher {
+    pub fn new() -> Self {
+        Self {
+            integrated: false,
+            stage: Stage::Starting,
+            command: String::new(),
+            output: String::new(),
+            pattern: None,
+            pending: String::new(),
+            line: String::new(),
+            last_pattern_hint: None,
+        }
+    }
+
+    /// Tells the watcher the user has typed into the terminal. Without shell integration, that is when the output
+    /// starts to come from commands rather than from the shell starting up.
+    pub fn typed(&mut self) {
+        if self.stage == Stage::Starting {
+            self.stage = Stage::Output;
+        }
+    }
+
+    /// Looks at the next piece of output. While a full screen program like vim runs in the `alternate_screen`, its
+    /// output is what is on the screen rather than what went wrong, so it isn't searched for errors.
+    pub fn watch(&mut self, output: &str, alternate_screen: bool) -> Vec<Watched> {
+        let mut text = std::mem::take(&mut self.pending);
+        text.push_str(output);
+        if let Some(escape) = text.rfind('\x1b') {
+            if text.len() - escape < MAX_PENDING_SEQUENCE && incomplete(&text[escape..]) {
+                self.pending = text.split_off(escape);
+            }
+        }
+
+        let mut watched = vec![];
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find("\x1b]133;") {
+            self.take(&rest[..start], alternate_screen, &mut watched);
+            let mark = &rest[start + "\x1b]133;".len()..];
+            let (mark, after) = match (mark.find('\x07'), mark.find("\x1b\\")) {
+                (Some(bel), Some(st)) if st < bel => (&mark[..st], &mark[st + 2..]),
+                (Some(bel), _) => (&mark[..bel], &mark[bel + 1..]),
+                (None, Some(st)) => (&mark[..st], &mark[st + 2..]),
+                (None, None) => (mark, ""),
+            };
+            self.mark(mark, &mut watched);
+            rest = after;
+        }
+        self.take(rest, alternate_screen, &mut watched);
+        watched
+    }
+
+    /// Handles an OSC 133 mark: A where the prompt starts, B where the command starts, C where its output starts,
+    /// and D with its exit code once it is done.
+    fn mark(&mut self, mark: &str, watched: &mut Vec<Watched>) {
+        // Whatever came before the first mark wasn't marked as a command, so its exit code is no command's either
+        if !self.integrated {
+            self.integrated = true;
+            self.stage = Stage::Prompt;
+        }
+        let mut parts = mark.split(';');
+        match parts.next() {
+            Some("A") => self.stage = Stage::Prompt,
+            Some("B") => {
+                self.stage = Stage::Command;
+                self.command.clear();
+            }
+            Some("C") => {
+                self.stage = Stage::Output;
+                self.output.clear();
+                self.line.clear();
+                self.pattern = None;
+            }
+            Some("D") => {
+                // Only commands that ran have output, an empty prompt also ends with D
+                if self.stage == Stage::Output {
+                    match parts
+                        .next()
+                        .and_then(|code| code.trim().parse::<i32>().ok())
+                    {
+                        Some(0) => watched.push(Watched::Succeeded),
+                        Some(code) => watched.push(Watched::Failed(self.failure(code))),
+                        None => {}
+                    }
+                }
+                self.stage = Stage::Prompt;
+            }
+            _ => {}
+        }
+    }
+
+    fn failure(&self, code: i32) -> Failure {
+        let command = last_line(&self.command);
+        let mut summary = match command.is_empty() {
+            true => format!("The command exited with {}", code),
+            false => format!("`{}` exited with {}", command, code),
+        };
+        if let Some(pattern) = self.pattern {
+            summary.push_str(&format!(" ({})", pattern.to_lowercase()));
+        }
+        Failure {
+            summary,
+            output: Some(self.output.clone()),
+        }
+    }
+
+    /// Takes in output between the marks.
+    fn take(&mut self, text: &str, alternate_screen: bool, watched: &mut Vec<Watched>) {
+        if text.is_empty() {
+            return;
+        }
+        match self.stage {
+            Stage::Starting | Stage::Prompt => return,
+            Stage::Command => {
+                // Backspaces are control characters too, but `last_line` needs them to apply the editing
+                let text = text
+                    .split(['\x08', '\x7f'])
+                    .map(strip_escape_sequences)
+                    .collect::<Vec<_>>()
+                    .join("\x08");
+                self.command.push_str(&text);
+                if self.command.len() > MAX_COMMAND_LENGTH * 4 {
+                    self.command = last_line(&self.command);
+                }
+                return;
+            }
+            Stage::Output => {}
+        }
+        let text = strip_escape_sequences(text);
+        self.output.push_str(&text);
+        keep_tail(&mut self.output);
+        if alternate_screen {
+            self.line.clear();
+            return;
+        }
+
+        self.line.push_str(&text);
+        while let Some(newline) = self.line.find('\n') {
+            let line: String = self.line.drain(..=newline).collect();
+            let Some((_, pattern)) = PATTERNS.iter().find(|(needle, _)| line.contains(needle))
+            else {
+                continue;
+            };
+            if self.integrated {
+                // The exit code decides, the first error only says what went wrong
+                self.pattern.get_or_insert(pattern);
+            } else if self
+                .last_pattern_hint
+                .is_none_or(|last| last.elapsed() >= PATTERN_COOLDOWN)
+            {
+                self.last_pattern_hint = Some(Instant::now());
+                watched.push(Watched::Failed(Failure {
+                    summary: pattern.to_string(),
+                    output: None,
+                }));
+            }
+        }
+        // Output without newlines, like a progress bar, would pile up
+        if self.line.len() > MAX_FAILURE_OUTPUT {
+            self.line.clear();
+        }
+    }
+}
+
+/// The end of the terminal output, without escape sequences, for failures spotted without shell integration.
+pub fn recent_output(terminal_context: &str) -> String {
+    let mut output = strip_escape_sequences(terminal_context);
+    keep_tail(&mut output);
+    output
+}
+
+/// Only keeps the last `MAX_FAILURE_OUTPUT` bytes of `output`.
+fn keep_tail(output: &mut String) {
+    if output.len() > MAX_FAILURE_OUTPUT {
+        let mut cut = output.len() - MAX_FAILURE_OUTPUT;
+        while !output.is_char_boundary(cut) {
+            cut += 1;
+        }
+        output.drain(..cut);
+    }
+}
+
+/// Whether `text`, which starts with an escape, stops before the escape sequence ends.
+fn incomplete(text: &str) -> bool {
+    let mut chars = text.chars().skip(1);
+    match chars.next() {
+        None => true,
+        Some('[') => !chars.any(|char| ('@'..='~').contains(&char)),
+        Some(']') => !text.contains('\x07') && !text[1..].contains("\x1b\\"),
+        Some(_) => false,
+    }
+}
+
+/// The last line of what was typed, with backspaces applied, as the shell redraws the line while it is edited.
+/// The newline echoed for Enter comes before the output starts, so a blank last line doesn't count.
+fn last_line(typed: &str) -> String {
+    typed
+        .rsplit('\n')
+        .map(|typed| {
+            let mut line = String::new();
+            for char in typed.chars() {
+                match char {
+                    '\x08' | '\x7f' => {
+                        line.pop();
+                    }
+                    char if char.is_control() => {}
+                    char => line.push(char),
+                }
+            }
+            line.trim()
+                .chars()
+                .take(MAX_COMMAND_LENGTH)
+                .collect::<String>()
+        })
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The marks a shell with shell integration sends around a command
+    const PROMPT: &str = "\x1b]133;A\x07$ \x1b]133;B\x07";
+    const OUTPUT: &str = "\r\n\x1b]133;C\x07";
+
+    fn summaries(watched: &[Watched]) -> Vec<String> {
+        watched
+            .iter()
+            .map(|watched| match watched {
+                Watched::Failed(failure) => failure.summary.clone(),
+                Watched::Succeeded => "succeeded".to_string(),
+            })
+            .collect()
+    }
+
+    fn run(watcher: &mut ErrorWatcher, command: &str, output: &str, code: i32) -> Vec<String> {
+        let mut watched = watcher.watch(PROMPT, false);
+        watched.extend(watcher.watch(command, false));
+        watched.extend(watcher.watch(OUTPUT, false));
+        watched.extend(watcher.watch(output, false));
+        watched.extend(watcher.watch(&format!("\x1b]133;D;{}\x07", code), false));
+        summaries(&watched)
+    }
+
+    #[test]
+    fn reports_commands_by_their_exit_code() {
+        let mut watcher = ErrorWatcher::new();
+        assert_eq!(run(&mut watcher, "ls", "file\r\n", 0), ["succeeded"]);
+        assert_eq!(
+            run(&mut watcher, "make test", "make: *** Error 2\r\n", 2),
+            ["`make test` exited with 2"]
+        );
+        // With shell integration, an error in the output of a command that succeeded doesn't count
+        assert_eq!(
+            run(&mut watcher, "grep -r x", "a: Permission denied\r\n", 0),
+            ["succeeded"]
+        );
+    }
+
+    #[test]
+    fn names_the_first_error_in_the_output() {
+        let mut watcher = ErrorWatcher::new();
+        let output = "bash: foo: command not found\r\nfatal: again\r\n";
+        assert_eq!(
+            run(&mut watcher, "foo", output, 127),
+            ["`foo` exited with 127 (command not found)"]
+        );
+
+        // The shell echoes a backspace, a space and another backspace to erase a typo
+        watcher.watch(PROMPT, false);
+        watcher.watch("caz\x08 \x08t x", false);
+        watcher.watch(OUTPUT, false);
+        watcher.watch("cat: x: No such file or directory\r\n", false);
+        let Some(Watched::Failed(failure)) = watcher.watch("\x1b]133;D;1\x07", false).pop() else {
+            panic!("the failure wasn't reported");
+        };
+        assert_eq!(
+            failure.summary,
+            "`cat x` exited with 1 (no such file or directory)"
+        );
+        assert_eq!(
+            failure.output.as_deref(),
+            Some("cat: x: No such file or directory\n")
+        );
+    }
+
+    #[test]
+    fn ignores_empty_prompts_and_what_came_before_the_first_mark() {
+        let mut watcher = ErrorWatcher::new();
+        // A failed command in the rc file, and the exit code of it the first prompt reports
+        assert!(watcher.watch("rc: Permission denied\r\n", false).is_empty());
+        assert!(watcher.watch("\x1b]133;D;1\x07", false).is_empty());
+        // Enter on an empty prompt
+        assert!(watcher
+            .watch(&format!("{}\x1b]133;D;1\x07", PROMPT), false)
+            .is_empty());
+    }
+
+    #[test]
+    fn handles_marks_split_between_reads() {
+        let mut watcher = ErrorWatcher::new();
+        let mut watched = vec![];
+        let text = format!("{}false{}\x1b]133;D;1\x1b\\", PROMPT, OUTPUT);
+        for char in text.chars() {
+            watched.extend(watcher.watch(&char.to_string(), false));
+        }
+        assert_eq!(summaries(&watched), ["`false` exited with 1"]);
+    }
+
+    #[test]
+    fn spots_errors_in_the_output_without_shell_integration() {
+        let mut watcher = ErrorWatcher::new();
+        // What the shell prints while starting up isn't the output of a command
+        assert!(watcher
+            .watch("motd: No such file or directory\r\n", false)
+            .is_empty());
+        watcher.typed();
+        // Nor is what a full screen program shows
+        assert!(watcher
+            .watch("Segmentation fault in the help text\r\n", true)
+            .is_empty());
+        // The line is only searched once it is complete
+        assert!(watcher.watch("bash: foo: command not", false).is_empty());
+        assert_eq!(
+            summaries(&watcher.watch(" found\r\n", false)),
+            ["Command not found"]
+        );
+        // More errors right after are left out
+        assert!(watcher.watch("Permission denied\r\n", false).is_empty());
+    }
+}
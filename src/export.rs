@@ -0,0 +1,615 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    app_state::{Message, MessageSender},
+    attachment::{self, Attachment},
+    markdown,
+    services::chat_service::Role,
+    usage::{self, Total, Usage},
+};
+
+/// Said of answers that stopped at the token limit.
+const TRUNCATED: &str = "Cut off at the token limit";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Markdown,
+    Json,
+    Html,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "md" | "markdown" => Some(Format::Markdown),
+            "json" => Some(Format::Json),
+            "html" | "htm" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Json => "json",
+            Format::Html => "html",
+        }
+    }
+}
+
+/// How to export the conversation, as given to the `/export` command.
+pub struct ExportOptions {
+    pub format: Format,
+    pub include_terminal_output: bool,
+    // Where to write the export. Defaults to a file named after the current time, in the working directory.
+    pub path: Option<PathBuf>,
+}
+
+impl ExportOptions {
+    /// Parses `[md|json|html] [--no-terminal] [path]`, in any order.
+    /// Without a format, it is taken from the extension of the path, falling back to Markdown.
+    pub fn parse(arguments: &str) -> Result<Self, String> {
+        let mut format = None;
+        let mut include_terminal_output = true;
+        let mut path: Option<PathBuf> = None;
+        for argument in arguments.split_whitespace() {
+            if argument == "--no-terminal" {
+                include_terminal_output = false;
+            } else if let Some(name) = Format::from_name(argument).filter(|_| format.is_none()) {
+                format = Some(name);
+            } else if argument.starts_with("--") || path.is_some() {
+                return Err(format!(
+                    "Unknown export option {}, use /export [md|json|html] [--no-terminal] [path]",
+                    argument
+                ));
+            } else {
+                path = Some(argument.into());
+            }
+        }
+        let format = format
+            .or_else(|| {
+                path.as_ref()
+                    .and_then(|path| path.extension())
+                    .and_then(|extension| Format::from_name(&extension.to_string_lossy()))
+            })
+            .unwrap_or(Format::Markdown);
+        Ok(Self {
+            format,
+            include_terminal_output,
+            path,
+        })
+    }
+}
+
+/// Writes the conversation to a file, returning where it ended up.
+pub fn export(
+    options: &ExportOptions,
+    role: Role,
+    model: &str,
+    messages: &[&Message],
+    total: &Total,
+) -> io::Result<PathBuf> {
+    let path = match &options.path {
+        Some(path) => path.clone(),
+        None => default_path(options.format),
+    };
+    let include_terminal_output = options.include_terminal_output;
+    let contents = match options.format {
+        Format::Markdown => to_markdown(role, model, messages, total, include_terminal_output),
+        Format::Json => to_json(role, model, messages, total, include_terminal_output)?,
+        Format::Html => to_html(role, model, messages, total, include_terminal_output),
+    };
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, contents)?;
+    Ok(path)
+}
+
+fn default_path(format: Format) -> PathBuf {
+    let now = OffsetDateTime::now_utc();
+    PathBuf::from(format!(
+        "conversation-{}-{:02}{:02}{:02}.{}",
+        now.date(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+        format.extension()
+    ))
+}
+
+fn to_markdown(
+    role: Role,
+    model: &str,
+    messages: &[&Message],
+    total: &Total,
+    include_terminal_output: bool,
+) -> String {
+    let mut markdown = format!(
+        "# Conversation\n\n- Role: {}\n- Model: {}\n- Exported: {}\n",
+        role.title(),
+        model,
+        format_timestamp(Some(OffsetDateTime::now_utc())),
+    );
+    if !total.is_empty() {
+        markdown.push_str(&format!("- Usage: {}\n", describe_total(total)));
+    }
+    for message in messages {
+        markdown.push_str(&format!(
+            "\n## {} ({})\n\n",
+            sender_name(message.sender),
+            format_timestamp(message.timestamp)
+        ));
+        if let Some(usage) = &message.usage {
+            markdown.push_str(&format!("_{}_\n\n", describe_usage(usage)));
+        }
+        if let Some(terminal_output) = terminal_output(message, include_terminal_output) {
+            let fence = fence_for(&terminal_output);
+            markdown.push_str(&format!(
+                "Terminal output:\n\n{}text\n{}\n{}\n\n",
+                fence,
+                terminal_output.trim_end(),
+                fence
+            ));
+        }
+        for attachment in &message.attachments {
+            let fence = fence_for(&attachment.content);
+            markdown.push_str(&format!(
+                "Attached {}:\n\n{}\n{}\n{}\n\n",
+                describe_attachment(attachment),
+                fence,
+                attachment.content.trim_end(),
+                fence
+            ));
+        }
+        markdown.push_str(message.message.trim_end());
+        markdown.push('\n');
+        if message.truncated {
+            markdown.push_str(&format!("\n_{}_\n", TRUNCATED));
+        }
+        if !message.sources.is_empty() {
+            markdown.push_str(&format!("\n_Sources: {}_\n", message.sources.join(", ")));
+        }
+    }
+    markdown
+}
+
+#[derive(Serialize)]
+struct JsonConversation<'a> {
+    role: &'static str,
+    model: &'a str,
+    #[serde(with = "time::serde::rfc3339")]
+    exported_at: OffsetDateTime,
+    // The usage of the whole session, which includes answers that aren't exported, like regenerated ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<&'a Total>,
+    messages: Vec<JsonMessage<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonMessage<'a> {
+    sender: &'static str,
+    #[serde(
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    timestamp: Option<OffsetDateTime>,
+    content: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    terminal_output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<&'a Usage>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    sources: &'a [String],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    attachments: &'a [Attachment],
+}
+
+fn to_json(
+    role: Role,
+    model: &str,
+    messages: &[&Message],
+    total: &Total,
+    include_terminal_output: bool,
+) -> io::Result<String> {
+    let conversation = JsonConversation {
+        role: role.title(),
+        model,
+        exported_at: OffsetDateTime::now_utc(),
+        usage: Some(total).filter(|total| !total.is_empty()),
+        messages: messages
+            .iter()
+            .map(|message| JsonMessage {
+                sender: match message.sender {
+                    MessageSender::User => "user",
+                    MessageSender::Assistant => "assistant",
+                },
+                timestamp: message.timestamp,
+                content: &message.message,
+                terminal_output: terminal_output(message, include_terminal_output),
+                usage: message.usage.as_ref(),
+                truncated: message.truncated,
+                sources: &message.sources,
+                attachments: &message.attachments,
+            })
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&conversation)?)
+}
+
+const HTML_STYLE: &str =
+    "body { font-family: sans-serif; max-width: 60em; margin: 2em auto; padding: 0 1em; }
+.message { border-left: 4px solid #8bc34a; padding: 0 1em; margin: 1.5em 0; }
+.message.user { border-color: #ffc107; }
+.sender { font-weight: bold; }
+.timestamp, .usage { color: #777; font-size: 0.9em; }
+pre { background: #f4f4f4; padding: 0.75em; overflow-x: auto; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ccc; padding: 0.25em 0.5em; }";
+
+fn to_html(
+    role: Role,
+    model: &str,
+    messages: &[&Message],
+    total: &Total,
+    include_terminal_output: bool,
+) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Conversation</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>Conversation</h1>\n<ul>\n<li>Role: {}</li>\n<li>Model: {}</li>\n<li>Exported: {}</li>\n{}</ul>\n",
+        HTML_STYLE,
+        escape_html(role.title()),
+        escape_html(model),
+        format_timestamp(Some(OffsetDateTime::now_utc())),
+        match total.is_empty() {
+            true => String::new(),
+            false => format!("<li>Usage: {}</li>\n", escape_html(&describe_total(total))),
+        },
+    );
+    for message in messages {
+        let class = match message.sender {
+            MessageSender::User => "user",
+            MessageSender::Assistant => "assistant",
+        };
+        html.push_str(&format!(
+            "<div class=\"message {}\">\n<p><span class=\"sender\">{}</span> <span class=\"timestamp\">{}</span></p>\n",
+            class,
+            sender_name(message.sender),
+            format_timestamp(message.timestamp),
+        ));
+        if let Some(usage) = &message.usage {
+            html.push_str(&format!(
+                "<p class=\"usage\">{}</p>\n",
+                escape_html(&describe_usage(usage))
+            ));
+        }
+        if let Some(terminal_output) = terminal_output(message, include_terminal_output) {
+            html.push_str(&format!(
+                "<details>\n<summary>Terminal output</summary>\n<pre>{}</pre>\n</details>\n",
+                escape_html(terminal_output.trim_end())
+            ));
+        }
+        for attachment in &message.attachments {
+            html.push_str(&format!(
+                "<details>\n<summary>Attached {}</summary>\n<pre>{}</pre>\n</details>\n",
+                escape_html(&describe_attachment(attachment)),
+                escape_html(attachment.content.trim_end())
+            ));
+        }
+        match message.sender {
+            MessageSender::User => html.push_str(&format!(
+                "<p style=\"white-space: pre-wrap\">{}</p>\n",
+                escape_html(&message.message)
+            )),
+            MessageSender::Assistant => html.push_str(&markdown::to_html(&message.message)),
+        }
+        if message.truncated {
+            html.push_str(&format!("<p class=\"usage\">{}</p>\n", TRUNCATED));
+        }
+        if !message.sources.is_empty() {
+            html.push_str(&format!(
+                "<p class=\"usage\">Sources: {}</p>\n",
+                escape_html(&message.sources.join(", "))
+            ));
+        }
+        html.push_str("</div>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Like `configs/core-sw1.cfg (2.1 KB)`, saying so if only the start of the file was sent.
+fn describe_attachment(attachment: &Attachment) -> String {
+    let size = attachment::format_size(attachment.size);
+    match attachment.truncated {
+        true => format!("{} ({}, only the start was sent)", attachment.path, size),
+        false => format!("{} ({})", attachment.path, size),
+    }
+}
+
+fn sender_name(sender: MessageSender) -> &'static str {
+    match sender {
+        MessageSender::User => "You",
+        MessageSender::Assistant => "Assistant",
+    }
+}
+
+/// The exact counts rather than the short ones in the chat, like `1234 prompt + 321 completion tokens of gpt-4o,
+/// $0.0063`.
+fn describe_usage(usage: &Usage) -> String {
+    describe_tokens(
+        usage.prompt_tokens,
+        usage.completion_tokens,
+        &format!(" of {}", usage.model),
+        usage.estimated,
+        usage.cost,
+    )
+}
+
+fn describe_total(total: &Total) -> String {
+    describe_tokens(
+        total.prompt_tokens,
+        total.completion_tokens,
+        "",
+        total.estimated,
+        total.cost,
+    )
+}
+
+fn describe_tokens(
+    prompt_tokens: u32,
+    completion_tokens: u32,
+    model: &str,
+    estimated: bool,
+    cost: Option<f64>,
+) -> String {
+    let approximately = if estimated { "~" } else { "" };
+    let mut description = format!(
+        "{}{} prompt + {} completion tokens{}",
+        approximately, prompt_tokens, completion_tokens, model
+    );
+    if let Some(cost) = cost {
+        description.push_str(&format!(", {}{}", approximately, usage::format_cost(cost)));
+    }
+    description
+}
+
+fn format_timestamp(timestamp: Option<OffsetDateTime>) -> String {
+    match timestamp {
+        Some(timestamp) => format!(
+            "{} {:02}:{:02}:{:02} UTC",
+            timestamp.date(),
+            timestamp.hour(),
+            timestamp.minute(),
+            timestamp.second()
+        ),
+        None => "unknown time".to_string(),
+    }
+}
+
+/// The terminal output sent with the message, without the escape sequences that only make sense to a terminal.
+fn terminal_output(message: &Message, include_terminal_output: bool) -> Option<String> {
+    let terminal_output = message
+        .terminal_context
+        .as_deref()
+        .filter(|_| include_terminal_output)?;
+    Some(strip_escape_sequences(terminal_output))
+}
+
+/// Removes ANSI escape sequences (colors, cursor movement, window titles) and carriage returns.
+pub fn strip_escape_sequences(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '\x1b' => match chars.next() {
+                // Control sequences end with a byte in the range @ to ~
+                Some('[') => {
+                    for char in chars.by_ref() {
+                        if ('@'..='~').contains(&char) {
+                            break;
+                        }
+                    }
+                }
+                // Operating system commands end with BEL or ESC \
+                Some(']') => {
+                    while let Some(char) = chars.next() {
+                        if char == '\x07' {
+                            break;
+                        }
+                        if char == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\r' => {}
+            char if char.is_control() && char != '\n' && char != '\t' => {}
+            char => stripped.push(char),
+        }
+    }
+    stripped
+}
+
+/// A code fence longer than any run of backticks in the text, so the text can't close it.
+pub fn fence_for(text: &str) -> String {
+    let mut longest = 0;
+    let mut current = 0;
+    for char in text.chars() {
+        if char == '`' {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<Message> {
+        let mut question = Message::user(
+            "Why does this fail?".to_string(),
+            Some("\x1b[31m$ make\x1b[0m\r\nmake: *** Error 2\r\n".to_string()),
+        );
+        question.timestamp = None;
+        question.attachments.push(Attachment {
+            path: "Makefile".to_string(),
+            content: "all:\n\tfalse\n".to_string(),
+            size: 12,
+            truncated: false,
+        });
+        let mut answer = Message::assistant(
+            "The `false` target fails, see [the docs](https://a.b) or [this](javascript:alert(1))"
+                .to_string(),
+        );
+        answer.timestamp = None;
+        answer.usage = Some(Usage {
+            model: "gpt-4o".to_string(),
+            prompt_tokens: 1234,
+            completion_tokens: 321,
+            estimated: false,
+            cost: Some(0.0063),
+        });
+        answer.truncated = true;
+        answer.sources.push("docs/make.md:1-20".to_string());
+        vec![question, answer]
+    }
+
+    fn export_to(format: Format, include_terminal_output: bool) -> String {
+        let messages = messages();
+        let messages: Vec<&Message> = messages.iter().collect();
+        let total = Total::default();
+        match format {
+            Format::Markdown => to_markdown(
+                Role::General,
+                "gpt-4o",
+                &messages,
+                &total,
+                include_terminal_output,
+            ),
+            Format::Json => to_json(
+                Role::General,
+                "gpt-4o",
+                &messages,
+                &total,
+                include_terminal_output,
+            )
+            .unwrap(),
+            Format::Html => to_html(
+                Role::General,
+                "gpt-4o",
+                &messages,
+                &total,
+                include_terminal_output,
+            ),
+        }
+    }
+
+    #[test]
+    fn exports_markdown() {
+        let markdown = export_to(Format::Markdown, true);
+        // What comes after the time of the export
+        let (_, messages) = markdown.split_once("\n\n## ").unwrap();
+        assert_eq!(
+            messages,
+            "You (unknown time)\n\n\
+             Terminal output:\n\n```text\n$ make\nmake: *** Error 2\n```\n\n\
+             Attached Makefile (12 B):\n\n```\nall:\n\tfalse\n```\n\n\
+             Why does this fail?\n\n\
+             ## Assistant (unknown time)\n\n\
+             _1234 prompt + 321 completion tokens of gpt-4o, $0.0063_\n\n\
+             The `false` target fails, see [the docs](https://a.b) or [this](javascript:alert(1))\n\n\
+             _Cut off at the token limit_\n\n\
+             _Sources: docs/make.md:1-20_\n"
+        );
+        assert!(!export_to(Format::Markdown, false).contains("Terminal output"));
+    }
+
+    #[test]
+    fn exports_json() {
+        let json: serde_json::Value = serde_json::from_str(&export_to(Format::Json, true)).unwrap();
+        assert_eq!(json["role"], "General");
+        assert_eq!(json["model"], "gpt-4o");
+        // Without any usage in the session, there is no total
+        assert!(json.get("usage").is_none());
+        let messages = json["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["sender"], "user");
+        assert_eq!(
+            messages[0]["terminal_output"],
+            "$ make\nmake: *** Error 2\n"
+        );
+        assert_eq!(messages[0]["attachments"][0]["path"], "Makefile");
+        assert_eq!(messages[1]["sender"], "assistant");
+        assert_eq!(messages[1]["usage"]["completion_tokens"], 321);
+        assert_eq!(messages[1]["truncated"], true);
+        assert_eq!(messages[1]["sources"][0], "docs/make.md:1-20");
+
+        let json: serde_json::Value =
+            serde_json::from_str(&export_to(Format::Json, false)).unwrap();
+        assert!(json["messages"][0].get("terminal_output").is_none());
+    }
+
+    #[test]
+    fn exports_html_without_unsafe_links() {
+        let html = export_to(Format::Html, true);
+        assert!(html.contains("<li>Model: gpt-4o</li>"));
+        assert!(html
+            .contains("<summary>Terminal output</summary>\n<pre>$ make\nmake: *** Error 2</pre>"));
+        assert!(html.contains("<p style=\"white-space: pre-wrap\">Why does this fail?</p>"));
+        assert!(html.contains("<a href=\"https://a.b\">the docs</a>"));
+        assert!(html.contains("<a href=\"\">this</a>"));
+        assert!(!html.contains("javascript"));
+        assert!(html.contains("<p class=\"usage\">Sources: docs/make.md:1-20</p>"));
+    }
+
+    #[test]
+    fn parses_export_options() {
+        let options = ExportOptions::parse("--no-terminal out/chat.html").unwrap();
+        assert_eq!(options.format, Format::Html);
+        assert!(!options.include_terminal_output);
+        assert_eq!(options.path, Some(PathBuf::from("out/chat.html")));
+        // The format given wins over the extension
+        assert_eq!(
+            ExportOptions::parse("json a.md").unwrap().format,
+            Format::Json
+        );
+        assert_eq!(ExportOptions::parse("").unwrap().format, Format::Markdown);
+        assert!(ExportOptions::parse("--all").is_err());
+        assert!(ExportOptions::parse("a.md b.md").is_err());
+    }
+
+    #[test]
+    fn fences_text_with_backticks() {
+        assert_eq!(fence_for("no backticks"), "```");
+        assert_eq!(fence_for("````rust"), "`````");
+    }
+}
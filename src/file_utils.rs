@@ -0,0 +1,15 @@
+use std::{fs, io, path::Path};
+
+/// Writes `contents` to `path`, creating its folder if there is none yet.
+///
+/// The contents go to a file next to it first, which then replaces the old one, so a crash while writing leaves the
+/// old file as it was rather than half of the new one.
+pub fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(temporary, path)
+}
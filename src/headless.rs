@@ -0,0 +1,231 @@
+use std::{
+    io::{self, IsTerminal, Read, Write},
+    process::ExitCode,
+    sync::Arc,
+};
+
+use async_openai::types::FinishReason;
+use clap::Subcommand;
+use portable_pty::CommandBuilder;
+use tokio::sync::{mpsc, Mutex, Notify};
+
+use crate::{
+    app_state::Message,
+    services::{
+        chat_service::{Action, ChatService, Parameters, Role},
+        event_service::Event,
+    },
+    terminal_utils::{self, OutputOptions},
+};
+
+/// What to ask about a command's output, when the user doesn't say.
+const DEFAULT_RUN_QUESTION: &str =
+    "Explain the output of this command. If it failed, why did it, and how do I fix it?";
+/// The size of the pseudo terminal commands run in, when stdout isn't a terminal we can take the size of.
+const DEFAULT_PTY_SIZE: (u16, u16) = (120, 40);
+
+/// What to do without starting the TUI.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Ask a question, with whatever is piped to stdin as the terminal output
+    Ask {
+        #[arg(required = true)]
+        question: Vec<String>,
+    },
+    /// Run a command, then ask about its output
+    Run {
+        /// What to ask about the output, instead of what went wrong
+        question: Vec<String>,
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+impl Command {
+    pub async fn execute(
+        self,
+        chat_service: ChatService,
+        role: Role,
+        parameters: Parameters,
+    ) -> ExitCode {
+        match self {
+            Command::Ask { question } => {
+                ask(chat_service, role, parameters, question.join(" ")).await
+            }
+            Command::Run { question, command } => {
+                let question = Some(question.join(" ")).filter(|question| !question.is_empty());
+                run(chat_service, role, parameters, question, command).await
+            }
+        }
+    }
+}
+
+/// Asks the AI a question, and streams the answer to stdout.
+/// Anything piped to stdin is sent along as terminal output, so `ask "why did this fail?" < build.log` works.
+async fn ask(
+    chat_service: ChatService,
+    role: Role,
+    parameters: Parameters,
+    question: String,
+) -> ExitCode {
+    let mut input = vec![];
+    if !io::stdin().is_terminal() {
+        if let Err(err) = io::stdin().read_to_end(&mut input) {
+            eprintln!("Failed to read stdin: {}", err);
+            return ExitCode::FAILURE;
+        }
+    }
+    // The same limit as for the terminal in the TUI, keeping the end, which is usually where the error is
+    let mut terminal_context = String::new();
+    terminal_utils::append_terminal_context(
+        &mut terminal_context,
+        &String::from_utf8_lossy(&input),
+    );
+    let terminal_context = Some(terminal_context).filter(|context| !context.trim().is_empty());
+
+    match answer(
+        chat_service,
+        role,
+        parameters,
+        Message::user(question, terminal_context),
+    )
+    .await
+    {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
+
+/// Runs a command in a pseudo terminal, showing its output as it goes, then asks the AI about it.
+/// Exits with the exit code of the command, so it can stand in for the command in scripts.
+async fn run(
+    chat_service: ChatService,
+    role: Role,
+    parameters: Parameters,
+    question: Option<String>,
+    command: Vec<String>,
+) -> ExitCode {
+    let Some((program, args)) = command.split_first() else {
+        eprintln!("No command to run");
+        return ExitCode::FAILURE;
+    };
+    let mut cmd = CommandBuilder::new(program);
+    cmd.args(args);
+
+    let size = crossterm::terminal::size().unwrap_or(DEFAULT_PTY_SIZE);
+    let terminal_context = Arc::new(Mutex::new(String::new()));
+    let (event_sender, mut event_receiver) = mpsc::unbounded_channel();
+    let (echo_sender, mut echo_receiver) = mpsc::unbounded_channel();
+    let terminal_sender = match terminal_utils::spawn(
+        cmd,
+        size,
+        terminal_context.clone(),
+        event_sender,
+        Arc::new(Notify::new()),
+        OutputOptions {
+            echo_sender: Some(echo_sender),
+            ..OutputOptions::default()
+        },
+    ) {
+        Ok((_, terminal_sender, _)) => terminal_sender,
+        Err(err) => {
+            eprintln!("Failed to run {}: {}", program, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // The echo ends once all output has been read, which is after the command has exited
+    let mut stdout = io::stdout();
+    while let Some(output) = echo_receiver.recv().await {
+        let _ = stdout.write_all(&output).and_then(|_| stdout.flush());
+    }
+    // Nothing is typed into the command, and dropping the sender lets the pseudo terminal close
+    drop(terminal_sender);
+
+    let mut exit_code = ExitCode::FAILURE;
+    let mut exit_status = String::from("Unknown exit status");
+    while let Some(event) = event_receiver.recv().await {
+        match event {
+            Event::ChildExited(status) => {
+                exit_code = ExitCode::from(status.exit_code().min(u8::MAX.into()) as u8);
+                exit_status = status.to_string();
+                break;
+            }
+            Event::TerminalError(err) => tracing::warn!("Terminal error: {}", err),
+            _ => {}
+        }
+    }
+    println!();
+
+    let question = format!(
+        "I ran `{}`. {}\n\n{}",
+        command.join(" "),
+        exit_status,
+        question.as_deref().unwrap_or(DEFAULT_RUN_QUESTION)
+    );
+    let terminal_context = terminal_context.lock().await.clone();
+    let message = Message::user(question, Some(terminal_context));
+    match answer(chat_service, role, parameters, message).await {
+        true => exit_code,
+        false => ExitCode::FAILURE,
+    }
+}
+
+/// Sends the message to the AI, and streams the answer to stdout. Returns whether there was an answer.
+async fn answer(
+    mut chat_service: ChatService,
+    role: Role,
+    parameters: Parameters,
+    message: Message,
+) -> bool {
+    let (action_sender, mut action_receiver) = mpsc::unbounded_channel();
+    let (event_sender, mut event_receiver) = mpsc::unbounded_channel();
+    // There is no index running in the background here, so bring it up to date first
+    if chat_service.has_knowledge() {
+        action_sender.send(Action::IndexKnowledge).unwrap();
+    }
+    action_sender
+        .send(Action::AiRequest {
+            role,
+            messages: vec![message],
+            parameters,
+        })
+        .unwrap();
+    // Once the only request has been answered, the service stops and the events end
+    drop(action_sender);
+    tokio::spawn(async move { chat_service.start(event_sender, &mut action_receiver).await });
+
+    let mut stdout = io::stdout();
+    let mut answered = false;
+    let mut truncated = false;
+    let mut sources = vec![];
+    let mut error = None;
+    while let Some(event) = event_receiver.recv().await {
+        match event {
+            Event::AIStreamResponse(content) => {
+                let _ = write!(stdout, "{}", content).and_then(|_| stdout.flush());
+                answered = true;
+            }
+            Event::AIFinished(finish_reason) => truncated = finish_reason == FinishReason::Length,
+            Event::AISources(answer_sources) => sources = answer_sources,
+            Event::AIError(answer_error) => error = Some(answer_error),
+            Event::KnowledgeIndexed(Err(err)) => eprintln!("Failed to index the docs: {}", err),
+            _ => {}
+        }
+    }
+    if answered {
+        println!();
+        if !sources.is_empty() {
+            println!("\nSources: {}", sources.join(", "));
+        }
+        if truncated {
+            eprintln!("The answer was cut off at the token limit, see --max-tokens");
+        }
+    }
+    match error {
+        Some(error) => eprintln!("The AI request failed: {}", error),
+        None if !answered => eprintln!("No answer from the AI, see the log for details"),
+        None => {}
+    }
+    answered
+}
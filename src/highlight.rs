@@ -0,0 +1,245 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::Span,
+};
+use syntect::{
+    easy::ScopeRegionIterator,
+    highlighting::ScopeSelectors,
+    parsing::{ParseState, ScopeStack, SyntaxDefinition, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+use crate::theme::Theme;
+
+/// Syntaxes syntect doesn't ship with, but which are common in the answers we get
+const EXTRA_SYNTAXES: &[&str] = &[include_str!("syntaxes/cisco-ios.sublime-syntax")];
+
+/// Highlighting the same code block for every frame is wasteful, so we remember the last ones we did
+const CACHE_SIZE: usize = 256;
+
+static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+static SCOPE_STYLES: OnceLock<Vec<(ScopeSelectors, ThemeColor, Modifier)>> = OnceLock::new();
+static CACHE: OnceLock<Mutex<HashMap<u64, Vec<Vec<Span<'static>>>>>> = OnceLock::new();
+
+/// Loads the syntaxes up front, so the first code block in the chat doesn't have to wait for it.
+pub fn warm_up() {
+    syntax_set();
+}
+
+/// Highlights a code block in the colors of `theme`, returning one list of spans per line.
+/// Returns `None` if we don't know the language, in which case the caller renders the code plainly.
+pub fn highlight(code: &str, language: &str, theme: &Theme) -> Option<Vec<Vec<Span<'static>>>> {
+    let syntax_set = syntax_set();
+    let syntax = find_syntax(syntax_set, language)?;
+
+    let mut hasher = DefaultHasher::new();
+    (language, code, theme).hash(&mut hasher);
+    let key = hasher.finish();
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(lines) = cache.lock().unwrap().get(&key) {
+        return Some(lines.clone());
+    }
+
+    let mut parse_state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut lines = vec![];
+    for line in LinesWithEndings::from(code) {
+        let operations = parse_state.parse_line(line, syntax_set).ok()?;
+        let mut spans = vec![];
+        // Each piece of text comes with the change to the scopes that applies to it
+        for (text, operation) in ScopeRegionIterator::new(&operations, line) {
+            stack.apply(operation).ok()?;
+            let text = text.trim_end_matches(['\n', '\r']);
+            if !text.is_empty() {
+                spans.push(Span::styled(text.to_string(), style(&stack, theme)));
+            }
+        }
+        lines.push(spans);
+    }
+
+    let mut cache = cache.lock().unwrap();
+    if cache.len() >= CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(key, lines.clone());
+    Some(lines)
+}
+
+fn syntax_set() -> &'static SyntaxSet {
+    SYNTAX_SET.get_or_init(|| {
+        let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+        for syntax in EXTRA_SYNTAXES {
+            match SyntaxDefinition::load_from_str(syntax, true, None) {
+                Ok(syntax) => builder.add(syntax),
+                Err(err) => tracing::warn!("Failed to load syntax: {:?}", err),
+            }
+        }
+        builder.build()
+    })
+}
+
+fn find_syntax<'a>(syntax_set: &'a SyntaxSet, language: &str) -> Option<&'a SyntaxReference> {
+    let language = language.to_lowercase();
+    // Names the AI likes to use for shell snippets, which syntect doesn't know
+    let language = match language.as_str() {
+        "console" | "shell" | "shell-session" | "terminal" | "sh-session" => "bash",
+        "yml" => "yaml",
+        "py" | "python3" => "python",
+        "ios" | "cisco-ios" | "iosxe" | "ios-xe" | "iosxr" | "ios-xr" | "nxos" => "cisco",
+        language => language,
+    };
+    if language.is_empty() {
+        return None;
+    }
+    syntax_set.find_syntax_by_token(language)
+}
+
+/// Picks one of the colors of a theme
+type ThemeColor = fn(&Theme) -> Color;
+
+/// The scopes that get a color of their own, with the color they get from the theme and how they are set apart
+/// besides. Where several match, the one matching the most specific scope wins, as in a syntect theme.
+fn scope_styles() -> &'static [(ScopeSelectors, ThemeColor, Modifier)] {
+    SCOPE_STYLES.get_or_init(|| {
+        let scopes: [(&str, ThemeColor, Modifier); 13] = [
+            ("comment", |theme| theme.syntax_comment, Modifier::ITALIC),
+            ("string", |theme| theme.syntax_string, Modifier::empty()),
+            (
+                "constant.numeric, constant.language, constant.character",
+                |theme| theme.syntax_constant,
+                Modifier::empty(),
+            ),
+            (
+                "keyword, storage.type",
+                |theme| theme.syntax_keyword,
+                Modifier::BOLD,
+            ),
+            (
+                "storage.modifier, keyword.operator",
+                |theme| theme.syntax_operator,
+                Modifier::empty(),
+            ),
+            (
+                "entity.name.function, support.function, variable.function",
+                |theme| theme.syntax_function,
+                Modifier::empty(),
+            ),
+            (
+                "entity.name.type, entity.name.class, support.type, support.class",
+                |theme| theme.syntax_type,
+                Modifier::empty(),
+            ),
+            (
+                "entity.name.tag, meta.mapping.key, string.unquoted.plain.out.key",
+                |theme| theme.syntax_tag,
+                Modifier::empty(),
+            ),
+            (
+                "support.type.property-name, entity.other.attribute-name",
+                |theme| theme.syntax_tag,
+                Modifier::empty(),
+            ),
+            (
+                "variable.other, variable.parameter, punctuation.definition.variable",
+                |theme| theme.syntax_variable,
+                Modifier::empty(),
+            ),
+            ("markup.heading", |theme| theme.heading, Modifier::BOLD),
+            (
+                "markup.inserted",
+                |theme| theme.syntax_inserted,
+                Modifier::empty(),
+            ),
+            (
+                "markup.deleted",
+                |theme| theme.syntax_deleted,
+                Modifier::empty(),
+            ),
+        ];
+        scopes
+            .into_iter()
+            .filter_map(|(scope, color, modifier)| {
+                Some((ScopeSelectors::from_str(scope).ok()?, color, modifier))
+            })
+            .collect()
+    })
+}
+
+/// The style of text in the scopes on `stack`. Text in none of the scopes in `scope_styles` is left to the
+/// terminal's default color.
+fn style(stack: &ScopeStack, theme: &Theme) -> Style {
+    scope_styles()
+        .iter()
+        .filter_map(|(selectors, color, modifier)| {
+            let power = selectors.does_match(stack.as_slice())?;
+            Some((power, color, modifier))
+        })
+        .max_by(|(power, ..), (other, ..)| power.partial_cmp(other).unwrap_or(Ordering::Equal))
+        .map(|(_, color, modifier)| Style::default().fg(color(theme)).add_modifier(*modifier))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styles(line: &[Span]) -> Vec<(String, Style)> {
+        line.iter()
+            .map(|span| (span.content.to_string(), span.style))
+            .filter(|(text, _)| !text.trim().is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn colors_code_from_the_theme() {
+        let code = "fn main() {\n    // hi\n    let x = \"s\";\n}\n";
+        let theme = Theme::dark();
+        let lines = highlight(code, "rust", &theme).unwrap();
+        assert_eq!(lines.len(), 4);
+        let keyword = Style::default()
+            .fg(theme.syntax_keyword)
+            .add_modifier(Modifier::BOLD);
+        assert_eq!(
+            styles(&lines[0]),
+            [
+                ("fn".to_string(), keyword),
+                (
+                    "main".to_string(),
+                    Style::default().fg(theme.syntax_function)
+                ),
+                ("(".to_string(), Style::default()),
+                (")".to_string(), Style::default()),
+                ("{".to_string(), Style::default()),
+            ]
+        );
+        let comment = Style::default()
+            .fg(theme.syntax_comment)
+            .add_modifier(Modifier::ITALIC);
+        assert!(styles(&lines[1]).iter().all(|(_, style)| *style == comment));
+        assert!(styles(&lines[2])
+            .contains(&("s".to_string(), Style::default().fg(theme.syntax_string))));
+
+        // Another theme gives other colors, rather than what was cached for the first
+        let light = Theme::light();
+        let lines = highlight(code, "rust", &light).unwrap();
+        assert_eq!(
+            lines[0][2].style,
+            Style::default().fg(light.syntax_function)
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_languages_alone() {
+        assert!(highlight("x", "", &Theme::dark()).is_none());
+        assert!(highlight("x", "no-such-language", &Theme::dark()).is_none());
+        assert!(highlight("show ip route", "ios", &Theme::dark()).is_some());
+    }
+}
@@ -0,0 +1,688 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+use crate::app_state::Mode;
+
+/// Something the app does when a key is pressed, instead of passing the key on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Command {
+    Quit,
+    ChangeMode,
+    ScrollUp,
+    ScrollDown,
+    Help,
+    DebugPane,
+    NextChat,
+    PreviousChat,
+    ChatList,
+    ExplainError,
+    PreviousCodeBlock,
+    NextCodeBlock,
+    InsertCode,
+    RunCode,
+    CopyCode,
+    CopyMessage,
+    EditMessage,
+    RegenerateMessage,
+    DeleteMessage,
+    NewChat,
+    CloseChat,
+    RestartShell,
+    QuitAfterShellExit,
+    ConfirmRun,
+}
+
+impl Command {
+    pub const ALL: [Command; 24] = [
+        Command::Quit,
+        Command::ChangeMode,
+        Command::ScrollUp,
+        Command::ScrollDown,
+        Command::Help,
+        Command::DebugPane,
+        Command::NextChat,
+        Command::PreviousChat,
+        Command::ChatList,
+        Command::ExplainError,
+        Command::PreviousCodeBlock,
+        Command::NextCodeBlock,
+        Command::InsertCode,
+        Command::RunCode,
+        Command::CopyCode,
+        Command::CopyMessage,
+        Command::EditMessage,
+        Command::RegenerateMessage,
+        Command::DeleteMessage,
+        Command::NewChat,
+        Command::CloseChat,
+        Command::RestartShell,
+        Command::QuitAfterShellExit,
+        Command::ConfirmRun,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Command::Quit => "Quit",
+            Command::ChangeMode => "Switch between the terminal and the chat",
+            Command::ScrollUp => "Scroll the chat up",
+            Command::ScrollDown => "Scroll the chat down",
+            Command::Help => "Show this help",
+            Command::DebugPane => "Show or hide the log and the last call to the AI",
+            Command::NextChat => "Show the next chat",
+            Command::PreviousChat => "Show the previous chat",
+            Command::ChatList => "List the open chats",
+            Command::ExplainError => "Ask the AI about the command that just failed",
+            Command::PreviousCodeBlock => "Select the previous code block",
+            Command::NextCodeBlock => "Select the next code block",
+            Command::InsertCode => "Insert the selected code into the terminal",
+            Command::RunCode => "Run the selected code block in the terminal",
+            Command::CopyCode => "Copy the selected code block",
+            Command::CopyMessage => "Copy the selected message",
+            Command::EditMessage => "Edit the selected message and send it again",
+            Command::RegenerateMessage => "Regenerate the selected answer",
+            Command::DeleteMessage => "Delete the selected message",
+            Command::NewChat => "Open a new chat, in the list of chats",
+            Command::CloseChat => "Close the chat, in the list of chats",
+            Command::RestartShell => "Restart the shell, once it has exited",
+            Command::QuitAfterShellExit => "Quit, once the shell has exited",
+            Command::ConfirmRun => "Run the code block, when asked to confirm",
+        }
+    }
+
+    /// The name of the command in the config file, like `copy-message`.
+    pub fn name(&self) -> String {
+        let mut name = String::new();
+        for char in format!("{:?}", self).chars() {
+            if char.is_uppercase() && !name.is_empty() {
+                name.push('-');
+            }
+            name.push(char.to_ascii_lowercase());
+        }
+        name
+    }
+
+    /// Whether the command only does something in one place, like when a message is selected, which is where
+    /// its key is looked for rather than in the keymap of the mode.
+    pub fn is_contextual(&self) -> bool {
+        matches!(
+            self,
+            Command::CopyMessage
+                | Command::EditMessage
+                | Command::RegenerateMessage
+                | Command::DeleteMessage
+                | Command::NewChat
+                | Command::CloseChat
+                | Command::RestartShell
+                | Command::QuitAfterShellExit
+                | Command::ConfirmRun
+        )
+    }
+}
+
+/// The keybindings from the `[keys]` table of the config file, which change the defaults.
+/// Each table maps a command to a key such as `"ctrl-b"`, `"alt-x"` or `"f1"`; `"none"` unbinds the command in
+/// that table. The bindings of a mode come on top of the global ones, so moving a global binding to one mode takes
+/// a `"none"` in `global` as well.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeySettings {
+    // A key that has to be pressed before the keys in `prefixed`, like in tmux
+    pub prefix: Option<String>,
+    // Bindings in both modes
+    pub global: HashMap<Command, String>,
+    pub terminal: HashMap<Command, String>,
+    pub chat: HashMap<Command, String>,
+    // Bindings for the key pressed after the prefix, in both modes
+    pub prefixed: HashMap<Command, String>,
+    // Bindings for the contextual commands, which only count where they do something, so they can share keys
+    pub context: HashMap<Command, String>,
+}
+
+/// What a key press turned out to be.
+pub enum KeyAction {
+    Command(Command),
+    // The prefix was pressed, so the next key decides
+    Prefix,
+    // Not bound to anything, so it is handled as a normal key. In the terminal, that means it goes to the shell.
+    Key(KeyEvent),
+}
+
+// A key without the press/release details of a `KeyEvent`
+type Key = (KeyCode, KeyModifiers);
+
+/// Decides which keys are commands for the app, and which are passed on.
+pub struct Keymap {
+    prefix: Option<Key>,
+    global: HashMap<Key, Command>,
+    terminal: HashMap<Key, Command>,
+    chat: HashMap<Key, Command>,
+    prefixed: HashMap<Key, Command>,
+    context: HashMap<Command, Key>,
+    // Whether the prefix was the last key pressed
+    prefix_pressed: bool,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(&KeySettings::default()).expect("the default keys are valid")
+    }
+}
+
+impl Keymap {
+    /// The default bindings, changed by `settings`. Only the chat scrolls with <CTRL>u/d, switches chats with
+    /// <CTRL>n/p/t and acts on code blocks with <ALT> keys by default, so the shell still gets those keys.
+    pub fn new(settings: &KeySettings) -> Result<Self, String> {
+        let mut global = bindings(&[
+            (Command::Quit, "ctrl-q"),
+            (Command::ChangeMode, "ctrl-b"),
+            (Command::Help, "f1"),
+            (Command::DebugPane, "f12"),
+            (Command::ExplainError, "alt-e"),
+        ]);
+        let mut terminal = HashMap::new();
+        let mut chat = bindings(&[
+            (Command::ScrollUp, "ctrl-u"),
+            (Command::ScrollDown, "ctrl-d"),
+            (Command::NextChat, "ctrl-n"),
+            (Command::PreviousChat, "ctrl-p"),
+            (Command::ChatList, "ctrl-t"),
+            (Command::PreviousCodeBlock, "alt-up"),
+            (Command::NextCodeBlock, "alt-down"),
+            (Command::InsertCode, "alt-i"),
+            (Command::RunCode, "alt-r"),
+            (Command::CopyCode, "alt-c"),
+        ]);
+        let mut prefixed = bindings(&[
+            (Command::Quit, "q"),
+            (Command::ChangeMode, "b"),
+            (Command::ScrollUp, "u"),
+            (Command::ScrollDown, "d"),
+            (Command::Help, "?"),
+            (Command::DebugPane, "l"),
+            (Command::NextChat, "n"),
+            (Command::PreviousChat, "p"),
+            (Command::ChatList, "t"),
+            (Command::ExplainError, "e"),
+        ]);
+        rebind(&mut global, &settings.global)?;
+        rebind(&mut terminal, &settings.terminal)?;
+        rebind(&mut chat, &settings.chat)?;
+        rebind(&mut prefixed, &settings.prefixed)?;
+
+        let mut context: HashMap<Command, Key> = [
+            (Command::CopyMessage, "c"),
+            (Command::EditMessage, "e"),
+            (Command::RegenerateMessage, "r"),
+            (Command::DeleteMessage, "d"),
+            (Command::NewChat, "n"),
+            (Command::CloseChat, "x"),
+            (Command::RestartShell, "r"),
+            (Command::QuitAfterShellExit, "q"),
+            (Command::ConfirmRun, "y"),
+        ]
+        .into_iter()
+        .map(|(command, key)| (command, parse_key(key).expect("the default keys are valid")))
+        .collect();
+        for (command, key) in &settings.context {
+            if !command.is_contextual() {
+                return Err(format!(
+                    "{} can't be bound in [keys.context], only in the other key tables",
+                    command.name()
+                ));
+            }
+            match key.as_str() {
+                "none" => context.remove(command),
+                key => context.insert(*command, parse_key(key)?),
+            };
+        }
+
+        Ok(Self {
+            prefix: settings.prefix.as_deref().map(parse_key).transpose()?,
+            global,
+            terminal,
+            chat,
+            prefixed,
+            context,
+            prefix_pressed: false,
+        })
+    }
+
+    /// Whether `key` is the key of the contextual `command`.
+    pub fn is(&self, key: KeyEvent, command: Command) -> bool {
+        self.context.get(&command) == Some(&normalize(key))
+    }
+
+    /// Which of the contextual `commands` `key` is the key of, if any.
+    pub fn contextual(&self, key: KeyEvent, commands: &[Command]) -> Option<Command> {
+        commands
+            .iter()
+            .copied()
+            .find(|command| self.is(key, *command))
+    }
+
+    /// Whether a command is bound to `code` without modifiers in `mode`, which takes it from what it does
+    /// otherwise, like <ENTER> sending a message.
+    pub fn is_bound(&self, code: KeyCode, mode: &Mode) -> bool {
+        let key = normalize(KeyEvent::new(code, KeyModifiers::NONE));
+        let mode_bindings = match mode {
+            Mode::Terminal => &self.terminal,
+            Mode::Chat => &self.chat,
+        };
+        Some(key) == self.prefix
+            || mode_bindings.contains_key(&key)
+            || self.global.contains_key(&key)
+    }
+
+    pub fn resolve(&mut self, key: KeyEvent, mode: &Mode) -> KeyAction {
+        let normalized = normalize(key);
+        if std::mem::take(&mut self.prefix_pressed) {
+            // Pressing the prefix twice sends it on, so it can still be used in the shell
+            if Some(normalized) == self.prefix {
+                return KeyAction::Key(key);
+            }
+            return match self.prefixed.get(&normalized) {
+                Some(command) => KeyAction::Command(*command),
+                None => KeyAction::Key(key),
+            };
+        }
+        if Some(normalized) == self.prefix {
+            self.prefix_pressed = true;
+            return KeyAction::Prefix;
+        }
+
+        let mode_bindings = match mode {
+            Mode::Terminal => &self.terminal,
+            Mode::Chat => &self.chat,
+        };
+        match mode_bindings
+            .get(&normalized)
+            .or_else(|| self.global.get(&normalized))
+        {
+            Some(command) => KeyAction::Command(*command),
+            None => KeyAction::Key(key),
+        }
+    }
+
+    /// How to do `command` in `mode`, such as `<CTRL>b`, if it can be done at all.
+    pub fn describe(&self, command: Command, mode: &Mode) -> Option<String> {
+        let mode_bindings = match mode {
+            Mode::Terminal => &self.terminal,
+            Mode::Chat => &self.chat,
+        };
+        let find = |bindings: &HashMap<Key, Command>| {
+            bindings
+                .iter()
+                .filter(|(_, bound)| **bound == command)
+                .map(|(key, _)| describe_key(*key))
+                .min()
+        };
+        find(mode_bindings)
+            .or_else(|| find(&self.global))
+            .or_else(|| self.context.get(&command).copied().map(describe_key))
+            .or_else(|| {
+                let prefix = describe_key(self.prefix?);
+                Some(format!("{} {}", prefix, find(&self.prefixed)?))
+            })
+    }
+
+    /// Every key bound to `command`, saying where it works, for the help.
+    pub fn describe_all(&self, command: Command) -> Vec<String> {
+        let mut descriptions = vec![];
+        for (bindings, place) in [
+            (&self.global, ""),
+            (&self.terminal, " in the terminal"),
+            (&self.chat, " in the chat"),
+        ] {
+            let mut keys: Vec<String> = bindings
+                .iter()
+                .filter(|(_, bound)| **bound == command)
+                .map(|(key, _)| format!("{}{}", describe_key(*key), place))
+                .collect();
+            keys.sort();
+            descriptions.extend(keys);
+        }
+        if let Some(prefix) = self.prefix {
+            let mut keys: Vec<String> = self
+                .prefixed
+                .iter()
+                .filter(|(_, bound)| **bound == command)
+                .map(|(key, _)| format!("{} {}", describe_key(prefix), describe_key(*key)))
+                .collect();
+            keys.sort();
+            descriptions.extend(keys);
+        }
+        descriptions.extend(self.context.get(&command).copied().map(describe_key));
+        descriptions
+    }
+
+    /// Whether the prefix was pressed, and the next key is a command.
+    pub fn prefix_pressed(&self) -> bool {
+        self.prefix_pressed
+    }
+}
+
+fn bindings(defaults: &[(Command, &str)]) -> HashMap<Key, Command> {
+    defaults
+        .iter()
+        .map(|(command, key)| {
+            (
+                parse_key(key).expect("the default keys are valid"),
+                *command,
+            )
+        })
+        .collect()
+}
+
+fn rebind(
+    bindings: &mut HashMap<Key, Command>,
+    settings: &HashMap<Command, String>,
+) -> Result<(), String> {
+    for (command, key) in settings {
+        if command.is_contextual() {
+            return Err(format!(
+                "{} can only be bound in [keys.context]",
+                command.name()
+            ));
+        }
+        bindings.retain(|_, bound| bound != command);
+        if key != "none" {
+            bindings.insert(parse_key(key)?, *command);
+        }
+    }
+    Ok(())
+}
+
+/// Parses keys like `ctrl-a`, `alt-shift-x`, `f1` or `pageup`.
+fn parse_key(key: &str) -> Result<Key, String> {
+    let lowercase = key.to_lowercase();
+    let mut parts: Vec<&str> = lowercase.split('-').collect();
+    // The key itself can be a dash, as in "ctrl--"
+    if lowercase.ends_with("--") || lowercase == "-" {
+        parts.truncate(parts.len().saturating_sub(2));
+        parts.push("-");
+    }
+    let Some((name, modifier_names)) = parts.split_last() else {
+        return Err("Empty key in the keybindings".to_string());
+    };
+
+    let mut modifiers = KeyModifiers::NONE;
+    for modifier in modifier_names {
+        modifiers |= match *modifier {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" | "meta" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return Err(format!("Unknown modifier {} in the key {}", modifier, key)),
+        };
+    }
+    let code = match *name {
+        "enter" | "return" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" | "ins" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        name => match (
+            name.strip_prefix('f').map(str::parse::<u8>),
+            name.chars().count(),
+        ) {
+            (Some(Ok(number @ 1..=12)), _) => KeyCode::F(number),
+            // Single characters keep their case from the original, so "?" and "A" work
+            (_, 1) => KeyCode::Char(key.chars().last().unwrap_or_default()),
+            _ => return Err(format!("Unknown key {}", key)),
+        },
+    };
+    Ok(normalize(KeyEvent::new(code, modifiers)))
+}
+
+/// Shift is part of the character already, and terminals don't agree on whether to report it as a modifier.
+fn normalize(key: KeyEvent) -> Key {
+    match key.code {
+        KeyCode::Char(char) => (
+            KeyCode::Char(match key.modifiers.contains(KeyModifiers::CONTROL) {
+                // <CTRL>A and <CTRL>a are the same to a terminal
+                true => char.to_ascii_lowercase(),
+                false => char,
+            }),
+            key.modifiers - KeyModifiers::SHIFT,
+        ),
+        code => (code, key.modifiers),
+    }
+}
+
+/// Describes the key the way the footer does, like `<CTRL>b` or `<F1>`.
+pub fn describe_key((code, modifiers): Key) -> String {
+    let mut description = String::new();
+    for (modifier, name) in [
+        (KeyModifiers::CONTROL, "<CTRL>"),
+        (KeyModifiers::ALT, "<ALT>"),
+        (KeyModifiers::SHIFT, "<SHIFT>"),
+    ] {
+        if modifiers.contains(modifier) {
+            description.push_str(name);
+        }
+    }
+    match code {
+        KeyCode::Char(' ') => description.push_str("<SPACE>"),
+        KeyCode::Char(char) => description.push(char),
+        KeyCode::Up => description.push('↑'),
+        KeyCode::Down => description.push('↓'),
+        KeyCode::Left => description.push('←'),
+        KeyCode::Right => description.push('→'),
+        KeyCode::F(number) => description.push_str(&format!("<F{}>", number)),
+        code => description.push_str(&format!("<{:?}>", code).to_uppercase()),
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    fn command(keymap: &mut Keymap, key: KeyEvent, mode: &Mode) -> Option<Command> {
+        match keymap.resolve(key, mode) {
+            KeyAction::Command(command) => Some(command),
+            KeyAction::Prefix | KeyAction::Key(_) => None,
+        }
+    }
+
+    fn settings(toml: &str) -> KeySettings {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn parses_keys() {
+        assert_eq!(
+            parse_key("ctrl-b"),
+            Ok((KeyCode::Char('b'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            parse_key("Ctrl-Alt-X"),
+            Ok((
+                KeyCode::Char('x'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            ))
+        );
+        assert_eq!(
+            parse_key("ctrl--"),
+            Ok((KeyCode::Char('-'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(parse_key("-"), Ok((KeyCode::Char('-'), KeyModifiers::NONE)));
+        assert_eq!(parse_key("f1"), Ok((KeyCode::F(1), KeyModifiers::NONE)));
+        assert_eq!(
+            parse_key("pageup"),
+            Ok((KeyCode::PageUp, KeyModifiers::NONE))
+        );
+        assert_eq!(
+            parse_key("shift-tab"),
+            Ok((KeyCode::Tab, KeyModifiers::SHIFT))
+        );
+        // Shift is part of the character
+        assert_eq!(parse_key("?"), Ok((KeyCode::Char('?'), KeyModifiers::NONE)));
+        assert_eq!(
+            parse_key("shift-A"),
+            Ok((KeyCode::Char('A'), KeyModifiers::NONE))
+        );
+
+        assert!(parse_key("f13").is_err());
+        assert!(parse_key("hyper-x").is_err());
+        assert!(parse_key("c-x").is_err());
+        assert!(parse_key("").is_err());
+    }
+
+    #[test]
+    fn resolves_keys_by_mode() {
+        let mut keymap = Keymap::default();
+        let ctrl_u = press(KeyCode::Char('u'), KeyModifiers::CONTROL);
+        assert_eq!(
+            command(&mut keymap, ctrl_u, &Mode::Chat),
+            Some(Command::ScrollUp)
+        );
+        // The shell gets it in the terminal
+        assert_eq!(command(&mut keymap, ctrl_u, &Mode::Terminal), None);
+        let ctrl_q = press(
+            KeyCode::Char('Q'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+        );
+        for mode in [Mode::Chat, Mode::Terminal] {
+            assert_eq!(command(&mut keymap, ctrl_q, &mode), Some(Command::Quit));
+        }
+        assert_eq!(
+            command(
+                &mut keymap,
+                press(KeyCode::Char('q'), KeyModifiers::NONE),
+                &Mode::Chat
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn resolves_keys_after_the_prefix() {
+        let mut keymap = Keymap::new(&settings("prefix = \"ctrl-a\"")).unwrap();
+        let ctrl_a = press(KeyCode::Char('a'), KeyModifiers::CONTROL);
+        let q = press(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert!(matches!(
+            keymap.resolve(ctrl_a, &Mode::Terminal),
+            KeyAction::Prefix
+        ));
+        assert!(keymap.prefix_pressed());
+        assert_eq!(
+            command(&mut keymap, q, &Mode::Terminal),
+            Some(Command::Quit)
+        );
+        // Only the key right after the prefix is a command
+        assert_eq!(command(&mut keymap, q, &Mode::Terminal), None);
+
+        // Pressing the prefix twice sends it on
+        keymap.resolve(ctrl_a, &Mode::Terminal);
+        assert!(matches!(
+            keymap.resolve(ctrl_a, &Mode::Terminal),
+            KeyAction::Key(key) if key == ctrl_a
+        ));
+        assert_eq!(
+            keymap.describe(Command::Quit, &Mode::Terminal).as_deref(),
+            Some("<CTRL>q")
+        );
+        assert_eq!(
+            keymap
+                .describe(Command::ScrollUp, &Mode::Terminal)
+                .as_deref(),
+            Some("<CTRL>a u")
+        );
+    }
+
+    #[test]
+    fn rebinds_and_unbinds_commands() {
+        let mut keymap = Keymap::new(&settings(
+            "[global]\nchange-mode = \"f2\"\nhelp = \"none\"\n[chat]\nquit = \"alt-q\"",
+        ))
+        .unwrap();
+        let f2 = press(KeyCode::F(2), KeyModifiers::NONE);
+        let ctrl_b = press(KeyCode::Char('b'), KeyModifiers::CONTROL);
+        assert_eq!(
+            command(&mut keymap, f2, &Mode::Terminal),
+            Some(Command::ChangeMode)
+        );
+        assert_eq!(command(&mut keymap, ctrl_b, &Mode::Terminal), None);
+        assert_eq!(
+            command(
+                &mut keymap,
+                press(KeyCode::F(1), KeyModifiers::NONE),
+                &Mode::Chat
+            ),
+            None
+        );
+        assert_eq!(keymap.describe(Command::Help, &Mode::Chat), None);
+
+        // A binding in a mode comes on top of the global one
+        let alt_q = press(KeyCode::Char('q'), KeyModifiers::ALT);
+        let ctrl_q = press(KeyCode::Char('q'), KeyModifiers::CONTROL);
+        assert_eq!(
+            command(&mut keymap, alt_q, &Mode::Chat),
+            Some(Command::Quit)
+        );
+        assert_eq!(command(&mut keymap, alt_q, &Mode::Terminal), None);
+        assert_eq!(
+            command(&mut keymap, ctrl_q, &Mode::Chat),
+            Some(Command::Quit)
+        );
+        assert_eq!(
+            keymap.describe_all(Command::Quit),
+            ["<CTRL>q", "<ALT>q in the chat"]
+        );
+
+        assert!(Keymap::new(&settings("[global]\nquit = \"hyper-q\"")).is_err());
+    }
+
+    #[test]
+    fn keeps_contextual_commands_apart() {
+        let keymap = Keymap::new(&settings("[context]\ndelete-message = \"x\"")).unwrap();
+        let x = press(KeyCode::Char('x'), KeyModifiers::NONE);
+        let r = press(KeyCode::Char('r'), KeyModifiers::NONE);
+        assert!(keymap.is(x, Command::DeleteMessage));
+        assert!(keymap.is(x, Command::CloseChat));
+        assert_eq!(
+            keymap.contextual(r, &[Command::CopyMessage, Command::RegenerateMessage]),
+            Some(Command::RegenerateMessage)
+        );
+        assert!(keymap.is(r, Command::RestartShell));
+        assert_eq!(
+            keymap
+                .describe(Command::DeleteMessage, &Mode::Chat)
+                .as_deref(),
+            Some("x")
+        );
+
+        assert_eq!(
+            Keymap::new(&settings("[chat]\ncopy-message = \"c\"")).err(),
+            Some("copy-message can only be bound in [keys.context]".to_string())
+        );
+        assert!(Keymap::new(&settings("[context]\nquit = \"q\"")).is_err());
+    }
+
+    #[test]
+    fn tells_which_plain_keys_are_taken() {
+        let keymap = Keymap::new(&settings("[chat]\nscroll-up = \"up\"")).unwrap();
+        assert!(keymap.is_bound(KeyCode::Up, &Mode::Chat));
+        assert!(!keymap.is_bound(KeyCode::Up, &Mode::Terminal));
+        assert!(!keymap.is_bound(KeyCode::Enter, &Mode::Chat));
+        assert!(keymap.is_bound(KeyCode::F(1), &Mode::Chat));
+    }
+}
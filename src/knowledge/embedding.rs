@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use async_openai::{
+    config::{AzureConfig, Config, OpenAIConfig},
+    types::CreateEmbeddingRequestArgs,
+    Client,
+};
+use serde::Deserialize;
+
+use crate::config;
+
+/// The embedding model used with an API, unless another one is configured. On Azure, this is the deployment.
+const DEFAULT_MODEL: &str = "text-embedding-3-small";
+/// How many texts are embedded in one request to an API.
+const BATCH_SIZE: usize = 64;
+/// The size of the vectors of the local embedder.
+const LOCAL_DIMENSIONS: usize = 512;
+/// Words that are in almost every text, and would only make unrelated texts look alike.
+const STOP_WORDS: [&str; 32] = [
+    "a", "an", "and", "are", "as", "at", "be", "by", "do", "for", "from", "how", "i", "if", "in",
+    "is", "it", "my", "no", "not", "of", "on", "or", "so", "that", "the", "this", "to", "was",
+    "what", "why", "with",
+];
+
+/// Where the embeddings of the docs and questions come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingProvider {
+    // Computed here from the words in the text. It needs no API, but only finds texts that use the same words.
+    #[default]
+    Local,
+    // OpenAI itself, with the key in the OPENAI_API_KEY environment variable
+    #[serde(rename = "openai")]
+    OpenAi,
+    // Azure OpenAI, set up in the `config` module, with the model as the deployment
+    Azure,
+}
+
+/// Turns texts into vectors, which are close together when the texts are about the same thing.
+pub enum Embedder {
+    Local,
+    OpenAi {
+        client: Client<OpenAIConfig>,
+        model: String,
+    },
+    Azure {
+        client: Client<AzureConfig>,
+        model: String,
+    },
+}
+
+impl Embedder {
+    pub fn new(provider: EmbeddingProvider, model: Option<String>) -> Self {
+        let model = model.unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        match provider {
+            EmbeddingProvider::Local => Embedder::Local,
+            EmbeddingProvider::OpenAi => Embedder::OpenAi {
+                client: Client::with_config(OpenAIConfig::new()),
+                model,
+            },
+            EmbeddingProvider::Azure => Embedder::Azure {
+                client: Client::with_config(config::get_config().with_deployment_id(&model)),
+                model,
+            },
+        }
+    }
+
+    /// Which embedder this is. Vectors from different embedders can't be compared, so an index made by another
+    /// embedder has to be made again.
+    pub fn id(&self) -> String {
+        match self {
+            Embedder::Local => format!("local-{}", LOCAL_DIMENSIONS),
+            Embedder::OpenAi { model, .. } => format!("openai-{}", model),
+            Embedder::Azure { model, .. } => format!("azure-{}", model),
+        }
+    }
+
+    /// The vectors of `texts`, in the same order.
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH_SIZE) {
+            let batch_vectors = match self {
+                Embedder::Local => batch.iter().map(|text| embed_locally(text)).collect(),
+                Embedder::OpenAi { client, model } => embed_remotely(client, model, batch).await?,
+                Embedder::Azure { client, model } => embed_remotely(client, model, batch).await?,
+            };
+            vectors.extend(batch_vectors);
+        }
+        Ok(vectors)
+    }
+}
+
+// OpenAI and Azure embed the same way, only the config of the client differs
+async fn embed_remotely<C: Config>(
+    client: &Client<C>,
+    model: &str,
+    texts: &[String],
+) -> Result<Vec<Vec<f32>>, String> {
+    let request = CreateEmbeddingRequestArgs::default()
+        .model(model)
+        .input(texts.to_vec())
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut data = client
+        .embeddings()
+        .create(request)
+        .await
+        .map_err(|err| err.to_string())?
+        .data;
+    data.sort_by_key(|embedding| embedding.index);
+    Ok(data
+        .into_iter()
+        .map(|embedding| embedding.embedding)
+        .collect())
+}
+
+/// Hashes the words of the text, and the pairs of words next to each other, into a vector of a fixed size.
+/// Words that come up more often count for more, but not proportionally, so a long log of one thing doesn't drown
+/// out the rest.
+fn embed_locally(text: &str) -> Vec<f32> {
+    let words: Vec<String> = text
+        .split(|char: char| !char.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|word| word.len() > 1 && !STOP_WORDS.contains(&word.as_str()))
+        .collect();
+    let mut counts: HashMap<String, f32> = HashMap::new();
+    for word in &words {
+        *counts.entry(word.clone()).or_default() += 1.0;
+    }
+    for pair in words.windows(2) {
+        // Pairs say more about what the text is about, but are rarer
+        *counts
+            .entry(format!("{} {}", pair[0], pair[1]))
+            .or_default() += 0.5;
+    }
+
+    let mut vector = vec![0.0; LOCAL_DIMENSIONS];
+    for (term, count) in counts {
+        let hash = fnv1a(&term);
+        // The sign spreads out collisions, so they cancel out rather than add up
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % LOCAL_DIMENSIONS as u64) as usize] += sign * (1.0 + count.ln());
+    }
+    let length = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if length > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= length);
+    }
+    vector
+}
+
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// How alike two vectors are, from -1 to 1.
+pub fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let length = |vector: &[f32]| vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    match length(a) * length(b) {
+        0.0 => 0.0,
+        lengths => dot / lengths,
+    }
+}
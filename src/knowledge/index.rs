@@ -0,0 +1,60 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::file_utils;
+
+/// The chunks of the docs and their vectors, kept in a file so only the files that changed are embedded again.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Index {
+    // The embedder that made the vectors, as an index made by another one is of no use
+    pub embedder: String,
+    pub files: HashMap<PathBuf, IndexedFile>,
+}
+
+/// A file in the docs, as it was when it was indexed.
+#[derive(Serialize, Deserialize)]
+pub struct IndexedFile {
+    // The path to show, relative to the folder of docs it is in
+    pub source: String,
+    // When it was last changed, in seconds since the epoch, and its size, to tell whether it has to be indexed again
+    pub modified: u64,
+    pub size: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+/// A piece of a file, small enough that several fit in the prompt.
+#[derive(Serialize, Deserialize)]
+pub struct Chunk {
+    // The lines it spans, counted from 1
+    pub first_line: usize,
+    pub last_line: usize,
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+impl Index {
+    /// The index saved at `path`, or an empty one if there is none yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Saves the index to `path`. A half written index would fail to load and every file would be embedded again,
+    /// so the old one is only replaced once the new one is complete.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string(self)?;
+        file_utils::write_atomically(path, json)
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.files.values().map(|file| file.chunks.len()).sum()
+    }
+}
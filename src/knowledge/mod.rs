@@ -0,0 +1,422 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::Deserialize;
+
+use crate::app_state::Message;
+
+use self::{
+    embedding::{similarity, Embedder, EmbeddingProvider},
+    index::{Chunk, Index, IndexedFile},
+};
+
+/// Turning text into vectors, locally or through an API
+pub mod embedding;
+
+/// The file the vectors are kept in
+pub mod index;
+
+/// The kinds of files that are indexed, unless others are configured.
+const DEFAULT_EXTENSIONS: [&str; 7] = ["md", "markdown", "txt", "rst", "conf", "cfg", "ini"];
+/// Bigger files are more likely to be logs or dumps than docs.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// Chunks end at the first blank line after this many bytes, or at a heading.
+const CHUNK_SIZE: usize = 1000;
+/// Chunks without blank lines end here anyway.
+const MAX_CHUNK_SIZE: usize = 2000;
+/// How much of the end of the terminal output is searched for along with the question, as that is where errors are.
+const QUERY_CONTEXT: usize = 1000;
+
+/// The `[knowledge]` table of the config file: local docs to search for what to add to the prompt.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KnowledgeSettings {
+    // Folders and files of docs, such as runbooks and device configs. Nothing is indexed without any.
+    pub paths: Vec<PathBuf>,
+    pub embedding: EmbeddingProvider,
+    // The embedding model, or on Azure the deployment of one
+    pub embedding_model: Option<String>,
+    // The extensions of the files to index, instead of Markdown, text and config files
+    pub extensions: Vec<String>,
+    // Where the index is kept, instead of the user's data directory
+    pub index: Option<PathBuf>,
+    // How many excerpts are added to the prompt at most
+    pub excerpts: Option<usize>,
+    // How alike an excerpt has to be to the question to be added, from 0 to 1
+    pub min_score: Option<f32>,
+}
+
+/// A chunk of the docs that matched a question.
+pub struct Excerpt {
+    // Where it came from, like `docs/runbooks/bgp.md:12-40`
+    pub source: String,
+    pub text: String,
+    pub score: f32,
+}
+
+/// The local docs, indexed so the chunks that match a question can be found.
+pub struct Knowledge {
+    paths: Vec<PathBuf>,
+    extensions: Vec<String>,
+    embedder: Embedder,
+    index: Index,
+    index_path: PathBuf,
+    excerpts: usize,
+    min_score: f32,
+}
+
+impl Knowledge {
+    /// The docs in `settings`, or nothing if there are none. The index is loaded by the first `update`.
+    pub fn new(settings: &KnowledgeSettings) -> Option<Self> {
+        if settings.paths.is_empty() {
+            return None;
+        }
+        Some(Self {
+            paths: settings.paths.clone(),
+            extensions: match settings.extensions.is_empty() {
+                true => DEFAULT_EXTENSIONS.map(str::to_string).to_vec(),
+                false => settings.extensions.clone(),
+            },
+            embedder: Embedder::new(settings.embedding, settings.embedding_model.clone()),
+            index: Index::default(),
+            index_path: settings.index.clone().unwrap_or_else(default_index_path),
+            excerpts: settings.excerpts.unwrap_or(4),
+            min_score: settings.min_score.unwrap_or(0.2),
+        })
+    }
+
+    /// Indexes the files that are new or changed since the last time, and forgets those that are gone.
+    /// Returns a summary for the user.
+    pub async fn update(&mut self) -> Result<String, String> {
+        let embedder = self.embedder.id();
+        if self.index.embedder != embedder {
+            self.index = Index::load(&self.index_path).unwrap_or_else(|err| {
+                tracing::warn!("Failed to load {}: {}", self.index_path.display(), err);
+                Index::default()
+            });
+            if self.index.embedder != embedder {
+                tracing::info!("Indexing the docs again for {}", embedder);
+                self.index = Index {
+                    embedder,
+                    ..Index::default()
+                };
+            }
+        }
+
+        let mut found = vec![];
+        let mut missing = vec![];
+        for root in &self.paths {
+            match fs::metadata(root) {
+                Ok(_) => find_files(root, root, &self.extensions, &mut found),
+                Err(err) => {
+                    tracing::warn!("Failed to read {}: {}", root.display(), err);
+                    missing.push(root.display().to_string());
+                }
+            }
+        }
+
+        // Embed the chunks of every changed file at once, so an API gets as few requests as possible
+        let mut changed: Vec<(PathBuf, IndexedFile)> = vec![];
+        let mut texts = vec![];
+        for (path, source) in &found {
+            let Ok(metadata) = fs::metadata(path) else {
+                continue;
+            };
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_secs());
+            if self.index.files.get(path).is_some_and(|file| {
+                file.modified == modified && file.size == metadata.len() && file.source == *source
+            }) {
+                continue;
+            }
+            // Files that aren't text, like a binary with a .conf extension, are left out
+            let Ok(contents) = fs::read_to_string(path) else {
+                tracing::debug!("Skipping {}, which isn't text", path.display());
+                continue;
+            };
+            let chunks: Vec<Chunk> = split(&contents)
+                .into_iter()
+                .map(|(first_line, last_line, text)| Chunk {
+                    first_line,
+                    last_line,
+                    text,
+                    vector: vec![],
+                })
+                .collect();
+            texts.extend(chunks.iter().map(|chunk| chunk.text.clone()));
+            changed.push((
+                path.clone(),
+                IndexedFile {
+                    source: source.clone(),
+                    modified,
+                    size: metadata.len(),
+                    chunks,
+                },
+            ));
+        }
+        let mut vectors = self.embedder.embed(&texts).await?.into_iter();
+        let changed_count = changed.len();
+        for (path, mut file) in changed {
+            for chunk in &mut file.chunks {
+                chunk.vector = vectors.next().unwrap_or_default();
+            }
+            self.index.files.insert(path, file);
+        }
+        self.index
+            .files
+            .retain(|path, _| found.iter().any(|(found, _)| found == path));
+
+        if let Err(err) = self.index.save(&self.index_path) {
+            tracing::warn!("Failed to save {}: {}", self.index_path.display(), err);
+        }
+        let mut summary = format!(
+            "Indexed {} docs in {} chunks, {} of them changed",
+            self.index.files.len(),
+            self.index.chunk_count(),
+            changed_count
+        );
+        if !missing.is_empty() {
+            summary.push_str(&format!(", {} not found", missing.join(", ")));
+        }
+        tracing::info!("{}", summary);
+        Ok(summary)
+    }
+
+    /// The chunks that match `query` best, best first.
+    pub async fn search(&self, query: &str) -> Result<Vec<Excerpt>, String> {
+        if self.index.files.is_empty() || query.trim().is_empty() {
+            return Ok(vec![]);
+        }
+        let query_vector = self
+            .embedder
+            .embed(&[query.to_string()])
+            .await?
+            .pop()
+            .unwrap_or_default();
+        let mut excerpts: Vec<Excerpt> = self
+            .index
+            .files
+            .values()
+            .flat_map(|file| {
+                file.chunks.iter().map(|chunk| Excerpt {
+                    source: format!("{}:{}-{}", file.source, chunk.first_line, chunk.last_line),
+                    text: chunk.text.clone(),
+                    score: similarity(&query_vector, &chunk.vector),
+                })
+            })
+            .filter(|excerpt| excerpt.score >= self.min_score)
+            .collect();
+        excerpts.sort_by(|a, b| b.score.total_cmp(&a.score));
+        excerpts.truncate(self.excerpts);
+        Ok(excerpts)
+    }
+}
+
+/// Where the index is kept, in the user's data directory.
+pub fn default_index_path() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("terminal-ai-ops")
+        .join("knowledge.json")
+}
+
+/// What to search the docs for: the question, and the end of the terminal output sent with it.
+pub fn query(message: &Message) -> String {
+    match &message.terminal_context {
+        Some(terminal_context) => {
+            let mut start = terminal_context.len().saturating_sub(QUERY_CONTEXT);
+            while !terminal_context.is_char_boundary(start) {
+                start += 1;
+            }
+            format!("{}\n{}", message.message, &terminal_context[start..])
+        }
+        None => message.message.clone(),
+    }
+}
+
+/// The system prompt that gives the AI the excerpts, and asks it to say which it used.
+pub fn prompt(excerpts: &[Excerpt]) -> String {
+    let mut prompt = String::from(
+        "These excerpts from the user's own docs may help with the question. Prefer them over general advice when \
+        they apply, and cite the source of each one you use in brackets, like [runbooks/bgp.md:12-40]. Ignore those \
+        that don't apply.",
+    );
+    for excerpt in excerpts {
+        prompt.push_str(&format!(
+            "\n\n[{}]\n{}",
+            excerpt.source,
+            excerpt.text.trim_end()
+        ));
+    }
+    prompt
+}
+
+/// Finds the files to index under `path`, with the path to show for each, relative to the folder `root` is in.
+/// Hidden files and folders, such as `.git`, are left out.
+fn find_files(root: &Path, path: &Path, extensions: &[String], found: &mut Vec<(PathBuf, String)>) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    if metadata.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            tracing::warn!("Failed to read {}", path.display());
+            return;
+        };
+        let mut entries: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            // Following links to folders could go around in circles
+            .filter(|entry| {
+                !entry
+                    .file_type()
+                    .is_ok_and(|kind| kind.is_symlink() && entry.path().is_dir())
+            })
+            .map(|entry| entry.path())
+            .filter(|path| {
+                !path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+            })
+            .collect();
+        entries.sort();
+        for entry in entries {
+            find_files(root, &entry, extensions, found);
+        }
+    } else if metadata.len() <= MAX_FILE_SIZE
+        && path.extension().is_some_and(|extension| {
+            extensions
+                .iter()
+                .any(|wanted| extension.eq_ignore_ascii_case(wanted.as_str()))
+        })
+    {
+        let base = root.parent().unwrap_or(root);
+        let source = path.strip_prefix(base).unwrap_or(path);
+        found.push((path.to_path_buf(), source.display().to_string()));
+    }
+}
+
+/// Splits a file into chunks of whole paragraphs, with the lines each spans. A Markdown heading starts a new chunk,
+/// so a chunk is about one thing.
+fn split(contents: &str) -> Vec<(usize, usize, String)> {
+    let mut chunks = vec![];
+    let mut text = String::new();
+    let (mut first_line, mut last_line) = (1, 1);
+    for (number, line) in contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+    {
+        let heading = line.starts_with('#') && line.trim_start_matches('#').starts_with(' ');
+        let full = (line.trim().is_empty() && text.len() >= CHUNK_SIZE)
+            || text.len() + line.len() > MAX_CHUNK_SIZE;
+        if (heading || full) && !text.is_empty() {
+            chunks.push((first_line, last_line, std::mem::take(&mut text)));
+        }
+        if line.trim().is_empty() && text.is_empty() {
+            continue;
+        }
+        if text.is_empty() {
+            first_line = number;
+        }
+        if !line.trim().is_empty() {
+            last_line = number;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    if !text.is_empty() {
+        chunks.push((first_line, last_line, text));
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(chunks: &[(usize, usize, String)]) -> Vec<(usize, usize)> {
+        chunks
+            .iter()
+            .map(|(first_line, last_line, _)| (*first_line, *last_line))
+            .collect()
+    }
+
+    #[test]
+    fn splits_at_headings() {
+        let chunks = split("\n# BGP\nCheck the peers.\n\n## Flapping\nLook at the logs.\n");
+        assert_eq!(
+            chunks,
+            [
+                (2, 3, "# BGP\nCheck the peers.\n\n".to_string()),
+                (5, 6, "## Flapping\nLook at the logs.\n".to_string()),
+            ]
+        );
+        // Without a space, it is a comment in a config rather than a heading
+        assert_eq!(split("interface eth0\n#shutdown\n").len(), 1);
+    }
+
+    #[test]
+    fn splits_long_text_at_blank_lines() {
+        let paragraph = "word ".repeat(120);
+        let contents = format!("{0}\n\n{0}\n\n{0}\n", paragraph);
+        // The first paragraph is too short to be a chunk of its own, so the second is added to it
+        assert_eq!(lines(&split(&contents)), [(1, 3), (5, 5)]);
+
+        // Without blank lines, a chunk ends once it would get too big
+        let contents = format!("{}\n", "x".repeat(99)).repeat(30);
+        let chunks = split(&contents);
+        assert_eq!(lines(&chunks), [(1, 20), (21, 30)]);
+        assert!(chunks
+            .iter()
+            .all(|(_, _, text)| text.len() <= MAX_CHUNK_SIZE && text.len() > CHUNK_SIZE / 2));
+    }
+
+    #[test]
+    fn queries_the_end_of_the_terminal_output() {
+        let message = Message::user("Why?".to_string(), None);
+        assert_eq!(query(&message), "Why?");
+
+        // The cut would be in the middle of the first "é", so it moves past it
+        let terminal_context = format!("{}x", "é".repeat(QUERY_CONTEXT / 2));
+        let message = Message::user("Why?".to_string(), Some(terminal_context));
+        assert_eq!(
+            query(&message),
+            format!("Why?\n{}x", "é".repeat(QUERY_CONTEXT / 2 - 1))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_files_leaving_out_hidden_ones_and_linked_folders() {
+        let folder =
+            std::env::temp_dir().join(format!("terminal-ai-ops-knowledge-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        let docs = folder.join("docs");
+        fs::create_dir_all(docs.join("runbooks")).unwrap();
+        fs::create_dir_all(docs.join(".git")).unwrap();
+        fs::write(docs.join("a.md"), "").unwrap();
+        fs::write(docs.join("b.CONF"), "").unwrap();
+        fs::write(docs.join("c.rs"), "").unwrap();
+        fs::write(docs.join(".hidden.md"), "").unwrap();
+        fs::write(docs.join(".git").join("notes.md"), "").unwrap();
+        fs::write(docs.join("runbooks").join("bgp.md"), "").unwrap();
+        // A link back up would go around in circles
+        std::os::unix::fs::symlink(&docs, docs.join("runbooks").join("up")).unwrap();
+
+        let extensions: Vec<String> = DEFAULT_EXTENSIONS.map(String::from).to_vec();
+        let mut found = vec![];
+        find_files(&docs, &docs, &extensions, &mut found);
+        fs::remove_dir_all(&folder).unwrap();
+        let sources: Vec<&str> = found.iter().map(|(_, source)| source.as_str()).collect();
+        assert_eq!(
+            sources,
+            ["docs/a.md", "docs/b.CONF", "docs/runbooks/bgp.md"]
+        );
+        assert_eq!(found[0].0, docs.join("a.md"));
+    }
+}
@@ -3,9 +3,66 @@ pub mod app_state;
 /// Terminal utils.
 pub mod terminal_utils;
 
+/// Writing files without leaving them half written
+pub mod file_utils;
+
 /// Config
 pub mod config;
 
+/// Settings from the config file
+pub mod settings;
+
+/// Command line arguments
+pub mod cli;
+
+/// Key bindings
+pub mod keymap;
+
+/// Commands typed into the chat
+pub mod slash_commands;
+
+/// Colors of the TUI
+pub mod theme;
+
+/// Markdown rendering
+pub mod markdown;
+
+/// Syntax highlighting of code blocks
+pub mod highlight;
+
+/// Copying to the system clipboard
+pub mod clipboard;
+
+/// Conversations with the AI, kept as a tree of alternatives
+pub mod conversation;
+
+/// Files attached to chat messages
+pub mod attachment;
+
+/// Saving and resuming conversations
+pub mod session;
+
+/// Exporting conversations to Markdown, JSON and HTML
+pub mod export;
+
+/// Token usage and what it costs
+pub mod usage;
+
+/// Searching local docs for what to add to the prompt
+pub mod knowledge;
+
+/// Spotting failed commands in the terminal output
+pub mod error_watch;
+
+/// Recording and replaying terminal sessions as asciicast v2
+pub mod recording;
+
+/// Asking the AI from scripts, without the TUI
+pub mod headless;
+
+/// Recent log records and AI calls, for the debug pane
+pub mod debug_log;
+
 /// Services
 pub mod services;
 
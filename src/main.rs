@@ -1,41 +1,184 @@
+use clap::Parser;
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
 use std::io;
+use std::process::ExitCode;
 use std::sync::Arc;
-use terminal_ai_ops::app_state::AppState;
-use terminal_ai_ops::services::chat_service::ChatService;
+use terminal_ai_ops::app_state::{AppState, Chat};
+use terminal_ai_ops::cli::Cli;
+use terminal_ai_ops::debug_log::DebugLog;
+use terminal_ai_ops::highlight;
+use terminal_ai_ops::keymap::Keymap;
+use terminal_ai_ops::knowledge::Knowledge;
+use terminal_ai_ops::recording::{self, Recorder, Recording};
+use terminal_ai_ops::services::chat_service::{
+    default_model, ChatService, Parameters, Provider, TEMPERATURES,
+};
 use terminal_ai_ops::services::event_service::EventService;
 use terminal_ai_ops::services::ui_service::UiService;
 use terminal_ai_ops::services::{chat_service::Action, event_service::Event};
-use terminal_ai_ops::terminal_utils;
+use terminal_ai_ops::session;
+use terminal_ai_ops::terminal_utils::{self, OutputOptions};
+use terminal_ai_ops::theme::Theme;
 use terminal_ai_ops::tracing::init_tracing;
+use terminal_ai_ops::usage::Prices;
 use tokio::sync::mpsc::{self};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
+
+/// Reports a problem with the arguments or the files they point to, before the TUI takes over the screen.
+fn exit_with_error(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let settings = cli.settings().unwrap_or_else(|err| exit_with_error(err));
     // drop after main is over
-    let _guard = init_tracing();
+    let debug_log = Arc::new(DebugLog::default());
+    let _guard =
+        init_tracing(&settings, debug_log.clone()).unwrap_or_else(|err| exit_with_error(err));
+
+    let provider = settings.provider.unwrap_or_default();
+    let role = settings.role.unwrap_or_default();
+    if let Some(temperature) = settings.temperature {
+        if !TEMPERATURES.contains(&temperature) {
+            exit_with_error(format!(
+                "The temperature should be from 0 to 2, not {}",
+                temperature
+            ));
+        }
+    }
+    let parameters = Parameters {
+        model: settings
+            .model
+            .clone()
+            .unwrap_or_else(|| default_model(provider)),
+        temperature: settings.temperature,
+        max_tokens: settings.max_tokens,
+    };
+    let mut chat_service = ChatService::new(provider, settings.model.clone(), debug_log.clone())
+        // Older Azure API versions reject the request for the usage, and there is no telling which one is in use
+        .with_stream_usage(settings.stream_usage.unwrap_or(provider != Provider::Azure))
+        .with_knowledge(Knowledge::new(&settings.knowledge));
+    if let Some(command) = cli.command {
+        return command.execute(chat_service, role, parameters).await;
+    }
+
+    // Read the recording before taking over the screen, so a bad file is reported plainly
+    let recording = cli.replay.map(|path| {
+        Recording::load(&path).unwrap_or_else(|err| {
+            exit_with_error(format!(
+                "Failed to load the recording {}: {}",
+                path.display(),
+                err
+            ))
+        })
+    });
+
+    // Loading the syntaxes for code blocks takes a moment, so get it out of the way while we start
+    tokio::task::spawn_blocking(highlight::warm_up);
 
     let (action_sender, mut action_receiver) = mpsc::unbounded_channel::<Action>();
+    // Bring the index up to date while the user gets started, rather than on the first question
+    if chat_service.has_knowledge() {
+        action_sender.send(Action::IndexKnowledge).unwrap();
+    }
     let (event_sender, event_receiver) = mpsc::unbounded_channel::<Event>();
 
     let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout())).unwrap();
     let terminal_context = Arc::new(Mutex::new(String::new()));
-    let app_state = AppState::new(terminal_context.clone());
-    let mut event_service = EventService::new(event_receiver);
-    let (parser, terminal_sender) = terminal_utils::new(&terminal, terminal_context.clone());
+    let mut app_state = AppState::new(terminal_context.clone());
+    app_state.debug_log = debug_log;
+    app_state.models = settings.models;
+    app_state.prices = Prices::new(&settings.prices);
+    app_state.layout = settings.layout.unwrap_or_default();
+    app_state.shell = settings.shell;
+    app_state.watch_errors = settings.watch_errors.unwrap_or(true);
+    app_state.keymap = Keymap::new(&settings.keys)
+        .unwrap_or_else(|err| exit_with_error(format!("Invalid keybindings: {}", err)));
+    if let Some(theme) = &settings.theme {
+        app_state.theme = Theme::load(theme).unwrap_or_else(|err| exit_with_error(err));
+    }
+    if let Some(emoji) = settings.emoji {
+        app_state.theme.emoji = emoji;
+    }
+
+    app_state.chat = match cli.resume {
+        Some(path) => {
+            let Some(path) = path.or_else(|| session::latest(&[])) else {
+                exit_with_error("There is no session to resume".to_string());
+            };
+            let session = session::load(&path).unwrap_or_else(|err| {
+                exit_with_error(format!(
+                    "Failed to load the session {}: {}",
+                    path.display(),
+                    err
+                ))
+            });
+            Chat::resume(session, path, parameters, settings.model.clone())
+        }
+        None => Chat::new(role.title().to_string(), role, parameters),
+    };
+
+    let mut event_service = EventService::new(event_receiver, Arc::new(Notify::new()));
+    let (parser, terminal_sender) = match recording {
+        Some(recording) => {
+            // There is no shell to switch to, but the recording is what the user wants to ask about
+            app_state.terminal_has_been_active = true;
+            recording::replay(
+                recording,
+                terminal_context.clone(),
+                event_sender.clone(),
+                event_service.terminal_updated(),
+            )
+        }
+        None => {
+            if let Some(path) = cli.record {
+                let (cols, rows) = terminal_utils::pane_size(&terminal, app_state.layout)
+                    .unwrap_or_else(|err| {
+                        exit_with_error(format!("Failed to get the terminal size: {}", err))
+                    });
+                let recorder = Recorder::create(&path, cols, rows).unwrap_or_else(|err| {
+                    exit_with_error(format!(
+                        "Failed to create the recording {}: {}",
+                        path.display(),
+                        err
+                    ))
+                });
+                app_state.recorder = Some(Arc::new(Mutex::new(recorder)));
+            }
+            let (parser, terminal_sender, pty_sizer) = terminal_utils::new(
+                &terminal,
+                app_state.shell.as_deref(),
+                app_state.layout,
+                terminal_context.clone(),
+                event_sender.clone(),
+                event_service.terminal_updated(),
+                OutputOptions {
+                    recorder: app_state.recorder.clone(),
+                    watch_errors: app_state.watch_errors,
+                    ..OutputOptions::default()
+                },
+            )
+            .unwrap_or_else(|err| exit_with_error(format!("Failed to start the shell: {}", err)));
+            app_state.pty_sizer = Some(pty_sizer);
+            (parser, terminal_sender)
+        }
+    };
     let mut ui_service = UiService::new(
         action_sender,
+        event_sender.clone(),
         app_state,
         &mut terminal,
         terminal_sender.clone(),
     );
 
-    let mut chat_service = ChatService::new();
     tokio::spawn(async move { chat_service.start(event_sender, &mut action_receiver).await });
     ui_service
         .start(&mut terminal, &mut event_service, parser)
         .await;
     ui_service.exit(&mut terminal);
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,797 @@
+use crate::{highlight, theme::Theme};
+use pulldown_cmark::{
+    Alignment, CodeBlockKind, CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd,
+};
+use ratatui::{
+    style::{Modifier, Style, Stylize},
+    text::{Line, Span},
+};
+
+const OPTIONS: Options = Options::ENABLE_TABLES
+    .union(Options::ENABLE_STRIKETHROUGH)
+    .union(Options::ENABLE_TASKLISTS);
+
+/// Renders markdown, as written by the AI, into lines for the chat.
+///
+/// The markdown might still be streaming in, so it has to cope with unfinished input. pulldown_cmark already
+/// treats an unclosed code fence as code until the end, and unclosed emphasis as plain text, which is what we want:
+/// the next chunk simply re-renders the message.
+///
+/// Code blocks are numbered, starting at `first_code_block`, so the user can pick one to act on.
+/// The one matching `selected_code_block` is highlighted.
+pub fn render(
+    markdown: &str,
+    width: usize,
+    first_code_block: usize,
+    selected_code_block: Option<SelectedCodeBlock>,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    let mut renderer = Renderer::new(width, first_code_block, selected_code_block, *theme);
+    for event in Parser::new_ext(markdown, OPTIONS) {
+        renderer.handle(event);
+    }
+    renderer.finish()
+}
+
+/// Returns the code blocks in the markdown, in the order `render` numbers them.
+pub fn code_blocks(markdown: &str) -> Vec<CodeBlock> {
+    let mut code_blocks = vec![];
+    let mut current: Option<CodeBlock> = None;
+    for event in Parser::new_ext(markdown, OPTIONS) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => current = Some(CodeBlock::new(kind)),
+            Event::Text(text) => {
+                if let Some(code_block) = &mut current {
+                    code_block.code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => code_blocks.extend(current.take()),
+            _ => {}
+        }
+    }
+    code_blocks.extend(current);
+    code_blocks
+}
+
+/// Renders markdown to HTML, for exporting the chat. HTML in the markdown is escaped rather than passed through.
+pub fn to_html(markdown: &str) -> String {
+    let events = Parser::new_ext(markdown, OPTIONS).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events);
+    html
+}
+
+/// The URL of a link or image in an export, unless it could run something when the file is opened in a browser,
+/// like a `javascript:` or `data:` URL. Only http, https and mailto URLs are kept, along with relative ones.
+fn safe_url(url: CowStr) -> CowStr {
+    // Browsers ignore whitespace and control characters in the scheme, so `java\tscript:` is still JavaScript
+    let normalized: String = url
+        .chars()
+        .filter(|char| !char.is_ascii_whitespace() && !char.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    match normalized.find([':', '/', '?', '#']) {
+        Some(end)
+            if normalized[end..].starts_with(':')
+                && !["http", "https", "mailto"].contains(&&normalized[..end]) =>
+        {
+            CowStr::Borrowed("")
+        }
+        _ => url,
+    }
+}
+
+struct List {
+    // The next number, for ordered lists
+    next: Option<u64>,
+    // The width of the marker of the current item, which its nested content is indented by
+    marker_width: usize,
+}
+
+#[derive(Clone)]
+pub struct CodeBlock {
+    pub language: String,
+    pub code: String,
+}
+
+impl CodeBlock {
+    fn new(kind: CodeBlockKind) -> Self {
+        let language = match kind {
+            CodeBlockKind::Fenced(info) => info
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            CodeBlockKind::Indented => String::new(),
+        };
+        Self {
+            language,
+            code: String::new(),
+        }
+    }
+}
+
+// A link or image being rendered, whose URL is shown after its text
+struct Link {
+    url: String,
+    // The text written for it so far, which can be in a table cell rather than the current line
+    text: String,
+}
+
+#[derive(Default)]
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<Vec<Span<'static>>>>,
+    header_rows: usize,
+}
+
+/// The code block the user has selected, and the keys that act on it, which are shown in its header.
+#[derive(Clone, Copy)]
+pub struct SelectedCodeBlock<'a> {
+    pub number: usize,
+    pub hint: &'a str,
+}
+
+struct Renderer {
+    width: usize,
+    lines: Vec<Line<'static>>,
+    current: Vec<Span<'static>>,
+    styles: Vec<Style>,
+    lists: Vec<List>,
+    // The marker of a list item we have started, but not written any text for yet
+    pending_marker: Option<String>,
+    block_quotes: usize,
+    code_block: Option<CodeBlock>,
+    table: Option<Table>,
+    link: Option<Link>,
+    // The number of the next code block, and the one the user has selected with the keys that act on it
+    next_code_block: usize,
+    selected_code_block: Option<usize>,
+    code_block_hint: String,
+    theme: Theme,
+}
+
+impl Renderer {
+    fn new(
+        width: usize,
+        first_code_block: usize,
+        selected_code_block: Option<SelectedCodeBlock>,
+        theme: Theme,
+    ) -> Self {
+        Self {
+            width,
+            lines: vec![],
+            current: vec![],
+            styles: vec![Style::default()],
+            lists: vec![],
+            pending_marker: None,
+            block_quotes: 0,
+            code_block: None,
+            table: None,
+            link: None,
+            next_code_block: first_code_block,
+            selected_code_block: selected_code_block.map(|selected| selected.number),
+            code_block_hint: selected_code_block
+                .map(|selected| selected.hint.to_string())
+                .unwrap_or_default(),
+            theme,
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => {
+                if let Some(code_block) = &mut self.code_block {
+                    code_block.code.push_str(&text);
+                } else {
+                    let style = self.style();
+                    self.push_span(Span::styled(text.into_string(), style));
+                }
+            }
+            Event::Code(code) => {
+                let style = self.style().patch(code_style(&self.theme));
+                self.push_span(Span::styled(code.into_string(), style));
+            }
+            Event::InlineMath(text) | Event::DisplayMath(text) => {
+                let style = self.style();
+                self.push_span(Span::styled(text.into_string(), style));
+            }
+            Event::Html(html) | Event::InlineHtml(html) => {
+                for (index, line) in html.lines().enumerate() {
+                    if index > 0 {
+                        self.flush();
+                    }
+                    self.push_span(Span::raw(line.to_string()));
+                }
+            }
+            Event::FootnoteReference(name) => {
+                self.push_span(Span::raw(format!("[{}]", name)));
+            }
+            // The AI uses single newlines deliberately, so keep them rather than joining the lines
+            Event::SoftBreak | Event::HardBreak => self.flush(),
+            Event::Rule => {
+                self.flush();
+                let width = self.width.saturating_sub(self.prefix_width()).max(1);
+                self.push_line(vec![Span::raw("─".repeat(width)).fg(self.theme.muted)]);
+                self.blank_line();
+            }
+            Event::TaskListMarker(checked) => {
+                self.push_span(Span::raw(if checked { "[x] " } else { "[ ] " }));
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => {}
+            Tag::Heading { level, .. } => {
+                self.flush();
+                self.styles
+                    .push(self.style().patch(heading_style(level, &self.theme)));
+            }
+            Tag::BlockQuote(_) => {
+                self.flush();
+                self.block_quotes += 1;
+            }
+            Tag::CodeBlock(kind) => {
+                self.flush();
+                self.code_block = Some(CodeBlock::new(kind));
+            }
+            Tag::List(start) => {
+                self.flush();
+                self.lists.push(List {
+                    next: start,
+                    marker_width: 2,
+                });
+            }
+            Tag::Item => {
+                self.flush();
+                if let Some(list) = self.lists.last_mut() {
+                    let marker = match &mut list.next {
+                        Some(next) => {
+                            *next += 1;
+                            format!("{}. ", *next - 1)
+                        }
+                        None => "• ".to_string(),
+                    };
+                    list.marker_width = marker.chars().count();
+                    self.pending_marker = Some(marker);
+                }
+            }
+            Tag::Table(alignments) => {
+                self.flush();
+                self.table = Some(Table {
+                    alignments,
+                    ..Default::default()
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(vec![]);
+                }
+            }
+            Tag::TableCell => {
+                if let Some(row) = self.table.as_mut().and_then(|table| table.rows.last_mut()) {
+                    row.push(vec![]);
+                }
+            }
+            Tag::Emphasis => self.styles.push(self.style().italic()),
+            Tag::Strong => self.styles.push(self.style().bold()),
+            Tag::Strikethrough => self.styles.push(self.style().crossed_out()),
+            Tag::Link { dest_url, .. } => {
+                self.styles
+                    .push(self.style().fg(self.theme.accent).underlined());
+                self.link = Some(Link {
+                    url: dest_url.into_string(),
+                    text: String::new(),
+                });
+            }
+            Tag::Image { dest_url, .. } => {
+                let icon = if self.theme.emoji { "🖼 " } else { "image: " };
+                self.push_span(Span::raw(icon).fg(self.theme.muted));
+                self.styles.push(self.style().fg(self.theme.accent));
+                self.link = Some(Link {
+                    url: dest_url.into_string(),
+                    text: String::new(),
+                });
+            }
+            Tag::FootnoteDefinition(name) => {
+                self.flush();
+                self.push_span(Span::raw(format!("[{}]: ", name)).fg(self.theme.muted));
+            }
+            Tag::HtmlBlock
+            | Tag::DefinitionList
+            | Tag::DefinitionListTitle
+            | Tag::DefinitionListDefinition
+            | Tag::MetadataBlock(_) => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph => {
+                self.flush();
+                // Paragraphs inside list items are only used for loose lists, keep those compact anyway
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+            }
+            TagEnd::Heading(_) => {
+                self.styles.pop();
+                self.flush();
+                self.blank_line();
+            }
+            TagEnd::BlockQuote(_) => {
+                self.flush();
+                self.block_quotes = self.block_quotes.saturating_sub(1);
+                if self.block_quotes == 0 {
+                    self.blank_line();
+                }
+            }
+            TagEnd::CodeBlock => {
+                if let Some(code_block) = self.code_block.take() {
+                    self.push_code_block(code_block);
+                }
+                self.blank_line();
+            }
+            TagEnd::List(_) => {
+                self.flush();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+            }
+            TagEnd::Item => {
+                self.flush();
+                self.pending_marker = None;
+            }
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.push_table(table);
+                }
+                self.blank_line();
+            }
+            TagEnd::TableHead => {
+                if let Some(table) = &mut self.table {
+                    table.header_rows = table.rows.len();
+                }
+            }
+            TagEnd::TableRow | TagEnd::TableCell => {}
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => {
+                self.styles.pop();
+            }
+            TagEnd::Link | TagEnd::Image => {
+                self.styles.pop();
+                if let Some(link) = self.link.take() {
+                    // Only show the URL when the text doesn't already
+                    if !link.text.ends_with(&link.url) {
+                        self.push_span(Span::raw(format!(" ({})", link.url)).fg(self.theme.muted));
+                    }
+                }
+            }
+            TagEnd::FootnoteDefinition => self.flush(),
+            TagEnd::HtmlBlock
+            | TagEnd::DefinitionList
+            | TagEnd::DefinitionListTitle
+            | TagEnd::DefinitionListDefinition
+            | TagEnd::MetadataBlock(_) => self.flush(),
+        }
+    }
+
+    fn style(&self) -> Style {
+        self.styles.last().copied().unwrap_or_default()
+    }
+
+    fn push_span(&mut self, span: Span<'static>) {
+        if let Some(link) = &mut self.link {
+            link.text.push_str(&span.content);
+        }
+        if let Some(cell) = self
+            .table
+            .as_mut()
+            .and_then(|table| table.rows.last_mut())
+            .and_then(|row| row.last_mut())
+        {
+            cell.push(span);
+        } else {
+            self.current.push(span);
+        }
+    }
+
+    /// Ends the current line, if anything has been written to it.
+    fn flush(&mut self) {
+        if !self.current.is_empty() {
+            let spans = std::mem::take(&mut self.current);
+            self.push_line(spans);
+        }
+    }
+
+    /// Adds a line, prefixed with the block quote bars and list indentation it's nested in.
+    fn push_line(&mut self, spans: Vec<Span<'static>>) {
+        let mut line = self.prefix();
+        line.extend(spans);
+        self.lines.push(Line::from(line));
+    }
+
+    fn blank_line(&mut self) {
+        if self.lines.last().is_some_and(|line| line.width() > 0) {
+            self.lines.push(Line::default());
+        }
+    }
+
+    fn prefix(&mut self) -> Vec<Span<'static>> {
+        let mut prefix = vec![];
+        if self.block_quotes > 0 {
+            prefix.push(Span::raw("│ ".repeat(self.block_quotes)).fg(self.theme.muted));
+        }
+        if let Some((list, parents)) = self.lists.split_last() {
+            // Nested lists are indented by the markers of their parents, and the rest of an item by its own marker
+            let indent = " ".repeat(parents.iter().map(|list| list.marker_width).sum());
+            match self.pending_marker.take() {
+                Some(marker) => {
+                    prefix.push(Span::raw(indent));
+                    prefix.push(Span::raw(marker).fg(self.theme.accent));
+                }
+                None => prefix.push(Span::raw(format!(
+                    "{}{}",
+                    indent,
+                    " ".repeat(list.marker_width)
+                ))),
+            }
+        }
+        prefix
+    }
+
+    fn prefix_width(&self) -> usize {
+        self.block_quotes * 2
+            + self
+                .lists
+                .iter()
+                .map(|list| list.marker_width)
+                .sum::<usize>()
+    }
+
+    fn push_code_block(&mut self, code_block: CodeBlock) {
+        let number = self.next_code_block;
+        self.next_code_block += 1;
+        let selected = self.selected_code_block == Some(number);
+
+        let border = match selected {
+            true => Style::default()
+                .fg(self.theme.selected_code_block)
+                .add_modifier(Modifier::BOLD),
+            false => Style::default().fg(self.theme.muted),
+        };
+        let label = if code_block.language.is_empty() {
+            "code"
+        } else {
+            &code_block.language
+        };
+        let mut header = vec![
+            Span::styled("╭─ ", border),
+            // Numbered from 1 for the user, even though we count from 0
+            Span::styled(format!("[{}]", number + 1), border.reversed()),
+            Span::styled(format!(" {} ", label), border),
+        ];
+        if selected {
+            header.push(Span::styled(
+                self.code_block_hint.clone(),
+                Style::default().fg(self.theme.muted),
+            ));
+        }
+        self.push_line(header);
+        let lines = highlight::highlight(&code_block.code, &code_block.language, &self.theme)
+            .unwrap_or_else(|| {
+                // Unknown language, fall back to a single color
+                code_block
+                    .code
+                    .lines()
+                    .map(|line| vec![Span::styled(line.to_string(), code_style(&self.theme))])
+                    .collect()
+            });
+        for line in lines {
+            let mut spans = vec![Span::styled("│ ", border)];
+            spans.extend(line);
+            self.push_line(spans);
+        }
+        self.push_line(vec![Span::styled("╰─", border)]);
+    }
+
+    fn push_table(&mut self, table: Table) {
+        let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        let mut widths = vec![1; columns];
+        for row in &table.rows {
+            for (column, cell) in row.iter().enumerate() {
+                widths[column] = widths[column].max(cell_width(cell));
+            }
+        }
+
+        // Shrink the widest columns until the table fits, cells that don't fit are truncated
+        let available = self
+            .width
+            .saturating_sub(self.prefix_width() + 3 * columns + 1);
+        while widths.iter().sum::<usize>() > available {
+            let (widest, width) = widths
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, width)| **width)
+                .map(|(column, width)| (column, *width))
+                .unwrap();
+            if width <= 3 {
+                break;
+            }
+            widths[widest] -= 1;
+        }
+
+        let border = Style::default().fg(self.theme.muted);
+        let separator = |left: &str, middle: &str, right: &str| {
+            let parts: Vec<String> = widths.iter().map(|width| "─".repeat(width + 2)).collect();
+            vec![Span::styled(
+                format!("{}{}{}", left, parts.join(middle), right),
+                border,
+            )]
+        };
+
+        self.push_line(separator("┌", "┬", "┐"));
+        for (index, row) in table.rows.iter().enumerate() {
+            let mut spans = vec![Span::styled("│", border)];
+            for (column, width) in widths.iter().enumerate() {
+                let cell = row.get(column).cloned().unwrap_or_default();
+                let alignment = table
+                    .alignments
+                    .get(column)
+                    .copied()
+                    .unwrap_or(Alignment::None);
+                spans.push(Span::raw(" "));
+                spans.extend(pad_cell(cell, *width, alignment, index < table.header_rows));
+                spans.push(Span::raw(" "));
+                spans.push(Span::styled("│", border));
+            }
+            self.push_line(spans);
+            if index + 1 == table.header_rows {
+                self.push_line(separator("├", "┼", "┤"));
+            }
+        }
+        self.push_line(separator("└", "┴", "┘"));
+    }
+
+    fn finish(mut self) -> Vec<Line<'static>> {
+        // Whatever is still open when the input ends, is a message still being streamed
+        if let Some(code_block) = self.code_block.take() {
+            self.push_code_block(code_block);
+        }
+        if let Some(table) = self.table.take() {
+            self.push_table(table);
+        }
+        self.flush();
+        while self.lines.last().is_some_and(|line| line.width() == 0) {
+            self.lines.pop();
+        }
+        self.lines
+    }
+}
+
+fn heading_style(level: HeadingLevel, theme: &Theme) -> Style {
+    match level {
+        HeadingLevel::H1 => Style::default()
+            .fg(theme.heading)
+            .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+        HeadingLevel::H2 => Style::default()
+            .fg(theme.heading)
+            .add_modifier(Modifier::BOLD),
+        _ => Style::default().add_modifier(Modifier::BOLD),
+    }
+}
+
+fn code_style(theme: &Theme) -> Style {
+    Style::default().fg(theme.code)
+}
+
+fn cell_width(cell: &[Span]) -> usize {
+    cell.iter().map(Span::width).sum()
+}
+
+/// Pads (or truncates) a table cell to exactly `width` columns.
+fn pad_cell(
+    cell: Vec<Span<'static>>,
+    width: usize,
+    alignment: Alignment,
+    header: bool,
+) -> Vec<Span<'static>> {
+    let mut spans = vec![];
+    let mut used = 0;
+    for span in cell {
+        let style = if header {
+            span.style.add_modifier(Modifier::BOLD)
+        } else {
+            span.style
+        };
+        if used + span.width() <= width {
+            used += span.width();
+            spans.push(Span::styled(span.content, style));
+            continue;
+        }
+        // Truncate, leaving room for an ellipsis
+        let mut content = String::new();
+        for char in span.content.chars() {
+            let char_width = Span::raw(char.to_string()).width();
+            if used + char_width + 1 > width {
+                break;
+            }
+            used += char_width;
+            content.push(char);
+        }
+        spans.push(Span::styled(content, style));
+        spans.push(Span::styled("…", style));
+        used += 1;
+        break;
+    }
+
+    let padding = width.saturating_sub(used);
+    let (left, right) = match alignment {
+        Alignment::Right => (padding, 0),
+        Alignment::Center => (padding / 2, padding - padding / 2),
+        Alignment::Left | Alignment::None => (0, padding),
+    };
+    let mut padded = vec![Span::raw(" ".repeat(left))];
+    padded.extend(spans);
+    padded.push(Span::raw(" ".repeat(right)));
+    padded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered(markdown: &str, selected_code_block: Option<SelectedCodeBlock>) -> Vec<String> {
+        render(markdown, 40, 0, selected_code_block, &Theme::default())
+            .iter()
+            .map(Line::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn renders_blocks() {
+        let markdown = "# Title\n\n- one\n- two\n  1. nested\n\n> quote\n\nSome **bold** `code`\n";
+        assert_eq!(
+            rendered(markdown, None),
+            [
+                "Title",
+                "",
+                "• one",
+                "• two",
+                "  1. nested",
+                "",
+                "│ quote",
+                "",
+                "Some bold code",
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_code_blocks_and_marks_the_selected_one() {
+        let markdown = "```bash\necho hi\n```\n\n```\nplain\n```\n";
+        let selected = SelectedCodeBlock {
+            number: 1,
+            hint: "keys",
+        };
+        assert_eq!(
+            rendered(markdown, Some(selected)),
+            [
+                "╭─ [1] bash ",
+                "│ echo hi",
+                "╰─",
+                "",
+                "╭─ [2] code keys",
+                "│ plain",
+                "╰─",
+            ]
+        );
+        let code_blocks = code_blocks(markdown);
+        assert_eq!(code_blocks.len(), 2);
+        assert_eq!(code_blocks[0].language, "bash");
+        assert_eq!(code_blocks[0].code, "echo hi\n");
+    }
+
+    #[test]
+    fn highlights_code_blocks_in_a_known_language() {
+        let theme = Theme::default();
+        let lines = render("```rust\nlet x = 1;\n```\n", 40, 0, None, &theme);
+        assert_eq!(lines[1].to_string(), "│ let x = 1;");
+        let styled: Vec<(String, ratatui::style::Color)> = lines[1]
+            .spans
+            .iter()
+            .filter_map(|span| Some((span.content.to_string(), span.style.fg?)))
+            .collect();
+        assert!(styled.contains(&("let".to_string(), theme.syntax_keyword)));
+        assert!(styled.contains(&("1".to_string(), theme.syntax_constant)));
+
+        // A language we don't know is all in the color of code
+        let lines = render("```nope\nlet x = 1;\n```\n", 40, 0, None, &theme);
+        assert_eq!(lines[1].spans[1].style.fg, Some(theme.code));
+    }
+
+    #[test]
+    fn renders_an_unfinished_code_block() {
+        assert_eq!(
+            rendered("```sh\necho hi", None),
+            ["╭─ [1] sh ", "│ echo hi", "╰─"]
+        );
+        assert_eq!(code_blocks("```sh\necho hi")[0].code, "echo hi");
+    }
+
+    #[test]
+    fn shows_link_urls() {
+        assert_eq!(
+            rendered("[docs](https://a.b) and <https://c.d>", None),
+            ["docs (https://a.b) and https://c.d"]
+        );
+    }
+
+    #[test]
+    fn shows_link_urls_in_tables() {
+        let markdown = "| a | b |\n|---|---|\n| [docs](https://a.b) | <https://c.d> |\n";
+        assert_eq!(
+            rendered(markdown, None),
+            [
+                "┌────────────────────┬─────────────┐",
+                "│ a                  │ b           │",
+                "├────────────────────┼─────────────┤",
+                "│ docs (https://a.b) │ https://c.d │",
+                "└────────────────────┴─────────────┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_html_in_exports() {
+        assert_eq!(
+            to_html("<script>alert(1)</script>\n"),
+            "&lt;script&gt;alert(1)&lt;/script&gt;\n"
+        );
+    }
+
+    #[test]
+    fn only_keeps_safe_urls_in_exports() {
+        assert_eq!(
+            to_html("[a](javascript:alert(1)) [b](https://a.b) [c](mailto:a@b.c) [d](docs/a.md)"),
+            "<p><a href=\"\">a</a> <a href=\"https://a.b\">b</a> <a href=\"mailto:a@b.c\">c</a> \
+             <a href=\"docs/a.md\">d</a></p>\n"
+        );
+        assert_eq!(
+            to_html("![i](data:text/html,x) [j](<JAVA\tSCRIPT:x>)"),
+            "<p><img src=\"\" alt=\"i\" /> <a href=\"\">j</a></p>\n"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{
+        mpsc::{self, Sender, UnboundedSender},
+        Mutex, Notify, RwLock,
+    },
+    task, time,
+};
+
+use crate::{services::event_service::Event, terminal_utils};
+
+/// Long pauses in a recording, like someone walking away from the keyboard, are cut short when replaying it.
+const MAX_REPLAY_PAUSE: Duration = Duration::from_secs(2);
+
+/// The first line of an asciicast v2 file.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u8,
+    width: u16,
+    height: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<u64>,
+}
+
+/// Records terminal output to an asciicast v2 file, which can be played back with `--replay` or asciinema.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    started: Instant,
+    // The start of a character split between two reads, held back until the rest of it arrives
+    incomplete: Vec<u8>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, width: u16, height: u16) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = Header {
+            version: 2,
+            width,
+            height,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|now| now.as_secs()),
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+            incomplete: vec![],
+        })
+    }
+
+    /// Records output read from the terminal at `read_at`.
+    pub fn record(&mut self, read_at: Instant, output: &[u8]) -> io::Result<()> {
+        // Events hold text, so only write up to the last complete character
+        let text = terminal_utils::decode_output(&mut self.incomplete, output);
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        self.write_event(read_at, "o", &text)
+    }
+
+    /// Records that the terminal was resized to `cols` x `rows` at `resized_at`.
+    pub fn resize(&mut self, resized_at: Instant, cols: u16, rows: u16) -> io::Result<()> {
+        self.write_event(resized_at, "r", &format!("{}x{}", cols, rows))
+    }
+
+    fn write_event(&mut self, at: Instant, kind: &str, data: &str) -> io::Result<()> {
+        let elapsed = at.saturating_duration_since(self.started).as_secs_f64();
+        serde_json::to_writer(&mut self.writer, &(elapsed, kind, data))?;
+        self.writer.write_all(b"\n")?;
+        // Flushed right away, so the recording is complete up to a crash
+        self.writer.flush()
+    }
+}
+
+/// What happened at a point in a recording.
+#[derive(Debug, PartialEq)]
+enum Frame {
+    Output(String),
+    // columns, rows
+    Resize(u16, u16),
+}
+
+/// A recording loaded from an asciicast v2 file.
+pub struct Recording {
+    pub width: u16,
+    pub height: u16,
+    // The output and resizes, and how far into the recording they happened
+    frames: Vec<(Duration, Frame)>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: Header = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "empty recording",
+                ))
+            }
+        };
+        if header.version != 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported asciicast version {}", header.version),
+            ));
+        }
+
+        let mut frames = vec![];
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (time, kind, data): (f64, String, String) = serde_json::from_str(&line)?;
+            // Only output and resizes matter for the screen; input and markers are skipped
+            let frame = match kind.as_str() {
+                "o" => Frame::Output(data),
+                "r" => match data
+                    .split_once('x')
+                    .and_then(|(cols, rows)| Some((cols.parse().ok()?, rows.parse().ok()?)))
+                {
+                    Some((cols, rows)) => Frame::Resize(cols, rows),
+                    None => {
+                        tracing::warn!("Skipping a resize to {:?} in the recording", data);
+                        continue;
+                    }
+                },
+                _ => continue,
+            };
+            // A time that is negative or too large to be one can't be placed, so the event is left out
+            match Duration::try_from_secs_f64(time) {
+                Ok(at) => frames.push((at, frame)),
+                Err(err) => {
+                    tracing::warn!("Skipping an event at {} in the recording: {}", time, err)
+                }
+            }
+        }
+        Ok(Self {
+            width: header.width,
+            height: header.height,
+            frames,
+        })
+    }
+}
+
+/// Plays a recording back in place of a live shell.
+///
+/// Works like `terminal_utils::new`: the output goes through the parser and into the terminal context, and
+/// `terminal_updated` is notified. Anything sent to the returned sender is ignored, as there is nothing to type into.
+pub fn replay(
+    recording: Recording,
+    terminal_context: Arc<Mutex<String>>,
+    event_sender: UnboundedSender<Event>,
+    terminal_updated: Arc<Notify>,
+) -> (Arc<RwLock<vt100::Parser>>, Sender<Bytes>) {
+    let parser = Arc::new(RwLock::new(vt100::Parser::new(
+        recording.height,
+        recording.width,
+        0,
+    )));
+
+    {
+        let parser = parser.clone();
+        task::spawn(async move {
+            let mut previous = Duration::ZERO;
+            for (at, frame) in recording.frames {
+                time::sleep(at.saturating_sub(previous).min(MAX_REPLAY_PAUSE)).await;
+                previous = at;
+
+                let output = match frame {
+                    Frame::Output(output) => output,
+                    Frame::Resize(cols, rows) => {
+                        // The output after a resize was drawn for the new size
+                        parser.write().await.set_size(rows, cols);
+                        terminal_updated.notify_one();
+                        continue;
+                    }
+                };
+                parser.write().await.process(output.as_bytes());
+                terminal_utils::append_terminal_context(
+                    &mut *terminal_context.lock().await,
+                    &output,
+                );
+                terminal_updated.notify_one();
+            }
+            tracing::info!("Finished replaying the recording");
+            let _ = event_sender.send(Event::ReplayFinished);
+        });
+    }
+
+    let (terminal_sender, mut terminal_receiver) = mpsc::channel::<Bytes>(32);
+    task::spawn(async move { while terminal_receiver.recv().await.is_some() {} });
+
+    (parser, terminal_sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_what_was_recorded() {
+        let path = std::env::temp_dir().join(format!(
+            "terminal-ai-ops-recording-{}.cast",
+            std::process::id()
+        ));
+        let mut recorder = Recorder::create(&path, 80, 24).unwrap();
+        let started = recorder.started;
+        // "é" split between two reads is written once it is complete
+        recorder.record(started, b"$ caf\xc3").unwrap();
+        recorder
+            .record(started + Duration::from_millis(500), b"\xa9\r\n")
+            .unwrap();
+        recorder
+            .resize(started + Duration::from_secs(1), 120, 40)
+            .unwrap();
+        drop(recorder);
+
+        // A timestamp that can't be a duration is skipped instead of stopping the replay
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        writeln!(file, "[-1.0, \"o\", \"lost\"]").unwrap();
+        writeln!(file, "[2.5, \"i\", \"ls\"]").unwrap();
+        drop(file);
+
+        let recording = Recording::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((recording.width, recording.height), (80, 24));
+        assert_eq!(
+            recording.frames,
+            [
+                (Duration::ZERO, Frame::Output("$ caf".to_string())),
+                (
+                    Duration::from_millis(500),
+                    Frame::Output("é\r\n".to_string())
+                ),
+                (Duration::from_secs(1), Frame::Resize(120, 40)),
+            ]
+        );
+    }
+}
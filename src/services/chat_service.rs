@@ -1,46 +1,281 @@
-use crate::{config, services::event_service::Event};
+use crate::{
+    app_state::{Message, MessageSender},
+    config,
+    debug_log::DebugLog,
+    knowledge::{self, Excerpt, Knowledge},
+    services::event_service::Event,
+    tracing::CONTENT,
+    usage::Usage,
+};
 
 use async_openai::{
-    config::AzureConfig,
+    config::{AzureConfig, Config, OpenAIConfig},
     error::OpenAIError,
     types::{
         ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
         ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+        ChatCompletionResponseStream, ChatCompletionStreamOptions, CreateChatCompletionRequest,
         CreateChatCompletionRequestArgs,
     },
     Client,
 };
+use clap::ValueEnum;
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{ops::RangeInclusive, sync::Arc};
 use tokio::sync::mpsc;
-use tracing::info;
+
+/// The model answering the questions, unless another one is configured.
+pub const DEFAULT_MODEL: &str = "gpt-4o";
+
+/// The model that answers without one being asked for. On Azure, that is whatever the deployment in the config
+/// runs, which is only known by the name of the deployment.
+pub fn default_model(provider: Provider) -> String {
+    match provider {
+        Provider::Azure => {
+            // The config keeps the deployment to itself, but puts it in the URLs
+            let url = config::get_config().url("");
+            match url.rsplit_once("/openai/deployments/") {
+                Some((_, deployment)) if !deployment.is_empty() => deployment.to_string(),
+                _ => DEFAULT_MODEL.to_string(),
+            }
+        }
+        Provider::OpenAi => DEFAULT_MODEL.to_string(),
+    }
+}
+
+/// Roughly how many tokens `text` is, at about four bytes per token for English text and code.
+/// Good enough to show the user how big a conversation is getting, without a tokenizer.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// The temperatures the API takes.
+pub const TEMPERATURES: RangeInclusive<f32> = 0.0..=2.0;
+
+/// Parses a temperature, which the API only takes from 0 to 2.
+pub fn parse_temperature(temperature: &str) -> Result<f32, String> {
+    match temperature.parse::<f32>() {
+        Ok(temperature) if TEMPERATURES.contains(&temperature) => Ok(temperature),
+        _ => Err(format!(
+            "The temperature should be a number from 0 to 2, not {}",
+            temperature
+        )),
+    }
+}
 
 pub enum Action {
-    AiRequest(String),
-    Clear,
+    // Ask the AI to answer the conversation, which ends with a question from the user
+    AiRequest {
+        role: Role,
+        messages: Vec<Message>,
+        parameters: Parameters,
+    },
+    // Ask the provider which models there are, for the model picker
+    ListModels,
+    // Index the docs that changed since they were last indexed
+    IndexKnowledge,
+}
+
+/// How the AI is asked, besides what it is asked. The UI owns these, as they can be changed while it runs.
+#[derive(Clone, Debug)]
+pub struct Parameters {
+    pub model: String,
+    // Without one, the API's default is used
+    pub temperature: Option<f32>,
+    // Without a limit, answers can be as long as the model allows
+    pub max_tokens: Option<u32>,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            model: DEFAULT_MODEL.to_string(),
+            temperature: None,
+            max_tokens: None,
+        }
+    }
+}
+
+/// Who the AI acts as, which decides the system prompt it gets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+pub enum Role {
+    #[default]
+    #[serde(alias = "general")]
+    General,
+    #[value(name = "network")]
+    #[serde(alias = "network")]
     NetworkEngineer,
+    #[value(name = "linux")]
+    #[serde(alias = "linux")]
     LinuxEngineer,
 }
 
+impl Role {
+    /// The name shown in the chat. The general role doesn't have one.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::General => "",
+            Role::NetworkEngineer => "Network Engineer",
+            Role::LinuxEngineer => "Linux Engineer",
+        }
+    }
+
+    /// The name of the role outside of the chat, where the general role needs one too.
+    pub fn title(&self) -> &'static str {
+        match self {
+            Role::General => "General",
+            role => role.name(),
+        }
+    }
+
+    /// What the AI says when the user switches to this role.
+    pub fn greeting(&self) -> Option<&'static str> {
+        match self {
+            Role::General => None,
+            Role::NetworkEngineer => Some("Hi! I'm your personal Network assistant. I'm can see your terminal, so feel free to ask questions!"),
+            Role::LinuxEngineer => Some("Hi! I'm your personal Linux assistant. I'm can see your terminal, so feel free to ask questions!"),
+        }
+    }
+
+    fn system_prompt(&self) -> &'static str {
+        match self {
+            Role::General => "You are a general purpose programmer. The user that will chat with you appreciates short answers when possible. If you want to share commands, there is not reason to explain what all the commands does in detail, a short sentence maximum. The user will most likely send you questions about his terminal output. When answering, be consice!!
+
+                If you're asked what IP a demo device has, respond with 10.82.189.130.",
+            Role::NetworkEngineer => "You are an advanced network assistant. Your role is to assist users in resolving their network challenges, improving their config, and understanding networking concepts. Follow these guidelines: - Analyze the config provided by the user for errors, inefficiencies, or areas that require explanation. - If in doubt of which operating system, ask (e.g. Cisco IOS XE or IOS XR) - Offer solutions, optimizations, or explanations that are directly relevant to the user's request. -Ensure that your responses are in the same operating system language that the user is using. - Provide clear, step-by-step guidance when explaining solutions or concepts. - Encourage best practices in networking, such as clean code principles, commenting, and efficient algorithms. - Structure your response and questions using markdown headers to organize different aspects of coding assistance. For example: ### <your text> <feedback> - Always respond in the same language as the user writes to you in (If the user writes in Norwegian, respond in Norwegian). Remember to adapt your guidance to the user's level of expertise, from beginner to advanced.",
+            Role::LinuxEngineer => "You are a Linux Security Expert. The user that will chat with you appreciates short answers when possible. If you want to share commands, there is not reason to explain what all the commands does in detail, a short sentence maximum. The user will most likely send you questions about his terminal output. When answering, be consice!!",
+        }
+    }
+}
+
+/// Which API the AI is reached through.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    // Azure OpenAI, set up in the `config` module
+    #[default]
+    Azure,
+    // OpenAI itself, with the key in the OPENAI_API_KEY environment variable
+    #[value(name = "openai")]
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+// The clients for each provider are different types, as their configs are
+enum ChatClient {
+    Azure(Client<AzureConfig>),
+    OpenAi(Client<OpenAIConfig>),
+}
+
+impl ChatClient {
+    /// On Azure the model is picked by the deployment, so a `model` there replaces the deployment from the config.
+    fn new(provider: Provider, model: Option<&str>) -> Self {
+        match provider {
+            Provider::Azure => {
+                let mut config = config::get_config();
+                if let Some(model) = model {
+                    config = config.with_deployment_id(model);
+                }
+                ChatClient::Azure(Client::with_config(config))
+            }
+            Provider::OpenAi => ChatClient::OpenAi(Client::with_config(OpenAIConfig::new())),
+        }
+    }
+
+    async fn create_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatCompletionResponseStream, OpenAIError> {
+        match self {
+            ChatClient::Azure(client) => client.chat().create_stream(request).await,
+            ChatClient::OpenAi(client) => client.chat().create_stream(request).await,
+        }
+    }
+
+    /// The chat models the provider has, by name.
+    async fn list_models(&self) -> Result<Vec<String>, String> {
+        match self {
+            ChatClient::Azure(_) => Err(
+                "Azure can't list its deployments, add them to models in the config instead"
+                    .to_string(),
+            ),
+            ChatClient::OpenAi(client) => {
+                let response = client
+                    .models()
+                    .list()
+                    .await
+                    .map_err(|err| err.to_string())?;
+                // The list includes models for embeddings, images and audio, which can't chat
+                let mut models: Vec<String> = response
+                    .data
+                    .into_iter()
+                    .map(|model| model.id)
+                    .filter(|id| {
+                        ["gpt-", "o1", "o3", "o4", "chatgpt-"]
+                            .iter()
+                            .any(|prefix| id.starts_with(prefix))
+                    })
+                    .collect();
+                models.sort();
+                Ok(models)
+            }
+        }
+    }
+}
+
+/// Talks to the AI. The conversation itself is owned by the UI, which sends it along with every request,
+/// so the service doesn't keep any history of its own.
 pub struct ChatService {
-    client: Client<AzureConfig>,
-    previous_messages: Vec<ChatCompletionRequestMessage>,
+    provider: Provider,
+    client: ChatClient,
+    // The model the client was made for
+    model: String,
+    // Where the raw request and response of the last call go, for the debug pane
+    debug_log: Arc<DebugLog>,
+    // Whether to ask the API for the token usage, rather than estimate it
+    stream_usage: bool,
+    // The docs to add excerpts of to the prompt, if any are configured
+    knowledge: Option<Knowledge>,
+}
+
+impl Default for ChatService {
+    fn default() -> Self {
+        Self::new(Provider::default(), None, Arc::default())
+    }
 }
 
 impl ChatService {
-    pub fn new() -> Self {
-        let client = Client::with_config(config::get_config());
-        let system_prompt = ChatCompletionRequestSystemMessageArgs::default()
-                .content("You are a general purpose programmer. The user that will chat with you appreciates short answers when possible. If you want to share commands, there is not reason to explain what all the commands does in detail, a short sentence maximum. The user will most likely send you questions about his terminal output. When answering, be consice!!
-
-                If you're asked what IP a demo device has, respond with 10.82.189.130.")
-                .build()
-                .unwrap();
+    /// Without a `model`, the default model is used, or on Azure the deployment from the config.
+    pub fn new(provider: Provider, model: Option<String>, debug_log: Arc<DebugLog>) -> Self {
+        let client = ChatClient::new(provider, model.as_deref());
+        let model = model.unwrap_or_else(|| default_model(provider));
         Self {
+            provider,
             client,
-            previous_messages: vec![system_prompt.into()],
+            model,
+            debug_log,
+            stream_usage: true,
+            knowledge: None,
         }
     }
 
+    /// Without asking the API for the token usage, which older Azure API versions reject, the usage is estimated.
+    pub fn with_stream_usage(mut self, stream_usage: bool) -> Self {
+        self.stream_usage = stream_usage;
+        self
+    }
+
+    pub fn has_knowledge(&self) -> bool {
+        self.knowledge.is_some()
+    }
+
+    /// With docs, the excerpts that match the question best go along with it, for the AI to cite.
+    pub fn with_knowledge(mut self, knowledge: Option<Knowledge>) -> Self {
+        self.knowledge = knowledge;
+        self
+    }
+
     pub async fn start(
         &mut self,
         event_sender: mpsc::UnboundedSender<Event>,
@@ -51,119 +286,245 @@ impl ChatService {
         // Inspiration: https://github.com/dustinblackman/oatmeal/blob/a6148b2474778698f7b261aa549dcbda439e2060/src/domain/services/actions.rs#L239
         while let Some(action) = action_receiver.recv().await {
             match action {
-                Action::NetworkEngineer => {
-                    // Clear all messages except first, which is the system message
-                    self.previous_messages.drain(1..);
-                    tracing::info!("Changing prompt to network engineer");
-                    if let Some(first) = self.previous_messages.first_mut() {
-                        *first = ChatCompletionRequestSystemMessageArgs::default()
-                            .content("You are an advanced network assistant. Your role is to assist users in resolving their network challenges, improving their config, and understanding networking concepts. Follow these guidelines: - Analyze the config provided by the user for errors, inefficiencies, or areas that require explanation. - If in doubt of which operating system, ask (e.g. Cisco IOS XE or IOS XR) - Offer solutions, optimizations, or explanations that are directly relevant to the user's request. -Ensure that your responses are in the same operating system language that the user is using. - Provide clear, step-by-step guidance when explaining solutions or concepts. - Encourage best practices in networking, such as clean code principles, commenting, and efficient algorithms. - Structure your response and questions using markdown headers to organize different aspects of coding assistance. For example: ### <your text> <feedback> - Always respond in the same language as the user writes to you in (If the user writes in Norwegian, respond in Norwegian). Remember to adapt your guidance to the user's level of expertise, from beginner to advanced.")
-                            .build()
-                            .unwrap().into();
+                Action::ListModels => {
+                    let models = self.client.list_models().await;
+                    if let Err(err) = &models {
+                        tracing::warn!("Failed to list the models: {}", err);
                     }
-                    tracing::info!(
-                        "First message: {:?}",
-                        self.previous_messages.first().unwrap()
-                    );
-                    event_sender
-                        .send(Event::AIStreamResponse("Hi! I'm your personal Network assistant. I'm can see your terminal, so feel free to ask questions!".into()))
-                        .unwrap();
+                    let _ = event_sender.send(Event::Models(models));
                 }
-                Action::LinuxEngineer => {
-                    // Clear all messages except first, which is the system message
-                    self.previous_messages.drain(1..);
-                    tracing::info!("Changing prompt to linux engineer");
-                    if let Some(first) = self.previous_messages.first_mut() {
-                        *first = ChatCompletionRequestSystemMessageArgs::default()
-                            .content("You are a Linux Security Expert. The user that will chat with you appreciates short answers when possible. If you want to share commands, there is not reason to explain what all the commands does in detail, a short sentence maximum. The user will most likely send you questions about his terminal output. When answering, be consice!!")
-                            .build()
-                            .unwrap().into();
+                Action::IndexKnowledge => {
+                    let result = match &mut self.knowledge {
+                        Some(knowledge) => knowledge.update().await,
+                        None => Err(
+                            "There are no docs to index, add their paths to [knowledge] in the config"
+                                .to_string(),
+                        ),
+                    };
+                    if let Err(err) = &result {
+                        tracing::warn!("Failed to index the docs: {}", err);
                     }
-                    tracing::info!("First message: {:?}", self.previous_messages);
-                    event_sender
-                        .send(Event::AIStreamResponse("Hi! I'm your personal Linux assistant. I'm can see your terminal, so feel free to ask questions!".into()))
-                        .unwrap();
+                    let _ = event_sender.send(Event::KnowledgeIndexed(result));
                 }
-                Action::Clear => {
-                    // Clear all messages except first, which is the system message
-                    self.previous_messages.drain(1..);
-                }
-                Action::AiRequest(message) => {
-                    // Process the AI request...
-                    let new_message = ChatCompletionRequestUserMessageArgs::default()
-                        .content(message)
-                        .build()
-                        .unwrap();
-
-                    // Push the message into the history
-                    self.previous_messages.push(new_message.into());
-
-                    let request = CreateChatCompletionRequestArgs::default()
-                        .model("gpt-4o")
-                        .max_tokens(512u16)
-                        .messages(self.previous_messages.clone())
-                        .build()
-                        .unwrap();
-
-                    let mut stream = self.client.chat().create_stream(request).await.unwrap();
-
-                    let mut assistant_response = String::new();
-                    while let Some(result) = stream.next().await {
-                        match result {
-                            Ok(response) => {
-                                for chat_choice in response.choices.iter() {
-                                    if let Some(ref content) = chat_choice.delta.content {
-                                        assistant_response.push_str(content);
-                                        info!("{}", content);
-                                        event_sender
-                                            .send(Event::AIStreamResponse(content.into()))
-                                            .unwrap();
-                                    }
-
-                                    // send event to the UI to indicate if the AI is reasoning or not
-                                    event_sender
-                                        .send(Event::AIReasoning(
-                                            chat_choice.finish_reason.is_some(),
-                                        ))
-                                        .unwrap();
-                                }
-                                tracing::info!("{:?}", response)
+                Action::AiRequest {
+                    role,
+                    messages,
+                    parameters,
+                } => {
+                    let excerpts = self.search_knowledge(&messages).await;
+                    let mut request_messages: Vec<ChatCompletionRequestMessage> =
+                        vec![ChatCompletionRequestSystemMessageArgs::default()
+                            .content(role.system_prompt())
+                            .build()
+                            .unwrap()
+                            .into()];
+                    if !excerpts.is_empty() {
+                        request_messages.push(
+                            ChatCompletionRequestSystemMessageArgs::default()
+                                .content(knowledge::prompt(&excerpts))
+                                .build()
+                                .unwrap()
+                                .into(),
+                        );
+                    }
+                    for message in messages {
+                        request_messages.push(match message.sender {
+                            MessageSender::User => ChatCompletionRequestUserMessageArgs::default()
+                                .content(message.prompt())
+                                .build()
+                                .unwrap()
+                                .into(),
+                            MessageSender::Assistant => {
+                                ChatCompletionRequestAssistantMessageArgs::default()
+                                    .content(message.message)
+                                    .build()
+                                    .unwrap()
+                                    .into()
                             }
-                            Err(err) => match err {
-                                OpenAIError::Reqwest(reqerror) => {
-                                    tracing::warn!("reqwest error {:?}", reqerror);
-                                }
-                                OpenAIError::StreamError(reqerror) => {
-                                    tracing::warn!("stream error {:?}", reqerror);
-                                }
-                                OpenAIError::JSONDeserialize(reqerror) => {
-                                    tracing::warn!("JSON des error {:?}", reqerror);
-                                }
-                                OpenAIError::FileReadError(reqerror) => {
-                                    tracing::warn!("File readerror {:?}", reqerror);
-                                }
-                                OpenAIError::ApiError(reqerror) => {
-                                    tracing::warn!("API error readerror {:?}", reqerror);
-                                }
-                                OpenAIError::InvalidArgument(reqerror) => {
-                                    tracing::warn!("Invalid arg error readerror {:?}", reqerror);
-                                }
-                                _ => {
-                                    tracing::warn!("{:?}", err);
-                                }
-                            },
-                        }
+                        });
                     }
-                    if !assistant_response.is_empty() {
-                        tracing::info!(assistant_response);
-                        let ai_response = ChatCompletionRequestAssistantMessageArgs::default()
-                            .content(assistant_response)
-                            .build()
+                    let answered = self
+                        .stream_response(&event_sender, request_messages, parameters)
+                        .await;
+                    if answered && !excerpts.is_empty() {
+                        let sources = excerpts.into_iter().map(|excerpt| excerpt.source);
+                        let _ = event_sender.send(Event::AISources(sources.collect()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// The excerpts of the docs that match the last question, if there are docs. Without them the AI can still
+    /// answer, so failing to search only gets logged.
+    async fn search_knowledge(&self, messages: &[Message]) -> Vec<Excerpt> {
+        let (Some(knowledge), Some(question)) = (
+            &self.knowledge,
+            messages
+                .iter()
+                .rev()
+                .find(|message| message.sender == MessageSender::User),
+        ) else {
+            return vec![];
+        };
+        match knowledge.search(&knowledge::query(question)).await {
+            Ok(excerpts) => {
+                tracing::info!("Found {} excerpts of the docs", excerpts.len());
+                excerpts
+            }
+            Err(err) => {
+                tracing::warn!("Failed to search the docs: {}", err);
+                vec![]
+            }
+        }
+    }
+
+    /// Sends the conversation to the AI, and streams the answer to the UI. Returns whether there was an answer.
+    async fn stream_response(
+        &mut self,
+        event_sender: &mpsc::UnboundedSender<Event>,
+        messages: Vec<ChatCompletionRequestMessage>,
+        parameters: Parameters,
+    ) -> bool {
+        if parameters.model != self.model {
+            self.client = ChatClient::new(self.provider, Some(&parameters.model));
+            self.model = parameters.model;
+        }
+        let mut request = CreateChatCompletionRequestArgs::default()
+            .model(&self.model)
+            .messages(messages)
+            .build()
+            .unwrap();
+        request.temperature = parameters.temperature;
+        request.max_tokens = parameters.max_tokens;
+        if self.stream_usage {
+            request.stream_options = Some(ChatCompletionStreamOptions {
+                include_usage: true,
+            });
+        }
+        // The messages as JSON are a bit more than their content, like the few tokens each message costs on top
+        let estimated_prompt_tokens =
+            estimate_tokens(&serde_json::to_string(&request.messages).unwrap_or_default());
+
+        tracing::info!(
+            "Sending {} messages to {}",
+            request.messages.len(),
+            self.model
+        );
+        tracing::debug!(target: CONTENT, "Request: {:?}", request.messages);
+        self.debug_log
+            .start_exchange(serde_json::to_string_pretty(&request).unwrap_or_default());
+        let mut stream = match self.client.create_stream(request).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                tracing::warn!("Failed to send the request: {:?}", err);
+                self.debug_log.push_response(format!("Error: {}", err));
+                // There won't be an answer, so let the user carry on
+                let _ = event_sender.send(Event::AIError(err.to_string()));
+                let _ = event_sender.send(Event::AIReasoning(true));
+                return false;
+            }
+        };
+
+        let mut assistant_response = String::new();
+        let mut usage = None;
+        let mut model = self.model.clone();
+        let mut finished = false;
+        let mut error = None;
+        while let Some(result) = stream.next().await {
+            match result {
+                Ok(response) => {
+                    self.debug_log
+                        .push_response(serde_json::to_string(&response).unwrap_or_default());
+                    // Only the last chunk has the usage, and on Azure the model is the one behind the deployment
+                    if let Some(reported) = &response.usage {
+                        usage = Some((reported.prompt_tokens, reported.completion_tokens));
+                    }
+                    if !response.model.is_empty() {
+                        model.clone_from(&response.model);
+                    }
+                    for chat_choice in response.choices.iter() {
+                        if let Some(ref content) = chat_choice.delta.content {
+                            assistant_response.push_str(content);
+                            event_sender
+                                .send(Event::AIStreamResponse(content.into()))
+                                .unwrap();
+                        }
+
+                        if let Some(finish_reason) = chat_choice.finish_reason {
+                            tracing::info!("The answer finished with {:?}", finish_reason);
+                            finished = true;
+                            let _ = event_sender.send(Event::AIFinished(finish_reason));
+                        }
+                        // send event to the UI to indicate if the AI is reasoning or not
+                        event_sender
+                            .send(Event::AIReasoning(chat_choice.finish_reason.is_some()))
                             .unwrap();
-                        self.previous_messages.push(ai_response.into());
                     }
+                    tracing::trace!(target: CONTENT, "{:?}", response)
+                }
+                Err(err) => {
+                    self.debug_log.push_response(format!("Error: {}", err));
+                    error = Some(err.to_string());
+                    match err {
+                        OpenAIError::Reqwest(reqerror) => {
+                            tracing::warn!("reqwest error {:?}", reqerror);
+                        }
+                        OpenAIError::StreamError(reqerror) => {
+                            tracing::warn!("stream error {:?}", reqerror);
+                        }
+                        OpenAIError::JSONDeserialize(reqerror) => {
+                            tracing::warn!("JSON des error {:?}", reqerror);
+                        }
+                        OpenAIError::FileReadError(reqerror) => {
+                            tracing::warn!("File readerror {:?}", reqerror);
+                        }
+                        OpenAIError::ApiError(reqerror) => {
+                            tracing::warn!("API error readerror {:?}", reqerror);
+                        }
+                        OpenAIError::InvalidArgument(reqerror) => {
+                            tracing::warn!("Invalid arg error readerror {:?}", reqerror);
+                        }
+                        _ => {
+                            tracing::warn!("{:?}", err);
+                        }
+                    }
+                    break;
                 }
             }
         }
+        // Without a finish reason, the UI would wait for the rest of the answer forever
+        if !finished {
+            let _ = event_sender.send(Event::AIError(
+                error.unwrap_or_else(|| "The answer ended before it was complete".to_string()),
+            ));
+            let _ = event_sender.send(Event::AIReasoning(true));
+        }
+        tracing::info!("Got an answer of {} bytes", assistant_response.len());
+        if !assistant_response.is_empty() {
+            tracing::debug!(target: CONTENT, "Answer: {}", assistant_response);
+        }
+
+        let usage = match usage {
+            Some((prompt_tokens, completion_tokens)) => Usage {
+                model,
+                prompt_tokens,
+                completion_tokens,
+                estimated: false,
+                cost: None,
+            },
+            // Nothing was said, so nothing was paid for
+            None if assistant_response.is_empty() => return false,
+            None => Usage {
+                model,
+                prompt_tokens: estimated_prompt_tokens as u32,
+                completion_tokens: estimate_tokens(&assistant_response) as u32,
+                estimated: true,
+                cost: None,
+            },
+        };
+        tracing::info!("Used {} tokens of {}", usage.total_tokens(), usage.model);
+        let _ = event_sender.send(Event::AIUsage(usage));
+        !assistant_response.is_empty()
     }
 }
@@ -1,48 +1,97 @@
 use std::io;
 
-use crossterm::event::{
-    Event as CrosstermEvent, EventStream, KeyCode, KeyEvent, KeyModifiers, MouseEventKind,
-};
+use async_openai::types::FinishReason;
+use crossterm::event::{Event as CrosstermEvent, EventStream, KeyEvent};
 use futures::StreamExt;
-use tokio::{sync::mpsc, time};
+use portable_pty::ExitStatus;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+
+use crate::{error_watch::Failure, tracing::CONTENT, usage::Usage};
 
 #[derive(Debug)]
 pub enum Event {
+    // Only sent while something is animating, such as the throbber
     Tick,
+    // New output from the pseudo terminal has been parsed
+    TerminalUpdated,
     Key(KeyEvent),
     ChangeMode,
     Quit,
     AIStreamResponse(String),
     AIReasoning(bool),
+    // Why the answer ended, sent just before it has
+    AIFinished(FinishReason),
+    // The tokens the answer took, sent once it is complete
+    AIUsage(Usage),
+    // The docs the excerpts in the prompt came from, sent once the answer is complete
+    AISources(Vec<String>),
+    // Why the answer failed, or ended before it was complete
+    AIError(String),
+    // How indexing the docs went
+    KnowledgeIndexed(Result<String, String>),
+    // The models the provider has, or why it couldn't say
+    Models(Result<Vec<String>, String>),
     // columns, rows
     Resize(u16, u16),
     ScrollUp,
     ScrollDown,
+    // The shell running in the pseudo terminal has exited
+    ChildExited(ExitStatus),
+    // Reading from or writing to the pseudo terminal failed
+    TerminalError(String),
+    // A command in the terminal failed, which the user can ask the AI about
+    CommandFailed(Failure),
+    // A command in the terminal succeeded, so the last failure doesn't need explaining anymore
+    CommandSucceeded,
+    // All of the recording being replayed has been shown
+    ReplayFinished,
 }
 
 pub struct EventService {
     crossterm_events: EventStream,
+    // Whether the input from the terminal has ended, after which the stream would only return None, over and over
+    input_ended: bool,
     event_receiver: mpsc::UnboundedReceiver<Event>,
+    terminal_updated: Arc<Notify>,
 }
 
 impl EventService {
-    pub fn new(event_receiver: mpsc::UnboundedReceiver<Event>) -> Self {
+    pub fn new(
+        event_receiver: mpsc::UnboundedReceiver<Event>,
+        terminal_updated: Arc<Notify>,
+    ) -> Self {
         Self {
             crossterm_events: EventStream::new(),
+            input_ended: false,
             event_receiver,
+            terminal_updated,
         }
     }
 
+    /// The notifier the pseudo terminal uses to tell us it has new output.
+    /// Notifications are coalesced, so a flood of output results in a single `Event::TerminalUpdated`.
+    pub fn terminal_updated(&self) -> Arc<Notify> {
+        self.terminal_updated.clone()
+    }
+
     pub async fn next(&mut self) -> io::Result<Event> {
         loop {
             let received_event = tokio::select! {
                 event_from_receiver = self.event_receiver.recv() => event_from_receiver,
-                crossterm_event = self.crossterm_events.next() => match crossterm_event {
+                crossterm_event = self.crossterm_events.next(), if !self.input_ended => match crossterm_event {
                     Some(Ok(input)) => self.handle_crossterm_event(input),
-                    Some(Err(err)) => {println!("{}", err); None},
-                    None => {println!("none event"); None},
+                    Some(Err(err)) => {
+                        tracing::warn!("Failed to read the terminal input: {}", err);
+                        None
+                    }
+                    None => {
+                        tracing::debug!("The terminal input has ended");
+                        self.input_ended = true;
+                        None
+                    }
                 },
-                _ = time::sleep(time::Duration::from_millis(10)) => Some(Event::Tick),
+                _ = self.terminal_updated.notified() => Some(Event::TerminalUpdated),
             };
 
             if let Some(event) = received_event {
@@ -63,29 +112,10 @@ impl EventService {
                 }
             }
             CrosstermEvent::Key(key) => {
+                // Which keys are commands depends on the keymap and the mode, so that is up to the UI
                 if key.kind == crossterm::event::KeyEventKind::Press {
-                    match key.code {
-                        KeyCode::Char('q') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                            tracing::info!("Quitting app : {:?}", key);
-                            Some(Event::Quit)
-                        }
-                        KeyCode::Char('b') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                            tracing::info!("Changing mode : {:?}", key);
-                            Some(Event::ChangeMode)
-                        }
-                        KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                            tracing::info!("Scroll up : {:?}", key);
-                            Some(Event::ScrollUp)
-                        }
-                        KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                            tracing::info!("Scroll down : {:?}", key);
-                            Some(Event::ScrollDown)
-                        }
-                        _ => {
-                            tracing::info!("key event {:?}", key);
-                            Some(Event::Key(key))
-                        }
-                    }
+                    tracing::trace!(target: CONTENT, "key event {:?}", key);
+                    Some(Event::Key(key))
                 } else {
                     None
                 }
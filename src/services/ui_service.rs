@@ -1,79 +1,138 @@
-use crate::app_state::{self, AppState, Message, MessageSender, Mode};
+use crate::{
+    app_state::{
+        attachment_chips, AppState, Chat, DebugPane, DebugView, Message, MessageSender, Mode,
+        ModelPicker,
+    },
+    attachment::{self, Attachment, MAX_ATTACHMENTS},
+    clipboard, error_watch,
+    export::{self, ExportOptions},
+    keymap::{self, Command, KeyAction},
+    markdown::SelectedCodeBlock,
+    session,
+    slash_commands::SlashCommand,
+    terminal_utils::{self, OutputOptions},
+    tracing::CONTENT,
+    usage::{self, Total},
+};
 
 use super::{
-    chat_service::Action,
+    chat_service::{parse_temperature, Action, Role},
     event_service::{Event, EventService},
 };
+use async_openai::types::FinishReason;
 use bytes::Bytes;
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture, KeyCode, KeyModifiers},
+    event::{DisableMouseCapture, EnableMouseCapture, KeyCode, KeyEvent, KeyModifiers},
     terminal::{self as crossterm_terminal, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Alignment, Constraint, Direction, Layout},
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Rect},
     style::{Modifier, Style, Stylize},
-    text::Line,
-    widgets::{Block, Borders, Paragraph, Wrap},
+    text::{Line, Span},
+    widgets::{
+        Block, Borders, Clear, List, ListItem, ListState, Paragraph, Scrollbar,
+        ScrollbarOrientation, ScrollbarState, Wrap,
+    },
     Frame, Terminal,
 };
 use std::{
     io::{self, Stdout},
     panic,
+    path::Path,
     sync::Arc,
 };
-use tokio::sync::{
-    mpsc::{Sender, UnboundedSender},
-    RwLock,
+use tokio::{
+    sync::{
+        mpsc::{Sender, UnboundedSender},
+        RwLock,
+    },
+    time::{self, Duration, Instant},
 };
-use tracing::info;
+use tracing::Level;
 use tui_term::widget::PseudoTerminal;
 use vt100::Screen;
 
+/// What the AI is asked to continue a cut off answer with.
+const CONTINUE_PROMPT: &str = "Your answer was cut off. Continue exactly where it stopped, without repeating anything or commenting on it.";
+/// The shortest time between two frames, capping rendering at ~60 FPS.
+const FRAME_DURATION: Duration = Duration::from_millis(16);
+/// How many lines the mouse wheel and the scroll keys scroll the chat.
+const SCROLL_LINES: u16 = 3;
+/// How often the throbber moves while we wait for the AI.
+const THROBBER_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the debug pane is redrawn while it is open, to show new log records.
+const DEBUG_PANE_INTERVAL: Duration = Duration::from_millis(250);
+/// The log levels the debug pane can filter on, from the least to the most shown.
+const DEBUG_LEVELS: [Level; 5] = [
+    Level::ERROR,
+    Level::WARN,
+    Level::INFO,
+    Level::DEBUG,
+    Level::TRACE,
+];
+
 pub struct UiService {
     action_sender: UnboundedSender<Action>,
+    event_sender: UnboundedSender<Event>,
     app_state: AppState,
     terminal_sender: Sender<Bytes>,
-    gpt_role: String,
 }
 
 impl UiService {
     /// Renders the user interface widgets.
-    pub fn render(&self, frame: &mut Frame, screen: &Screen) {
+    pub fn render(&mut self, frame: &mut Frame, screen: &Screen) {
         // Root layout which has a footer spanning the entire screen
         let root_box = Layout::default()
             .direction(Direction::Vertical)
             .constraints(vec![Constraint::Fill(1), Constraint::Max(1)])
-            .split(frame.size());
+            .split(frame.area());
         // Outer layout, which is inside the root_layout, on top of the footer. This is essentially the area we use
         let outer_layout = Layout::default()
-            .direction(Direction::Horizontal)
+            .direction(self.app_state.layout.direction())
             .constraints(vec![
                 Constraint::Percentage(60), // Terminal
                 Constraint::Percentage(40), // Chat
             ])
             .split(root_box[0]);
-        let footer_text = "<CTRL>q to exit | <CTRL>b to change mode".to_string();
-        let footer = Paragraph::new(footer_text)
-            .style(Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED))
+        // The footer has hints for what can be done right now, and what the chat is using on the right
+        let footer_status = self.footer_status();
+        let footer_layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![
+                Constraint::Fill(1),
+                Constraint::Length(footer_status.chars().count() as u16),
+            ])
+            .split(root_box[1]);
+        let theme = self.app_state.theme;
+        let footer_style = Style::default()
+            .fg(theme.footer_text)
+            .bg(theme.footer_background)
+            .add_modifier(Modifier::BOLD);
+        let footer = Paragraph::new(self.footer_hints())
+            .style(footer_style)
             .alignment(Alignment::Center);
-        frame.render_widget(footer, root_box[1]);
+        frame.render_widget(footer, footer_layout[0]);
+        frame.render_widget(
+            Paragraph::new(footer_status).style(footer_style.bg(theme.footer_status)),
+            footer_layout[1],
+        );
 
         let terminal_style = match self.app_state.current_mode {
-            Mode::Terminal => Style::default().cyan(),
-            Mode::Chat => Style::default(),
+            Mode::Terminal => Style::default().fg(theme.focused_border),
+            Mode::Chat => Style::default().fg(theme.border),
         };
 
         let chat_box_style = match self.app_state.current_mode {
-            Mode::Terminal => Style::default(),
-            Mode::Chat => Style::default().cyan(),
+            Mode::Terminal => Style::default().fg(theme.border),
+            Mode::Chat => Style::default().fg(theme.focused_border),
         };
 
         let chat_input_style = match self.app_state.current_mode {
-            Mode::Terminal => Style::default(),
+            Mode::Terminal => Style::default().fg(theme.border),
             Mode::Chat => match self.app_state.disable_chat {
-                true => Style::default().gray(),
-                false => Style::default().cyan(),
+                true => Style::default().fg(theme.disabled),
+                false => Style::default().fg(theme.focused_border),
             },
         };
 
@@ -89,6 +148,34 @@ impl UiService {
             outer_layout[0],
         );
 
+        // Let the user know the shell is gone, instead of showing a frozen terminal
+        if let Some(terminal_exit) = &self.app_state.terminal_exit {
+            let popup_area = centered_rect(outer_layout[0], 60, 6);
+            let popup = Paragraph::new(vec![
+                Line::from(terminal_exit.as_str()),
+                Line::from(""),
+                Line::from(format!(
+                    "In terminal mode, press {}",
+                    self.command_hints(&[
+                        (&[Command::RestartShell], "to restart the shell"),
+                        (&[Command::QuitAfterShellExit], "to exit"),
+                    ])
+                    .join(" or ")
+                ))
+                .bold(),
+            ])
+            .wrap(Wrap { trim: true })
+            .alignment(Alignment::Center)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(theme.error_border))
+                    .title("Terminal stopped"),
+            );
+            frame.render_widget(Clear, popup_area);
+            frame.render_widget(popup, popup_area);
+        }
+
         // Chat code - here we need to create our own layout, with two boxes inside
         let chat_layout = Layout::default()
             .direction(Direction::Vertical)
@@ -98,39 +185,139 @@ impl UiService {
             ])
             .split(outer_layout[1]);
 
-        let chat_block = Block::default()
-            .title("GPT")
+        // The open chats as tabs, with the one shown highlighted, and the model it uses
+        let mut tabs = vec![];
+        for (index, chat) in self.app_state.chats().into_iter().enumerate() {
+            let tab = Span::raw(format!(" {} ", chat.name));
+            tabs.push(match index == self.app_state.chat_index {
+                true => tab.reversed(),
+                false => tab,
+            });
+        }
+        let mut chat_block = Block::default()
+            .title(Line::from(tabs))
+            .title(Line::from(self.app_state.chat.parameters.model.as_str()).right_aligned())
             .borders(Borders::ALL)
             .border_style(chat_box_style);
+        // Offered rather than pushed, so it stays out of the way until the user wants it
+        if let Some(failure) = &self.app_state.error_hint {
+            let mut hint = match theme.emoji {
+                true => format!(" 💡 {}. Explain this error?", failure.summary),
+                false => format!(" {}. Explain this error?", failure.summary),
+            };
+            let keymap = &self.app_state.keymap;
+            if let Some(key) = keymap.describe(Command::ExplainError, &self.app_state.current_mode)
+            {
+                hint.push_str(&format!(" {}", key));
+            }
+            hint.push(' ');
+            chat_block = chat_block.title_bottom(Line::from(hint).right_aligned().fg(theme.accent));
+        }
 
-        let styled_messages: Vec<Line> = self
+        // Code blocks are numbered across all messages
+        let mut next_code_block = 0;
+        let code_block_hint = self
+            .command_hints(&[
+                (&[Command::InsertCode], "insert"),
+                (&[Command::RunCode], "run"),
+                (&[Command::CopyCode], "copy"),
+            ])
+            .join(" | ");
+        let selected_code_block =
+            self.app_state
+                .selected_code_block
+                .map(|number| SelectedCodeBlock {
+                    number,
+                    hint: &code_block_hint,
+                });
+        // Where the selected message starts, in unwrapped lines
+        let mut selected_message_start = None;
+        let mut styled_messages: Vec<Line> = vec![];
+        for (index, message) in self
             .app_state
-            .chat_history
-            .iter()
-            .flat_map(|message| {
-                message
-                    .style(chat_layout[0].width.into(), self.gpt_role.clone())
-                    .into_iter()
-            })
-            .collect();
+            .chat
+            .conversation
+            .messages()
+            .into_iter()
+            .enumerate()
+        {
+            let mut lines = message.style(
+                chat_layout[0].width.into(),
+                &self.app_state.chat,
+                next_code_block,
+                selected_code_block,
+                self.app_state.chat.conversation.siblings(index),
+                &theme,
+            );
+            next_code_block += message.code_blocks().len();
+            if self.app_state.selected_message == Some(index) {
+                selected_message_start = Some(styled_messages.len());
+                if let Some(header) = lines.first_mut() {
+                    *header = header.clone().reversed();
+                }
+            }
+            styled_messages.extend(lines);
+        }
+
+        // Scrolling is in wrapped lines, so we need to know how many there are at the current width
+        let chat_inner = chat_block.inner(chat_layout[0]);
+        let selected_message_offset = selected_message_start.map(|start| {
+            Paragraph::new(styled_messages[..start].to_vec())
+                .wrap(Wrap { trim: false })
+                .line_count(chat_inner.width)
+                .try_into()
+                .unwrap_or(u16::MAX)
+        });
+
+        // Don't trim, as that would strip the indentation of code blocks and lists
+        let chat = Paragraph::new(styled_messages).wrap(Wrap { trim: false });
+        let line_count = chat.line_count(chat_inner.width);
+        self.app_state.chat_height = chat_inner.height;
+        self.app_state.max_scroll = line_count
+            .saturating_sub(chat_inner.height.into())
+            .try_into()
+            .unwrap_or(u16::MAX);
+        if self.app_state.follow_chat {
+            self.app_state.scroll = self.app_state.max_scroll;
+        } else {
+            self.app_state.scroll = self.app_state.scroll.min(self.app_state.max_scroll);
+        }
+        // Bring the header of the selected message into view
+        if let Some(offset) = selected_message_offset {
+            if offset < self.app_state.scroll || offset >= self.app_state.scroll + chat_inner.height
+            {
+                self.app_state.scroll = offset.min(self.app_state.max_scroll);
+                self.app_state.follow_chat = false;
+            }
+        }
 
         frame.render_widget(
-            Paragraph::new(styled_messages)
-                .wrap(Wrap { trim: true })
-                .block(chat_block)
-                .scroll((self.app_state.scroll, 0)),
+            chat.block(chat_block).scroll((self.app_state.scroll, 0)),
             chat_layout[0],
         );
+        let mut scrollbar_state = ScrollbarState::new(self.app_state.max_scroll.into())
+            .position(self.app_state.scroll.into())
+            .viewport_content_length(chat_inner.height.into());
+        frame.render_stateful_widget(
+            Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .begin_symbol(None)
+                .end_symbol(None),
+            chat_layout[0].inner(Margin {
+                vertical: 1,
+                horizontal: 0,
+            }),
+            &mut scrollbar_state,
+        );
 
         // Chat box where we type shit
         let chat_box_style = match self.app_state.disable_chat {
-            true => Style::default().gray(),
+            true => Style::default().fg(theme.disabled),
             false => Style::default(),
         };
 
         let default_throbber = throbber_widgets_tui::Throbber::default()
             .label("Loading...")
-            .style(ratatui::style::Style::default().fg(ratatui::style::Color::Gray));
+            .style(Style::default().fg(theme.throbber));
 
         let chatbox_widget = match self.app_state.disable_chat {
             true => Paragraph::new(self.app_state.user_chat_to_send_to_gpt.clone())
@@ -151,7 +338,16 @@ impl UiService {
                     Block::new()
                         .borders(Borders::ALL)
                         .border_style(chat_input_style)
-                        .title("GPT")
+                        .title(match self.app_state.editing_message {
+                            Some(_) => "Editing message (<ESC> to cancel)".to_string(),
+                            // Who the message goes to
+                            None => format!(
+                                "{} | {}",
+                                self.app_state.chat.role.title(),
+                                self.app_state.chat.parameters.model
+                            ),
+                        })
+                        .title_bottom(attachment_chips(&self.app_state.attachments, &theme))
                         .style(chat_box_style),
                 )
                 .alignment(if self.app_state.disable_chat {
@@ -162,265 +358,1583 @@ impl UiService {
         };
 
         frame.render_widget(chatbox_widget, chat_layout[1]);
+
+        // Running a command the AI suggested needs an explicit yes from the user
+        if let Some(command) = &self.app_state.pending_run {
+            let mut lines = vec![
+                Line::from("Run this in the terminal?").bold(),
+                Line::from(""),
+            ];
+            lines.extend(command.lines().map(|line| Line::from(line).fg(theme.code)));
+            lines.push(Line::from(""));
+            let confirm = match self
+                .command_hints(&[(&[Command::ConfirmRun], "to run")])
+                .pop()
+            {
+                Some(confirm) => format!("{}, any other key to cancel", confirm),
+                None => "Any key to cancel".to_string(),
+            };
+            lines.push(Line::from(confirm).bold());
+
+            let popup_area = centered_rect(outer_layout[1], 60, lines.len() as u16 + 2);
+            let popup = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(theme.focused_border))
+                    .title("Confirm"),
+            );
+            frame.render_widget(Clear, popup_area);
+            frame.render_widget(popup, popup_area);
+        }
+
+        if let Some(model_picker) = &self.app_state.model_picker {
+            let area = outer_layout[1];
+            let items: Vec<ListItem> = match model_picker.models.is_empty() {
+                true => vec![ListItem::new("Asking the provider for its models...")],
+                false => model_picker
+                    .models
+                    .iter()
+                    .map(
+                        |model| match *model == self.app_state.chat.parameters.model {
+                            true => ListItem::new(format!("{} (current)", model)),
+                            false => ListItem::new(model.as_str()),
+                        },
+                    )
+                    .collect(),
+            };
+            let popup_area = centered_rect(
+                area,
+                60,
+                (items.len() as u16 + 2).min(area.height.saturating_sub(2)),
+            );
+            let list = List::new(items)
+                .highlight_style(Style::default().reversed())
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(theme.focused_border))
+                        .title("Model"),
+                );
+            let mut list_state = ListState::default().with_selected(
+                Some(model_picker.selected).filter(|_| !model_picker.models.is_empty()),
+            );
+            frame.render_widget(Clear, popup_area);
+            frame.render_stateful_widget(list, popup_area, &mut list_state);
+        }
+
+        if let Some(selected) = self.app_state.chat_list {
+            let chats = self.app_state.chats();
+            let name_width = chats
+                .iter()
+                .map(|chat| chat.name.chars().count())
+                .max()
+                .unwrap_or_default();
+            let items: Vec<ListItem> = chats
+                .into_iter()
+                .map(|chat| {
+                    ListItem::new(Line::from(vec![
+                        Span::raw(format!("{:<width$}  ", chat.name, width = name_width)),
+                        Span::raw(format!(
+                            "{} | {} | {} message{}",
+                            chat.role.title(),
+                            chat.parameters.model,
+                            chat.conversation.len(),
+                            if chat.conversation.len() == 1 {
+                                ""
+                            } else {
+                                "s"
+                            }
+                        ))
+                        .fg(theme.muted),
+                    ]))
+                })
+                .collect();
+            let area = outer_layout[1];
+            let popup_area = centered_rect(
+                area,
+                area.width.saturating_sub(4),
+                (items.len() as u16 + 2).min(area.height.saturating_sub(2)),
+            );
+            let list = List::new(items)
+                .highlight_style(Style::default().reversed())
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(theme.focused_border))
+                        .title("Chats"),
+                );
+            let mut list_state = ListState::default().with_selected(Some(selected));
+            frame.render_widget(Clear, popup_area);
+            frame.render_stateful_widget(list, popup_area, &mut list_state);
+        }
+
+        if self.app_state.debug_pane.is_some() {
+            // Drawn over the bottom of the screen, so the terminal keeps its size
+            let area = root_box[0];
+            let height = area.height * 2 / 5;
+            self.render_debug_pane(
+                frame,
+                Rect {
+                    y: area.y + area.height - height,
+                    height,
+                    ..area
+                },
+            );
+        }
+
+        if self.app_state.show_help {
+            let lines = self.help();
+            let popup_area = centered_rect(root_box[0], 90, lines.len() as u16 + 2);
+            let popup = Paragraph::new(lines).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(theme.focused_border))
+                    .title("Help (any key to close)"),
+            );
+            frame.render_widget(Clear, popup_area);
+            frame.render_widget(popup, popup_area);
+        }
+    }
+
+    /// Renders the debug pane, with the latest log records or the last call to the AI.
+    fn render_debug_pane(&mut self, frame: &mut Frame, area: Rect) {
+        let theme = self.app_state.theme;
+        let debug_log = self.app_state.debug_log.clone();
+        let Some(debug_pane) = &mut self.app_state.debug_pane else {
+            return;
+        };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.focused_border));
+        let height = block.inner(area).height;
+
+        let (title, paragraph) = match debug_pane.view {
+            DebugView::Log => {
+                let lines: Vec<Line> = debug_log
+                    .records(debug_pane.level)
+                    .into_iter()
+                    .map(|record| {
+                        let color = match record.level {
+                            Level::ERROR => theme.error_border,
+                            Level::WARN => theme.user_message,
+                            Level::INFO => theme.accent,
+                            _ => theme.muted,
+                        };
+                        Line::from(vec![
+                            Span::raw(format!(
+                                "{:02}:{:02}:{:02} ",
+                                record.time.hour(),
+                                record.time.minute(),
+                                record.time.second()
+                            ))
+                            .fg(theme.muted),
+                            Span::raw(format!("{:<5} ", record.level)).fg(color),
+                            Span::raw(format!("{}: ", record.target)).fg(theme.muted),
+                            Span::raw(record.message),
+                        ])
+                    })
+                    .collect();
+                // Scrolled up from the latest record, which is at the bottom
+                let max_scroll = (lines.len() as u16).saturating_sub(height);
+                debug_pane.scroll = debug_pane.scroll.min(max_scroll);
+                (
+                    format!("Log, {} and up (UTC)", debug_pane.level),
+                    Paragraph::new(lines).scroll((max_scroll - debug_pane.scroll, 0)),
+                )
+            }
+            DebugView::LastCall => {
+                let exchange = debug_log.exchange();
+                let mut lines = vec![Line::from("Request").bold()];
+                lines.extend(
+                    exchange
+                        .request
+                        .lines()
+                        .map(|line| Line::raw(line.to_string())),
+                );
+                lines.push(Line::from(""));
+                lines.push(Line::from("Response").bold());
+                lines.extend(
+                    exchange
+                        .response
+                        .into_iter()
+                        .map(|chunk| Line::raw(chunk).fg(theme.code)),
+                );
+                // The chunks are long lines, so they are wrapped, and scrolling is in wrapped lines
+                let paragraph = Paragraph::new(lines).wrap(Wrap { trim: false });
+                let line_count = paragraph.line_count(block.inner(area).width);
+                let max_scroll = u16::try_from(line_count)
+                    .unwrap_or(u16::MAX)
+                    .saturating_sub(height);
+                debug_pane.scroll = debug_pane.scroll.min(max_scroll);
+                (
+                    "Last AI call".to_string(),
+                    paragraph.scroll((debug_pane.scroll, 0)),
+                )
+            }
+        };
+        frame.render_widget(Clear, area);
+        frame.render_widget(paragraph.block(block.title(title)), area);
+    }
+
+    /// Hints for the keys that do something right now.
+    fn footer_hints(&self) -> String {
+        let keymap = &self.app_state.keymap;
+        let mode = &self.app_state.current_mode;
+        if let Some(status_message) = &self.app_state.status_message {
+            return status_message.clone();
+        }
+        if keymap.prefix_pressed() {
+            return "Waiting for a command key".to_string();
+        }
+        if self.app_state.model_picker.is_some() {
+            return self
+                .key_hints(&[
+                    (&[KeyCode::Up, KeyCode::Down], "select"),
+                    (&[KeyCode::Enter], "switch to the model"),
+                    (&[KeyCode::Esc], "cancel"),
+                ])
+                .join(" | ");
+        }
+        if self.app_state.chat_list.is_some() {
+            let mut hints = self.key_hints(&[
+                (&[KeyCode::Up, KeyCode::Down], "select"),
+                (&[KeyCode::Enter], "switch to the chat"),
+            ]);
+            hints.extend(self.command_hints(&[
+                (&[Command::NewChat], "new chat"),
+                (&[Command::CloseChat], "close chat"),
+            ]));
+            hints.extend(self.key_hints(&[(&[KeyCode::Esc], "done")]));
+            return hints.join(" | ");
+        }
+        if let Some(debug_pane) = &self.app_state.debug_pane {
+            return self
+                .key_hints(&[
+                    (&[KeyCode::Tab], "log/last AI call"),
+                    (
+                        &[KeyCode::Left, KeyCode::Right],
+                        &format!("level ({})", debug_pane.level),
+                    ),
+                    (
+                        &[
+                            KeyCode::Up,
+                            KeyCode::Down,
+                            KeyCode::PageUp,
+                            KeyCode::PageDown,
+                        ],
+                        "scroll",
+                    ),
+                    (&[KeyCode::Esc], "close"),
+                ])
+                .join(" | ");
+        }
+        if self.app_state.selected_message.is_some() && matches!(mode, Mode::Chat) {
+            let mut hints = self.key_hints(&[
+                (&[KeyCode::Up, KeyCode::Down], "select message"),
+                (&[KeyCode::Left, KeyCode::Right], "switch version"),
+            ]);
+            hints.extend(self.command_hints(&[
+                (&[Command::CopyMessage], "copy"),
+                (&[Command::EditMessage], "edit"),
+                (&[Command::RegenerateMessage], "regenerate"),
+                (&[Command::DeleteMessage], "delete"),
+            ]));
+            hints.extend(self.key_hints(&[(&[KeyCode::Esc], "done")]));
+            return hints.join(" | ");
+        }
+
+        let mut hints = self.command_hints(&[
+            (&[Command::Quit], "to exit"),
+            (&[Command::ChangeMode], "to change mode"),
+            (&[Command::Help], "for help"),
+        ]);
+        match mode {
+            Mode::Chat if self.app_state.selected_code_block.is_some() => {
+                hints.extend(self.command_hints(&[
+                    (&[Command::InsertCode], "insert"),
+                    (&[Command::RunCode], "run"),
+                    (&[Command::CopyCode], "copy"),
+                    (
+                        &[Command::PreviousCodeBlock, Command::NextCodeBlock],
+                        "select code block",
+                    ),
+                ]));
+                hints.extend(self.key_hints(&[(&[KeyCode::Esc], "done")]));
+            }
+            Mode::Chat if self.app_state.editing_message.is_some() => {
+                hints.extend(
+                    self.key_hints(&[(&[KeyCode::Enter], "resend"), (&[KeyCode::Esc], "cancel")]),
+                );
+            }
+            Mode::Chat => {
+                hints.extend(self.key_hints(&[(&[KeyCode::Up], "select message")]));
+                hints.extend(self.command_hints(&[(
+                    &[Command::PreviousCodeBlock, Command::NextCodeBlock],
+                    "select code block",
+                )]));
+            }
+            Mode::Terminal => {}
+        }
+        hints.join(" | ")
+    }
+
+    /// Hints like `<CTRL>q to exit`, for the commands with a key in the current mode. Commands that share a hint,
+    /// like selecting the previous and next code block, have their keys joined, as in `<ALT>↑/<ALT>↓`.
+    fn command_hints(&self, commands: &[(&[Command], &str)]) -> Vec<String> {
+        let mode = &self.app_state.current_mode;
+        commands
+            .iter()
+            .filter_map(|(commands, hint)| {
+                let keys: Option<Vec<String>> = commands
+                    .iter()
+                    .map(|command| self.app_state.keymap.describe(*command, mode))
+                    .collect();
+                Some(format!("{} {}", keys?.join("/"), hint))
+            })
+            .collect()
+    }
+
+    /// Hints like `<ENTER> resend`, for keys that aren't commands. Those a command has been bound to in the current
+    /// mode do that instead, so they are left out.
+    fn key_hints(&self, keys: &[(&[KeyCode], &str)]) -> Vec<String> {
+        keys.iter()
+            .filter_map(|(keys, hint)| {
+                let keys = self.fixed_keys(keys, &self.app_state.current_mode)?;
+                Some(format!("{} {}", keys, hint))
+            })
+            .collect()
+    }
+
+    /// The keys that still do what they do without a command in `mode`, joined like `↑/↓`, if any do.
+    fn fixed_keys(&self, keys: &[KeyCode], mode: &Mode) -> Option<String> {
+        let keys: Vec<String> = keys
+            .iter()
+            .filter(|key| !self.app_state.keymap.is_bound(**key, mode))
+            .map(|key| keymap::describe_key((*key, KeyModifiers::NONE)))
+            .collect();
+        Some(keys.join("/")).filter(|_| !keys.is_empty())
+    }
+
+    /// The mode, role and model, roughly how many tokens the conversation is so far, and what the session has used
+    /// and cost.
+    fn footer_status(&self) -> String {
+        let mode = match self.app_state.current_mode {
+            Mode::Terminal => "TERMINAL",
+            Mode::Chat => "CHAT",
+        };
+        let tokens: usize = self
+            .app_state
+            .chat
+            .conversation
+            .messages()
+            .into_iter()
+            .map(Message::estimated_tokens)
+            .sum();
+        let tokens = match tokens {
+            0..=999 => tokens.to_string(),
+            _ => format!("{:.1}k", tokens as f64 / 1000.0),
+        };
+        let mut status = format!(
+            " {} | {} | {} | ~{} tokens ",
+            mode,
+            self.app_state.chat.role.title(),
+            self.app_state.chat.parameters.model,
+            tokens
+        );
+        let total = Total::of(self.app_state.chat.conversation.usage());
+        if !total.is_empty() {
+            let approximately = if total.estimated { "~" } else { "" };
+            status.push_str(&format!(
+                "| {}{} used ",
+                approximately,
+                usage::format_count(total.total_tokens())
+            ));
+            if let Some(cost) = total.cost {
+                status.push_str(&format!("{}{} ", approximately, usage::format_cost(cost)));
+            }
+        }
+        status
+    }
+
+    /// The lines of the help, listing the keys from the keymap and the slash commands.
+    fn help(&self) -> Vec<Line<'static>> {
+        const KEY_WIDTH: usize = 44;
+        let entry = |keys: String, description: &str| {
+            Line::from(vec![
+                Span::from(format!(" {:<width$}", keys, width = KEY_WIDTH))
+                    .fg(self.app_state.theme.accent),
+                Span::from(description.to_string()),
+            ])
+        };
+
+        let mut lines = vec![Line::from(" Keys").bold()];
+        for command in Command::ALL {
+            let keys = self.app_state.keymap.describe_all(command);
+            if !keys.is_empty() {
+                lines.push(entry(keys.join(", "), command.description()));
+            }
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from(" In the chat").bold());
+        lines.push(entry(
+            "<ALT>1-9".to_string(),
+            "Select a code block by its number",
+        ));
+        for (keys, description) in [
+            (
+                &[KeyCode::Char('?')][..],
+                "Show this help, when nothing is typed",
+            ),
+            (
+                &[KeyCode::Up],
+                "Select a message, to copy, edit, regenerate or delete it",
+            ),
+            (
+                &[
+                    KeyCode::PageUp,
+                    KeyCode::PageDown,
+                    KeyCode::Home,
+                    KeyCode::End,
+                ],
+                "Scroll the chat by page, or to the top or bottom",
+            ),
+            (&[KeyCode::Tab], "Complete the path after /attach or @"),
+            (
+                &[KeyCode::Backspace],
+                "Remove the last attached file, when nothing is typed",
+            ),
+            (
+                &[KeyCode::Esc],
+                "Clear the input and the attached files, or the selected code block",
+            ),
+        ] {
+            if let Some(keys) = self.fixed_keys(keys, &Mode::Chat) {
+                lines.push(entry(keys, description));
+            }
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from(" Commands").bold());
+        for command in SlashCommand::ALL {
+            lines.push(entry(command.usage().to_string(), command.description()));
+        }
+        lines
     }
 
     pub async fn start(
         &mut self,
         terminal: &mut Terminal<CrosstermBackend<Stdout>>,
         event_service: &mut EventService,
-        parser: Arc<RwLock<vt100::Parser>>,
+        mut parser: Arc<RwLock<vt100::Parser>>,
     ) {
+        // Only render when something changed, and at most once per FRAME_DURATION.
+        // Bursts of events (terminal output, streamed tokens, pasted text) are coalesced into a single frame.
+        let mut dirty = true;
+        let mut next_frame = Instant::now();
         while self.app_state.running {
-            let screen = parser.read().await.screen().clone();
-            terminal.draw(|frame| self.render(frame, &screen)).unwrap();
+            if dirty && Instant::now() >= next_frame {
+                let parser = parser.read().await;
+                terminal
+                    .draw(|frame| self.render(frame, parser.screen()))
+                    .unwrap();
+                dirty = false;
+                next_frame = Instant::now() + FRAME_DURATION;
+            }
+
             // Handle events
-            match event_service.next().await.unwrap() {
+            let event = tokio::select! {
+                event = event_service.next() => event.unwrap(),
+                _ = time::sleep_until(next_frame), if dirty => continue,
+                // The throbber is the only thing animating on its own
+                _ = time::sleep(THROBBER_INTERVAL), if self.app_state.disable_chat => Event::Tick,
+                // New log records don't come with an event, so keep the debug pane up to date
+                _ = time::sleep(DEBUG_PANE_INTERVAL), if self.app_state.debug_pane.is_some() => Event::Tick,
+            };
+            dirty = true;
+            if let Event::Key(_) = event {
+                self.app_state.status_message = None;
+                // The help is closed by any key, which does nothing else
+                if self.app_state.show_help {
+                    self.app_state.show_help = false;
+                    continue;
+                }
+            }
+            // Keys bound to commands in the keymap become their events, the rest are handled as keys below
+            let event = match event {
+                Event::Key(key) => match self
+                    .app_state
+                    .keymap
+                    .resolve(key, &self.app_state.current_mode)
+                {
+                    KeyAction::Command(Command::Quit) => Event::Quit,
+                    KeyAction::Command(Command::ChangeMode) => Event::ChangeMode,
+                    KeyAction::Command(Command::ScrollUp) => Event::ScrollUp,
+                    KeyAction::Command(Command::ScrollDown) => Event::ScrollDown,
+                    KeyAction::Command(Command::Help) => {
+                        self.app_state.show_help = true;
+                        continue;
+                    }
+                    KeyAction::Command(Command::DebugPane) => {
+                        self.app_state.debug_pane = match self.app_state.debug_pane {
+                            Some(_) => None,
+                            None => Some(DebugPane::default()),
+                        };
+                        continue;
+                    }
+                    KeyAction::Command(Command::NextChat) => {
+                        self.cycle_chat(1);
+                        continue;
+                    }
+                    KeyAction::Command(Command::PreviousChat) => {
+                        self.cycle_chat(-1);
+                        continue;
+                    }
+                    KeyAction::Command(Command::ChatList) => {
+                        self.app_state.chat_list = match self.app_state.chat_list {
+                            Some(_) => None,
+                            None => Some(self.app_state.chat_index),
+                        };
+                        continue;
+                    }
+                    KeyAction::Command(Command::ExplainError) => {
+                        self.explain_error().await;
+                        continue;
+                    }
+                    KeyAction::Command(Command::PreviousCodeBlock) => {
+                        self.app_state.select_code_block(-1);
+                        continue;
+                    }
+                    KeyAction::Command(Command::NextCodeBlock) => {
+                        self.app_state.select_code_block(1);
+                        continue;
+                    }
+                    KeyAction::Command(
+                        command @ (Command::InsertCode | Command::RunCode | Command::CopyCode),
+                    ) => {
+                        let bracketed_paste = parser.read().await.screen().bracketed_paste();
+                        self.use_code_block(command, bracketed_paste).await;
+                        continue;
+                    }
+                    // The contextual commands aren't in the keymaps of the modes, their keys are looked up where they
+                    // do something
+                    KeyAction::Command(_) => Event::Key(key),
+                    KeyAction::Prefix => continue,
+                    KeyAction::Key(key) => Event::Key(key),
+                },
+                event => event,
+            };
+            match event {
                 Event::AIStreamResponse(stream) => {
-                    if let Some(last_message) = self.app_state.chat_history.last_mut() {
-                        match last_message.sender {
-                            MessageSender::User => {
-                                self.app_state.chat_history.push(Message {
-                                    sender: app_state::MessageSender::Assistant,
-                                    message: stream,
-                                });
-                            }
-                            MessageSender::Assistant => {
-                                last_message.message.push_str(&stream);
-                            }
+                    match self.app_state.chat.conversation.last_mut() {
+                        Some(last_message) if last_message.sender == MessageSender::Assistant => {
+                            last_message.message.push_str(&stream);
                         }
-                    } else {
-                        // No last message, so it has to be assistant role change
-                        self.app_state.chat_history.push(Message {
-                            sender: app_state::MessageSender::Assistant,
-                            message: stream,
-                        });
+                        _ => self
+                            .app_state
+                            .chat
+                            .conversation
+                            .push(Message::assistant(stream)),
                     }
                 }
                 Event::AIReasoning(is_finished_reasoning) => {
                     match is_finished_reasoning {
                         true => {
                             self.app_state.disable_chat = false;
+                            self.app_state.save_session();
                         }
                         false => {
                             self.app_state.disable_chat = true;
                         }
                     };
                 }
+                Event::AIFinished(finish_reason) => {
+                    if let Some(last_message) = self
+                        .app_state
+                        .chat
+                        .conversation
+                        .last_mut()
+                        .filter(|message| message.sender == MessageSender::Assistant)
+                    {
+                        last_message.truncated = finish_reason == FinishReason::Length;
+                    }
+                    if finish_reason == FinishReason::ContentFilter {
+                        self.app_state.status_message =
+                            Some("The answer was stopped by the content filter".to_string());
+                    }
+                }
+                Event::AIUsage(mut usage) => {
+                    self.app_state.prices.apply(&mut usage);
+                    if let Some(last_message) = self
+                        .app_state
+                        .chat
+                        .conversation
+                        .last_mut()
+                        .filter(|message| message.sender == MessageSender::Assistant)
+                    {
+                        // A continued answer already has the usage of what came before
+                        match &mut last_message.usage {
+                            Some(previous) => previous.add(usage),
+                            None => last_message.usage = Some(usage),
+                        }
+                        self.app_state.save_session();
+                    }
+                }
+                Event::AISources(sources) => {
+                    if let Some(last_message) = self
+                        .app_state
+                        .chat
+                        .conversation
+                        .last_mut()
+                        .filter(|message| message.sender == MessageSender::Assistant)
+                    {
+                        // A continued answer keeps the sources of what came before
+                        for source in sources {
+                            if !last_message.sources.contains(&source) {
+                                last_message.sources.push(source);
+                            }
+                        }
+                        self.app_state.save_session();
+                    }
+                }
+                Event::AIError(error) => {
+                    self.app_state.status_message =
+                        Some(format!("The AI request failed: {}", error));
+                    // A regenerated answer starts out empty, and shouldn't stay behind as an alternative to the old one
+                    let conversation = &mut self.app_state.chat.conversation;
+                    if conversation.last_mut().is_some_and(|message| {
+                        message.sender == MessageSender::Assistant && message.message.is_empty()
+                    }) {
+                        let len = conversation.len();
+                        conversation.remove(len - 1, len);
+                    }
+                }
+                Event::KnowledgeIndexed(result) => {
+                    self.app_state.status_message = Some(match result {
+                        Ok(summary) => summary,
+                        Err(err) => format!("Failed to index the docs: {}", err),
+                    });
+                }
+                Event::Models(models) => match models {
+                    // The picker may have been closed while the provider was asked
+                    Ok(models) if !models.is_empty() => {
+                        if let Some(model_picker) = &mut self.app_state.model_picker {
+                            model_picker.selected = models
+                                .iter()
+                                .position(|model| *model == self.app_state.chat.parameters.model)
+                                .unwrap_or_default();
+                            model_picker.models = models;
+                        }
+                    }
+                    Ok(_) => {
+                        self.app_state.model_picker = None;
+                        self.app_state.status_message =
+                            Some("The provider doesn't have any chat models".to_string());
+                    }
+                    Err(err) => {
+                        self.app_state.model_picker = None;
+                        self.app_state.status_message =
+                            Some(format!("Failed to list the models: {}", err));
+                    }
+                },
                 Event::Tick => self.app_state.tick(),
+                Event::TerminalUpdated => {}
                 Event::Quit => self.app_state.quit(),
                 Event::ChangeMode => self.app_state.change_mode(),
                 Event::Resize(columns, rows) => {
-                    let adjusted_width = (columns as f32 * 0.57).floor() as u16;
-                    parser.write().await.set_size(rows - 5, adjusted_width)
+                    // A recording is played back at the size it was made at, or its output would be garbled
+                    if let Some(pty_sizer) = &self.app_state.pty_sizer {
+                        let (cols, rows) = self.app_state.layout.terminal_size(columns, rows);
+                        parser.write().await.set_size(rows, cols);
+                        pty_sizer.resize(cols, rows);
+                        if let Some(recorder) = &self.app_state.recorder {
+                            let resized_at = std::time::Instant::now();
+                            if let Err(err) = recorder.lock().await.resize(resized_at, cols, rows) {
+                                tracing::warn!("Failed recording the resize: {:?}", err);
+                            }
+                        }
+                    }
                 }
-                Event::ScrollUp => self.app_state.scroll = self.app_state.scroll.saturating_add(1),
-                Event::ScrollDown => {
-                    self.app_state.scroll = self.app_state.scroll.saturating_sub(1)
+                Event::ScrollUp => self.app_state.scroll_up(SCROLL_LINES),
+                Event::ScrollDown => self.app_state.scroll_down(SCROLL_LINES),
+                Event::ChildExited(exit_status) => {
+                    self.app_state.terminal_exit =
+                        Some(format!("The shell has exited: {}", exit_status));
                 }
-
-                Event::Key(key) => match key.code {
-                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                        match self.app_state.current_mode {
-                            Mode::Terminal => {
-                                self.terminal_sender
-                                    .send(Bytes::from(vec![3]))
-                                    .await
-                                    .unwrap();
+                Event::CommandFailed(failure) => {
+                    tracing::info!("A command failed in the terminal");
+                    self.app_state.error_hint = Some(failure);
+                }
+                Event::CommandSucceeded => self.app_state.error_hint = None,
+                Event::ReplayFinished => {
+                    self.app_state.status_message =
+                        Some("The recording has been replayed to the end".to_string());
+                }
+                Event::TerminalError(error) => {
+                    // Once the shell has exited, the pseudo terminal is expected to error out
+                    if self.app_state.terminal_exit.is_none() {
+                        self.app_state.terminal_exit = Some(format!("Terminal error: {}", error));
+                    }
+                }
+                Event::Key(key) if self.app_state.debug_pane.is_some() => {
+                    self.handle_debug_pane_key(key)
+                }
+                Event::Key(key) if self.app_state.model_picker.is_some() => {
+                    self.handle_model_picker_key(key)
+                }
+                Event::Key(key) if self.app_state.chat_list.is_some() => {
+                    self.handle_chat_list_key(key).await
+                }
+                Event::Key(key)
+                    if self.app_state.terminal_exit.is_some()
+                        && matches!(self.app_state.current_mode, Mode::Terminal) =>
+                {
+                    let keymap = &self.app_state.keymap;
+                    match keymap
+                        .contextual(key, &[Command::RestartShell, Command::QuitAfterShellExit])
+                    {
+                        Some(Command::RestartShell) => match terminal_utils::new(
+                            terminal,
+                            self.app_state.shell.as_deref(),
+                            self.app_state.layout,
+                            self.app_state.terminal_context.clone(),
+                            self.event_sender.clone(),
+                            event_service.terminal_updated(),
+                            OutputOptions {
+                                recorder: self.app_state.recorder.clone(),
+                                watch_errors: self.app_state.watch_errors,
+                                ..OutputOptions::default()
+                            },
+                        ) {
+                            Ok((new_parser, terminal_sender, pty_sizer)) => {
+                                tracing::info!("Restarted the shell");
+                                parser = new_parser;
+                                self.terminal_sender = terminal_sender;
+                                self.app_state.pty_sizer = Some(pty_sizer);
+                                self.app_state.terminal_exit = None;
                             }
-                            Mode::Chat => {
-                                // handle
+                            Err(err) => {
+                                tracing::warn!("Failed to restart the shell: {:?}", err);
+                                self.app_state.terminal_exit =
+                                    Some(format!("Failed to restart the shell: {}", err));
                             }
-                        }
+                        },
+                        Some(_) => self.app_state.quit(),
+                        None => {}
                     }
-                    KeyCode::Char(char) => match self.app_state.current_mode {
-                        Mode::Chat => {
-                            if !self.app_state.disable_chat {
-                                self.app_state
-                                    .user_chat_to_send_to_gpt
-                                    .push_str(&char.to_string())
-                            }
-                        }
-                        Mode::Terminal => self
-                            .terminal_sender
-                            .send(Bytes::from(char.to_string().into_bytes()))
-                            .await
-                            .unwrap(),
-                    },
-                    KeyCode::Enter => match self.app_state.current_mode {
-                        Mode::Terminal => {
-                            self.terminal_sender
-                                .send(Bytes::from(vec![13u8]))
-                                .await
-                                .unwrap();
+                }
+
+                Event::Key(key) if self.app_state.pending_run.is_some() => {
+                    let command = self.app_state.pending_run.take().unwrap_or_default();
+                    if self.app_state.keymap.is(key, Command::ConfirmRun) {
+                        let bracketed_paste = parser.read().await.screen().bracketed_paste();
+                        self.insert_into_terminal(&command, bracketed_paste).await;
+                        self.send_to_terminal(Bytes::from(vec![13u8])).await;
+                        self.app_state.current_mode = Mode::Terminal;
+                    } else {
+                        self.app_state.status_message = Some("Cancelled".to_string());
+                    }
+                }
+                Event::Key(key)
+                    if self.app_state.selected_message.is_some()
+                        && matches!(self.app_state.current_mode, Mode::Chat) =>
+                {
+                    self.handle_selected_message_key(key)
+                }
+                // Keys that aren't commands go to the shell in the terminal, so these are the keys of the chat
+                Event::Key(key) if matches!(self.app_state.current_mode, Mode::Terminal) => {
+                    let application_cursor = parser.read().await.screen().application_cursor();
+                    match terminal_utils::encode_key(key, application_cursor) {
+                        Some(bytes) => self.send_to_terminal(Bytes::from(bytes)).await,
+                        None => tracing::debug!(target: CONTENT, "No terminal input for {:?}", key),
+                    }
+                }
+                Event::Key(key) => match key.code {
+                    KeyCode::Char(number @ '1'..='9') if key.modifiers == KeyModifiers::ALT => {
+                        let index = number as usize - '1' as usize;
+                        if index < self.app_state.code_blocks().len() {
+                            self.app_state.selected_code_block = Some(index);
                         }
-                        Mode::Chat => {
-                            if self.app_state.user_chat_to_send_to_gpt == "/clear" {
-                                self.app_state.user_chat_to_send_to_gpt.clear();
-                                self.app_state.chat_history.clear();
-                                self.app_state.scroll = 0;
-                                self.action_sender.send(Action::Clear).unwrap();
-                                self.app_state.terminal_context.lock().await.clear();
-                                continue;
-                            } else if self.app_state.user_chat_to_send_to_gpt == "/network" {
-                                self.app_state.user_chat_to_send_to_gpt.clear();
-                                self.app_state.chat_history.clear();
-                                self.app_state.scroll = 0;
-                                self.app_state.terminal_context.lock().await.clear();
-                                self.action_sender.send(Action::NetworkEngineer).unwrap();
-                                self.gpt_role = "Network Engineer".to_string();
-                                continue;
-                            } else if self.app_state.user_chat_to_send_to_gpt == "/linux" {
-                                self.app_state.user_chat_to_send_to_gpt.clear();
-                                self.app_state.chat_history.clear();
-                                self.app_state.scroll = 0;
-                                self.app_state.terminal_context.lock().await.clear();
-                                self.gpt_role = "Linux Engineer".to_string();
-                                self.action_sender.send(Action::LinuxEngineer).unwrap();
-                                continue;
-                            }
-                            match self.app_state.terminal_has_been_active {
-                                true => {
-                                    tracing::warn!(
-                                        "{}",
-                                        self.app_state.terminal_context.lock().await.clone()
-                                    );
-                                    self.action_sender
-                                        .send(Action::AiRequest(format!(
-                                            "This is my terminal output: \n\n ```\n{}\n```\n\n{}",
-                                            self.app_state.terminal_context.lock().await.clone(),
-                                            self.app_state.user_chat_to_send_to_gpt,
-                                        )))
-                                        .unwrap();
+                    }
+                    // AltGr comes as <CTRL><ALT> on some platforms, and what it types, like @ or {, belongs in the input
+                    KeyCode::Char(_)
+                        if key.modifiers.contains(KeyModifiers::CONTROL)
+                            && !key.modifiers.contains(KeyModifiers::ALT) => {}
+                    KeyCode::Char('?') if self.app_state.user_chat_to_send_to_gpt.is_empty() => {
+                        self.app_state.show_help = true
+                    }
+                    KeyCode::Char(char) if !self.app_state.disable_chat => {
+                        self.app_state.user_chat_to_send_to_gpt.push(char)
+                    }
+                    // Nothing is sent while an answer is coming in, or when there is neither a message nor a file
+                    KeyCode::Enter
+                        if self.app_state.disable_chat
+                            || (self.app_state.user_chat_to_send_to_gpt.trim().is_empty()
+                                && self.app_state.attachments.is_empty()) => {}
+                    KeyCode::Enter => {
+                        if let Some((command, arguments)) =
+                            SlashCommand::parse(&self.app_state.user_chat_to_send_to_gpt)
+                        {
+                            let arguments = arguments.to_string();
+                            self.app_state.user_chat_to_send_to_gpt.clear();
+                            match command {
+                                SlashCommand::Clear => self.clear_chat().await,
+                                SlashCommand::New => self.new_chat(Role::General, &arguments).await,
+                                SlashCommand::Network => {
+                                    self.new_chat(Role::NetworkEngineer, &arguments).await
+                                }
+                                SlashCommand::Linux => {
+                                    self.new_chat(Role::LinuxEngineer, &arguments).await
                                 }
-                                false => {
-                                    self.action_sender
-                                        .send(Action::AiRequest(
-                                            self.app_state.user_chat_to_send_to_gpt.clone(),
-                                        ))
-                                        .unwrap();
+                                SlashCommand::Rename => self.rename_chat(&arguments),
+                                SlashCommand::Chats => {
+                                    self.app_state.chat_list = Some(self.app_state.chat_index)
                                 }
+                                SlashCommand::Resume => self.resume_session(),
+                                SlashCommand::Attach => self.attach_file(&arguments),
+                                SlashCommand::Export => self.export_conversation(&arguments),
+                                SlashCommand::Continue => self.continue_answer(),
+                                SlashCommand::Model => self.change_model(&arguments),
+                                SlashCommand::Temperature => self.change_temperature(&arguments),
+                                SlashCommand::MaxTokens => self.change_max_tokens(&arguments),
+                                SlashCommand::Reindex => {
+                                    self.app_state.status_message =
+                                        Some("Indexing the docs...".to_string());
+                                    self.action_sender.send(Action::IndexKnowledge).unwrap();
+                                }
+                                SlashCommand::Help => self.app_state.show_help = true,
                             }
-
-                            // save chat to history
-                            self.app_state.chat_history.push(Message {
-                                sender: app_state::MessageSender::User,
-                                message: self.app_state.user_chat_to_send_to_gpt.clone(),
-                            });
-                            self.app_state.terminal_has_been_active = false;
-                            self.app_state.user_chat_to_send_to_gpt.clear();
-                            self.app_state.disable_chat = true;
-                        }
-                    },
-                    KeyCode::Backspace => match self.app_state.current_mode {
-                        Mode::Chat => {
-                            self.app_state.user_chat_to_send_to_gpt.pop();
-                        }
-                        Mode::Terminal => {
-                            self.terminal_sender
-                                .send(Bytes::from(vec![8u8]))
-                                .await
-                                .unwrap();
-                        }
-                    },
-                    KeyCode::Up => match self.app_state.current_mode {
-                        Mode::Terminal => {
-                            // Handle up arrow key in Terminal mode (e.g., navigate through command history)
-                            self.terminal_sender
-                                .send(Bytes::from(vec![27, 91, 65]))
-                                .await
-                                .unwrap(); // ASCII for ESC[A (Up Arrow)
-                        }
-                        Mode::Chat => {
-                            // Handle up arrow key in Chat mode (e.g., navigate through chat history)
-                        }
-                    },
-                    KeyCode::Down => match self.app_state.current_mode {
-                        Mode::Terminal => {
-                            // Handle down arrow key in Terminal mode
-                            self.terminal_sender
-                                .send(Bytes::from(vec![27, 91, 66]))
-                                .await
-                                .unwrap(); // ASCII for ESC[B (Down Arrow)
-                        }
-                        Mode::Chat => {
-                            // Handle down arrow key in Chat mode
-                        }
-                    },
-                    KeyCode::Left => match self.app_state.current_mode {
-                        Mode::Terminal => {
-                            // Handle left arrow key in Terminal mode
-                            self.terminal_sender
-                                .send(Bytes::from(vec![27, 91, 68]))
-                                .await
-                                .unwrap(); // ASCII for ESC[D (Left Arrow)
+                            continue;
                         }
-                        Mode::Chat => {
-                            // Handle left arrow key in Chat mode
-                        }
-                    },
-                    KeyCode::Right => match self.app_state.current_mode {
-                        Mode::Terminal => {
-                            // Handle right arrow key in Terminal mode
-                            self.terminal_sender
-                                .send(Bytes::from(vec![27, 91, 67]))
-                                .await
-                                .unwrap(); // ASCII for ESC[C (Right Arrow)
-                        }
-                        Mode::Chat => {
-                            // Handle right arrow key in Chat mode
-                        }
-                    },
-                    KeyCode::Delete => match self.app_state.current_mode {
-                        Mode::Terminal => {
-                            // Handle delete key in Terminal mode
-                            self.terminal_sender
-                                .send(Bytes::from(vec![27, 91, 51, 126]))
-                                .await
-                                .unwrap(); // ASCII for ESC[3~ (Delete)
+
+                        let Some(attachments) = self.message_attachments() else {
+                            continue;
+                        };
+                        let terminal_context = match self.app_state.terminal_has_been_active {
+                            true => Some(self.app_state.terminal_context.lock().await.clone()),
+                            false => None,
+                        };
+                        let mut message = Message::user(
+                            self.app_state.user_chat_to_send_to_gpt.clone(),
+                            terminal_context,
+                        );
+                        message.attachments = attachments;
+                        // Resending an edited message adds it next to the original, which is kept on its own branch
+                        match self.app_state.editing_message.take() {
+                            Some(editing) => {
+                                self.app_state.chat.conversation.branch(editing, message);
+                                self.app_state.selected_code_block = None;
+                            }
+                            None => self.app_state.chat.conversation.push(message),
                         }
-                        Mode::Chat => {
-                            // Handle delete key in Chat mode
-                            // You might want to implement similar to backspace or clear a specific part of the text
+                        self.request_answer();
+                        self.app_state.terminal_has_been_active = false;
+                        self.app_state.user_chat_to_send_to_gpt.clear();
+                        // Follow the answer, even if the user was reading something further up
+                        self.app_state.scroll_to_bottom();
+                    }
+                    KeyCode::Backspace => {
+                        if self.app_state.user_chat_to_send_to_gpt.is_empty() {
+                            self.app_state.attachments.pop();
                         }
-                    },
+                        self.app_state.user_chat_to_send_to_gpt.pop();
+                    }
+                    KeyCode::Tab if !self.app_state.disable_chat => self.complete_path(),
+                    // Select the latest message, unless the user is busy typing
+                    KeyCode::Up
+                        if self.app_state.user_chat_to_send_to_gpt.is_empty()
+                            && !self.app_state.chat.conversation.is_empty() =>
+                    {
+                        self.app_state.selected_message =
+                            Some(self.app_state.chat.conversation.len() - 1);
+                    }
                     KeyCode::Esc => {
-                        // Handle escape key globally, maybe switch mode or clear input
-                        match self.app_state.current_mode {
-                            Mode::Chat => {
-                                self.app_state.user_chat_to_send_to_gpt.clear();
-                            }
-                            Mode::Terminal => {
-                                self.terminal_sender
-                                    .send(Bytes::from(vec![27u8]))
-                                    .await
-                                    .unwrap(); // ASCII for ESC
-                            }
+                        if self.app_state.selected_code_block.is_some() {
+                            self.app_state.selected_code_block = None;
+                        } else {
+                            self.app_state.editing_message = None;
+                            self.app_state.user_chat_to_send_to_gpt.clear();
+                            self.app_state.attachments.clear();
                         }
                     }
-                    KeyCode::Tab => {
-                        // Handle Tab key, perhaps for auto-completion or cycling through options
-                        match self.app_state.current_mode {
-                            Mode::Chat => {
-                                // Handle tab in chat, if applicable
-                            }
-                            Mode::Terminal => {
-                                self.terminal_sender
-                                    .send(Bytes::from(vec![9u8]))
-                                    .await
-                                    .unwrap(); // ASCII for Tab
-                            }
+                    KeyCode::PageUp | KeyCode::PageDown | KeyCode::Home | KeyCode::End => {
+                        // Keep a couple of lines from the previous page, to not lose track
+                        let page = self.app_state.chat_height.saturating_sub(2).max(1);
+                        match key.code {
+                            KeyCode::PageUp => self.app_state.scroll_up(page),
+                            KeyCode::PageDown => self.app_state.scroll_down(page),
+                            KeyCode::Home => self.app_state.scroll_to_top(),
+                            _ => self.app_state.scroll_to_bottom(),
                         }
                     }
                     _ => {}
                 },
             }
         }
+        // Keep whatever made it into the conversation since the last save, like a half finished answer
+        self.app_state.save_session();
+    }
+}
+
+impl UiService {
+    /// Writes bytes to the pseudo terminal. Failures are reported by the terminal itself through
+    /// `Event::TerminalError`, so a closed terminal is only logged here.
+    async fn send_to_terminal(&self, bytes: Bytes) {
+        if self.terminal_sender.send(bytes).await.is_err() {
+            tracing::warn!("Terminal is closed, dropping input");
+        }
+    }
+
+    /// Types text into the terminal, without pressing enter.
+    /// If the shell supports bracketed paste we use it, so multi-line text isn't run line by line.
+    async fn insert_into_terminal(&self, text: &str, bracketed_paste: bool) {
+        let text = text.trim_end_matches('\n');
+        let bytes = match bracketed_paste {
+            true => format!("\x1b[200~{}\x1b[201~", text),
+            false => text.replace('\n', "\r"),
+        };
+        self.send_to_terminal(Bytes::from(bytes)).await;
+    }
+
+    /// Handles keys while the model picker is open.
+    fn handle_model_picker_key(&mut self, key: KeyEvent) {
+        let Some(model_picker) = &mut self.app_state.model_picker else {
+            return;
+        };
+        let last = model_picker.models.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Up => model_picker.selected = model_picker.selected.saturating_sub(1),
+            KeyCode::Down => model_picker.selected = (model_picker.selected + 1).min(last),
+            KeyCode::PageUp => model_picker.selected = model_picker.selected.saturating_sub(10),
+            KeyCode::PageDown => model_picker.selected = (model_picker.selected + 10).min(last),
+            KeyCode::Home => model_picker.selected = 0,
+            KeyCode::End => model_picker.selected = last,
+            KeyCode::Enter => {
+                if let Some(model) = model_picker.models.get(model_picker.selected).cloned() {
+                    self.change_model(&model);
+                }
+                self.app_state.model_picker = None;
+            }
+            KeyCode::Esc => self.app_state.model_picker = None,
+            _ => {}
+        }
+    }
+
+    /// Handles keys while the debug pane is open.
+    fn handle_debug_pane_key(&mut self, key: KeyEvent) {
+        let Some(debug_pane) = &mut self.app_state.debug_pane else {
+            return;
+        };
+        let page = self.app_state.chat_height.max(1);
+        // The log is scrolled up from the bottom, the last call down from the top
+        let up = |scroll: u16, lines: u16| match debug_pane.view {
+            DebugView::Log => scroll.saturating_add(lines),
+            DebugView::LastCall => scroll.saturating_sub(lines),
+        };
+        let down = |scroll: u16, lines: u16| match debug_pane.view {
+            DebugView::Log => scroll.saturating_sub(lines),
+            DebugView::LastCall => scroll.saturating_add(lines),
+        };
+        match key.code {
+            KeyCode::Esc => self.app_state.debug_pane = None,
+            KeyCode::Tab => {
+                debug_pane.view = match debug_pane.view {
+                    DebugView::Log => DebugView::LastCall,
+                    DebugView::LastCall => DebugView::Log,
+                };
+                debug_pane.scroll = 0;
+            }
+            KeyCode::Left | KeyCode::Right => {
+                let index = DEBUG_LEVELS
+                    .iter()
+                    .position(|level| *level == debug_pane.level)
+                    .unwrap_or_default();
+                let index = match key.code {
+                    KeyCode::Left => index.saturating_sub(1),
+                    _ => (index + 1).min(DEBUG_LEVELS.len() - 1),
+                };
+                debug_pane.level = DEBUG_LEVELS[index];
+                debug_pane.scroll = 0;
+            }
+            KeyCode::Up => debug_pane.scroll = up(debug_pane.scroll, 1),
+            KeyCode::Down => debug_pane.scroll = down(debug_pane.scroll, 1),
+            KeyCode::PageUp => debug_pane.scroll = up(debug_pane.scroll, page),
+            KeyCode::PageDown => debug_pane.scroll = down(debug_pane.scroll, page),
+            _ => {}
+        }
+    }
+
+    /// Handles keys while a message in the chat is selected.
+    fn handle_selected_message_key(&mut self, key: KeyEvent) {
+        let Some(selected) = self.app_state.selected_message else {
+            return;
+        };
+        let senders: Vec<MessageSender> = self
+            .app_state
+            .chat
+            .conversation
+            .messages()
+            .into_iter()
+            .map(|message| message.sender)
+            .collect();
+        // Changing the conversation while an answer streams in would mix up the turns
+        let busy = self.app_state.disable_chat;
+        let command = self.app_state.keymap.contextual(
+            key,
+            &[
+                Command::CopyMessage,
+                Command::EditMessage,
+                Command::RegenerateMessage,
+                Command::DeleteMessage,
+            ],
+        );
+
+        match (command, key.code) {
+            (None, KeyCode::Up) => {
+                self.app_state.selected_message = Some(selected.saturating_sub(1))
+            }
+            (None, KeyCode::Down) => {
+                // Moving past the last message goes back to typing
+                self.app_state.selected_message =
+                    Some(selected + 1).filter(|next| *next < senders.len())
+            }
+            (None, KeyCode::Esc | KeyCode::Enter) => self.app_state.selected_message = None,
+            (Some(Command::CopyMessage), _) => {
+                let Some(message) = self.app_state.chat.conversation.get(selected) else {
+                    return;
+                };
+                self.app_state.status_message = Some(match clipboard::copy(&message.message) {
+                    Ok(()) => "Copied the message to the clipboard".to_string(),
+                    Err(err) => format!("Failed to copy: {}", err),
+                })
+            }
+            (Some(_), _) | (None, KeyCode::Left | KeyCode::Right) if busy => {
+                self.app_state.status_message =
+                    Some("Wait for the answer to finish first".to_string())
+            }
+            (None, KeyCode::Left | KeyCode::Right) => {
+                let offset = if key.code == KeyCode::Left { -1 } else { 1 };
+                self.app_state
+                    .chat
+                    .conversation
+                    .select_sibling(selected, offset);
+                self.app_state.selected_code_block = None;
+                self.app_state.save_session();
+            }
+            (Some(Command::EditMessage), _) => {
+                if senders[selected] == MessageSender::User {
+                    // The files go along with the edited message, unless they are removed
+                    let message = self.app_state.chat.conversation.get(selected);
+                    self.app_state.user_chat_to_send_to_gpt = message
+                        .map(|message| message.message.clone())
+                        .unwrap_or_default();
+                    self.app_state.attachments = message
+                        .map(|message| message.attachments.clone())
+                        .unwrap_or_default();
+                    self.app_state.editing_message = Some(selected);
+                    self.app_state.selected_message = None;
+                } else {
+                    self.app_state.status_message =
+                        Some("Only your own messages can be edited".to_string())
+                }
+            }
+            (Some(Command::RegenerateMessage), _) => {
+                // Any answer to a question can be regenerated, the new answer is added next to the old one
+                let answers_question = senders[selected] == MessageSender::Assistant
+                    && selected > 0
+                    && senders[selected - 1] == MessageSender::User;
+                if answers_question {
+                    self.app_state
+                        .chat
+                        .conversation
+                        .branch(selected, Message::assistant(String::new()));
+                    self.app_state.selected_message = None;
+                    self.app_state.selected_code_block = None;
+                    self.request_answer();
+                    self.app_state.scroll_to_bottom();
+                } else {
+                    self.app_state.status_message =
+                        Some("Only answers to your questions can be regenerated".to_string())
+                }
+            }
+            (Some(Command::DeleteMessage), _) => {
+                // A turn is a question and the answers to it. Answers without a question are the greetings
+                // after changing roles, which are deleted on their own.
+                let start = senders[..=selected]
+                    .iter()
+                    .rposition(|sender| *sender == MessageSender::User)
+                    .unwrap_or(selected);
+                let end = match senders[start] {
+                    MessageSender::User => senders[start + 1..]
+                        .iter()
+                        .position(|sender| *sender == MessageSender::User)
+                        .map_or(senders.len(), |position| start + 1 + position),
+                    MessageSender::Assistant => start + 1,
+                };
+                self.app_state.chat.conversation.remove(start, end);
+                let remaining = self.app_state.chat.conversation.len();
+                self.app_state.selected_message =
+                    Some(start.min(remaining.saturating_sub(1))).filter(|_| remaining > 0);
+                self.app_state.selected_code_block = None;
+                self.app_state.save_session();
+            }
+            _ => {}
+        }
+    }
+
+    /// Asks the AI to answer the active branch of the conversation.
+    /// If it ends with an empty answer, that is where the new answer streams in.
+    fn request_answer(&mut self) {
+        let mut messages: Vec<Message> = self
+            .app_state
+            .chat
+            .conversation
+            .messages()
+            .into_iter()
+            .cloned()
+            .collect();
+        if messages
+            .last()
+            .is_some_and(|message| message.sender == MessageSender::Assistant)
+        {
+            messages.pop();
+        }
+        self.action_sender
+            .send(Action::AiRequest {
+                role: self.app_state.chat.role,
+                messages,
+                parameters: self.app_state.chat.parameters.clone(),
+            })
+            .unwrap();
+        self.app_state.disable_chat = true;
+    }
+
+    /// Asks the AI to go on with its last answer, as asked for by `/continue`, which is streamed onto the end of it.
+    fn continue_answer(&mut self) {
+        // A second answer would be streamed into the one that is still coming
+        if self.app_state.disable_chat {
+            self.app_state.status_message =
+                Some("Wait for the answer before continuing it".to_string());
+            return;
+        }
+        let mut messages: Vec<Message> = self
+            .app_state
+            .chat
+            .conversation
+            .messages()
+            .into_iter()
+            .cloned()
+            .collect();
+        let Some(last_message) = self
+            .app_state
+            .chat
+            .conversation
+            .last_mut()
+            .filter(|message| message.sender == MessageSender::Assistant)
+        else {
+            self.app_state.status_message = Some("There is no answer to continue".to_string());
+            return;
+        };
+        last_message.truncated = false;
+        // Only asked for, not part of the conversation, so the answer reads as one
+        messages.push(Message::user(CONTINUE_PROMPT.to_string(), None));
+        self.action_sender
+            .send(Action::AiRequest {
+                role: self.app_state.chat.role,
+                messages,
+                parameters: self.app_state.chat.parameters.clone(),
+            })
+            .unwrap();
+        self.app_state.disable_chat = true;
+        self.app_state.scroll_to_bottom();
+    }
+
+    /// Attaches the file at `path` to the next message, as asked for by `/attach`.
+    fn attach_file(&mut self, path: &str) {
+        if path.is_empty() {
+            self.app_state.status_message = Some("Which file? /attach <path>".to_string());
+            return;
+        }
+        if self.app_state.attachments.len() >= MAX_ATTACHMENTS {
+            self.app_state.status_message = Some(format!(
+                "A message can have at most {} files",
+                MAX_ATTACHMENTS
+            ));
+            return;
+        }
+        match Attachment::read(path) {
+            Ok(attachment) => {
+                if attachment.truncated {
+                    self.app_state.status_message = Some(format!(
+                        "{} is too big, only the first {} will be sent",
+                        path,
+                        attachment::format_size(attachment.content.len() as u64)
+                    ));
+                }
+                self.app_state
+                    .attachments
+                    .retain(|attached| attached.path != attachment.path);
+                self.app_state.attachments.push(attachment);
+            }
+            Err(err) => self.app_state.status_message = Some(err),
+        }
+    }
+
+    /// The files to send with the message being typed: those attached with /attach, and those mentioned with `@`.
+    /// Nothing is sent if any of them can't be, so the user can fix the message.
+    fn message_attachments(&mut self) -> Option<Vec<Attachment>> {
+        let mut attachments = std::mem::take(&mut self.app_state.attachments);
+        let attached = attachments.len();
+        for path in attachment::mentions(&self.app_state.user_chat_to_send_to_gpt) {
+            if attachments.iter().any(|attached| attached.path == path) {
+                continue;
+            }
+            match Attachment::read(&path) {
+                Ok(attachment) => attachments.push(attachment),
+                Err(err) => {
+                    self.app_state.status_message = Some(err);
+                    attachments.truncate(attached);
+                    self.app_state.attachments = attachments;
+                    return None;
+                }
+            }
+        }
+        if attachments.len() > MAX_ATTACHMENTS {
+            self.app_state.status_message = Some(format!(
+                "A message can have at most {} files",
+                MAX_ATTACHMENTS
+            ));
+            attachments.truncate(attached);
+            self.app_state.attachments = attachments;
+            return None;
+        }
+        Some(attachments)
+    }
+
+    /// Completes the path being typed after `/attach` or `@`, as far as it can without picking between files, and
+    /// shows the files to pick from.
+    fn complete_path(&mut self) {
+        let input = &self.app_state.user_chat_to_send_to_gpt;
+        let start = match input.strip_prefix("/attach ") {
+            Some(_) => "/attach ".len(),
+            None => match input
+                .rfind(char::is_whitespace)
+                .map_or(0, |space| space + 1)
+            {
+                word if input[word..].starts_with('@') => word + 1,
+                _ => return,
+            },
+        };
+        let completions = attachment::complete(&input[start..]);
+        let completed = attachment::common_prefix(&completions);
+        if completed.len() > input.len() - start {
+            self.app_state.user_chat_to_send_to_gpt.truncate(start);
+            self.app_state.user_chat_to_send_to_gpt.push_str(&completed);
+        }
+        if completions.len() != 1 {
+            let names: Vec<&str> = completions
+                .iter()
+                .map(|completion| {
+                    let name = completion.trim_end_matches('/');
+                    &completion[name.rfind('/').map_or(0, |slash| slash + 1)..]
+                })
+                .collect();
+            self.app_state.status_message = Some(match names.is_empty() {
+                true => "No files match".to_string(),
+                false => names.join("  "),
+            });
+        }
+    }
+
+    /// Asks the AI about the last command that failed in the terminal, as offered by the hint.
+    async fn explain_error(&mut self) {
+        if self.app_state.error_hint.is_none() {
+            self.app_state.status_message = Some("No command has failed".to_string());
+            return;
+        }
+        if self.app_state.disable_chat {
+            self.app_state.status_message =
+                Some("Wait for the answer before asking about the error".to_string());
+            return;
+        }
+        let Some(failure) = self.app_state.error_hint.take() else {
+            return;
+        };
+        let output = match failure.output {
+            Some(output) => output,
+            None => error_watch::recent_output(&self.app_state.terminal_context.lock().await),
+        };
+        self.app_state.chat.conversation.push(Message::user(
+            format!("Explain this error: {}", failure.summary),
+            Some(output),
+        ));
+        self.request_answer();
+        self.app_state.terminal_has_been_active = false;
+        self.app_state.selected_message = None;
+        self.app_state.scroll_to_bottom();
+    }
+
+    /// Starts the chat over with an empty conversation in a new session, as asked for by `/clear`.
+    async fn clear_chat(&mut self) {
+        self.app_state.save_session();
+        let chat = &self.app_state.chat;
+        self.app_state.chat = Chat::new(chat.name.clone(), chat.role, chat.parameters.clone());
+        self.app_state.selected_code_block = None;
+        self.app_state.scroll_to_bottom();
+        self.app_state.terminal_context.lock().await.clear();
+    }
+
+    /// Opens a new chat next to the others, with the AI in `role`. Without a `name`, it is named after the role.
+    async fn new_chat(&mut self, role: Role, name: &str) {
+        if !self.can_change_chat() {
+            return;
+        }
+        let name = match name {
+            "" => self.app_state.unique_chat_name(role.title()),
+            name => name.to_string(),
+        };
+        let chat = Chat::new(name, role, self.app_state.chat.parameters.clone());
+        self.app_state.open_chat(chat);
+        self.app_state.terminal_context.lock().await.clear();
+    }
+
+    /// Whether the chat shown can be changed. Not while an answer is coming in, as it would end up in the wrong chat.
+    fn can_change_chat(&mut self) -> bool {
+        if self.app_state.disable_chat {
+            self.app_state.status_message =
+                Some("Wait for the answer before changing chats".to_string());
+        }
+        !self.app_state.disable_chat
+    }
+
+    /// Shows the chat `offset` chats after the one shown now, wrapping around.
+    fn cycle_chat(&mut self, offset: isize) {
+        let count = self.app_state.other_chats.len() + 1;
+        if count == 1 || !self.can_change_chat() {
+            return;
+        }
+        let index = (self.app_state.chat_index as isize + offset).rem_euclid(count as isize);
+        self.app_state.switch_chat(index as usize);
+    }
+
+    /// Renames the chat shown, as asked for by `/rename`.
+    fn rename_chat(&mut self, name: &str) {
+        if name.is_empty() {
+            self.app_state.status_message =
+                Some("Give the chat a name, as in /rename <name>".to_string());
+            return;
+        }
+        self.app_state.chat.name = name.to_string();
+        self.app_state.save_session();
+    }
+
+    /// Handles keys while the list of chats is open.
+    async fn handle_chat_list_key(&mut self, key: KeyEvent) {
+        let Some(selected) = self.app_state.chat_list else {
+            return;
+        };
+        let last = self.app_state.other_chats.len();
+        match key.code {
+            KeyCode::Up => self.app_state.chat_list = Some(selected.saturating_sub(1)),
+            KeyCode::Down => self.app_state.chat_list = Some((selected + 1).min(last)),
+            KeyCode::Enter => {
+                self.app_state.chat_list = None;
+                if self.can_change_chat() {
+                    self.app_state.switch_chat(selected);
+                }
+            }
+            KeyCode::Esc => self.app_state.chat_list = None,
+            _ => match self
+                .app_state
+                .keymap
+                .contextual(key, &[Command::NewChat, Command::CloseChat])
+            {
+                Some(Command::NewChat) => {
+                    self.app_state.chat_list = None;
+                    self.new_chat(self.app_state.chat.role, "").await;
+                }
+                Some(_) if self.can_change_chat() => {
+                    self.app_state.switch_chat(selected);
+                    self.app_state.close_chat();
+                    self.app_state.chat_list = Some(self.app_state.chat_index);
+                }
+                _ => {}
+            },
+        }
+    }
+
+    /// Switches to `model` for the next answers, or opens the model picker without one. The models to pick from
+    /// are those in the config, or else whatever the provider has.
+    fn change_model(&mut self, model: &str) {
+        if !model.is_empty() {
+            self.app_state.chat.parameters.model = model.to_string();
+            self.app_state.status_message = Some(format!("Switched to {}", model));
+            return;
+        }
+        let mut models = self.app_state.models.clone();
+        if models.is_empty() {
+            self.action_sender.send(Action::ListModels).unwrap();
+        } else if !models.contains(&self.app_state.chat.parameters.model) {
+            models.insert(0, self.app_state.chat.parameters.model.clone());
+        }
+        let selected = models
+            .iter()
+            .position(|model| *model == self.app_state.chat.parameters.model)
+            .unwrap_or_default();
+        self.app_state.model_picker = Some(ModelPicker { models, selected });
+    }
+
+    /// Sets the temperature for the next answers, as asked for by `/temperature`, or shows what it is.
+    fn change_temperature(&mut self, arguments: &str) {
+        let parameters = &mut self.app_state.chat.parameters;
+        self.app_state.status_message = Some(match arguments {
+            "" => match parameters.temperature {
+                Some(temperature) => format!("The temperature is {}", temperature),
+                None => "The temperature is the default of the API".to_string(),
+            },
+            "default" => {
+                parameters.temperature = None;
+                "The temperature is back to the default of the API".to_string()
+            }
+            arguments => match parse_temperature(arguments) {
+                Ok(temperature) => {
+                    parameters.temperature = Some(temperature);
+                    format!("The temperature is {}", temperature)
+                }
+                Err(err) => err,
+            },
+        });
+    }
+
+    /// Limits how long the next answers can be, as asked for by `/max-tokens`, or shows what the limit is.
+    fn change_max_tokens(&mut self, arguments: &str) {
+        let parameters = &mut self.app_state.chat.parameters;
+        self.app_state.status_message = Some(match arguments {
+            "" => match parameters.max_tokens {
+                Some(max_tokens) => format!("Answers can be up to {} tokens", max_tokens),
+                None => "Answers can be as long as the model allows".to_string(),
+            },
+            "none" => {
+                parameters.max_tokens = None;
+                "Answers can be as long as the model allows".to_string()
+            }
+            arguments => match arguments.parse::<u32>() {
+                Ok(max_tokens) if max_tokens > 0 => {
+                    parameters.max_tokens = Some(max_tokens);
+                    format!("Answers can be up to {} tokens", max_tokens)
+                }
+                _ => format!(
+                    "The limit should be a number of tokens or none, not {}",
+                    arguments
+                ),
+            },
+        });
+    }
+
+    /// Writes the conversation to a file, as asked for by `/export`.
+    fn export_conversation(&mut self, arguments: &str) {
+        let options = match ExportOptions::parse(arguments) {
+            Ok(options) => options,
+            Err(err) => {
+                self.app_state.status_message = Some(err);
+                return;
+            }
+        };
+        let messages = self.app_state.chat.conversation.messages();
+        if messages.is_empty() {
+            self.app_state.status_message = Some("There is nothing to export yet".to_string());
+            return;
+        }
+        let result = export::export(
+            &options,
+            self.app_state.chat.role,
+            &self.app_state.chat.parameters.model,
+            &messages,
+            &Total::of(self.app_state.chat.conversation.usage()),
+        );
+        self.app_state.status_message = Some(match result {
+            Ok(path) => format!("Exported the conversation to {}", path.display()),
+            Err(err) => {
+                tracing::warn!("Failed to export conversation: {:?}", err);
+                format!("Failed to export: {}", err)
+            }
+        });
+    }
+
+    /// Picks up the most recent session that isn't open, in a new chat.
+    fn resume_session(&mut self) {
+        if !self.can_change_chat() {
+            return;
+        }
+        let open: Vec<&Path> = self
+            .app_state
+            .chats()
+            .into_iter()
+            .map(|chat| chat.session_path.as_path())
+            .collect();
+        let Some(path) = session::latest(&open) else {
+            self.app_state.status_message = Some("There is no session to resume".to_string());
+            return;
+        };
+        match session::load(&path) {
+            Ok(session) => {
+                let chat =
+                    Chat::resume(session, path, self.app_state.chat.parameters.clone(), None);
+                self.app_state.open_chat(chat);
+            }
+            Err(err) => {
+                tracing::warn!("Failed to load session {:?}: {:?}", path, err);
+                self.app_state.status_message = Some(format!("Failed to resume session: {}", err));
+            }
+        }
+    }
+
+    /// The code of the selected code block, if any.
+    fn selected_code(&self) -> Option<String> {
+        let selected = self.app_state.selected_code_block?;
+        self.app_state
+            .code_blocks()
+            .into_iter()
+            .nth(selected)
+            .map(|code_block| code_block.code)
+    }
+
+    /// Inserts, runs or copies the selected code block, as `command` says.
+    async fn use_code_block(&mut self, command: Command, bracketed_paste: bool) {
+        let Some(code) = self.selected_code() else {
+            let mut keys: Vec<String> = [Command::PreviousCodeBlock, Command::NextCodeBlock]
+                .into_iter()
+                .filter_map(|command| {
+                    self.app_state
+                        .keymap
+                        .describe(command, &self.app_state.current_mode)
+                })
+                .collect();
+            keys.push("<ALT>1-9".to_string());
+            self.app_state.status_message = Some(format!(
+                "Select a code block first, with {}",
+                keys.join(", ")
+            ));
+            return;
+        };
+        match command {
+            Command::InsertCode => {
+                self.insert_into_terminal(&code, bracketed_paste).await;
+                self.app_state.current_mode = Mode::Terminal;
+            }
+            Command::RunCode => self.app_state.pending_run = Some(code),
+            _ => {
+                self.app_state.status_message = Some(match clipboard::copy(&code) {
+                    Ok(()) => "Copied to the clipboard".to_string(),
+                    Err(err) => format!("Failed to copy: {}", err),
+                })
+            }
+        }
     }
 }
 
 impl UiService {
     pub fn new(
         action_sender: UnboundedSender<Action>,
+        event_sender: UnboundedSender<Event>,
         app_state: AppState,
         terminal: &mut Terminal<CrosstermBackend<Stdout>>,
         terminal_sender: Sender<Bytes>,
@@ -441,9 +1955,9 @@ impl UiService {
 
         Self {
             action_sender,
+            event_sender,
             app_state,
             terminal_sender,
-            gpt_role: "".into(),
         }
     }
 
@@ -457,3 +1971,15 @@ impl UiService {
         terminal.show_cursor().unwrap();
     }
 }
+
+/// Returns a rect of at most `width` x `height`, centered inside `area`.
+fn centered_rect(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::Chat, conversation::Conversation, file_utils, services::chat_service::Role,
+};
+
+/// A saved conversation, along with the name of its chat, and the role and model the AI had in it.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    // Sessions saved before there were several chats don't have a name or a model
+    #[serde(default)]
+    pub name: Option<String>,
+    pub role: Role,
+    #[serde(default)]
+    pub model: Option<String>,
+    pub conversation: Conversation,
+}
+
+// What gets written, so saving doesn't need to clone the conversation
+#[derive(Serialize)]
+struct SessionRef<'a> {
+    name: &'a str,
+    role: Role,
+    model: &'a str,
+    conversation: &'a Conversation,
+}
+
+/// Where sessions are saved, in the user's data directory.
+pub fn directory() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("terminal-ai-ops")
+        .join("sessions")
+}
+
+/// A path for a new session, named after when it was started. Chats can be opened in quick succession, so this
+/// is in milliseconds.
+pub fn new_path() -> PathBuf {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    directory().join(format!("session-{}.json", started))
+}
+
+/// Saves the conversation of the chat to its session file.
+pub fn save(chat: &Chat) -> io::Result<()> {
+    let json = serde_json::to_string(&SessionRef {
+        name: &chat.name,
+        role: chat.role,
+        model: &chat.parameters.model,
+        conversation: &chat.conversation,
+    })?;
+    // Saved after every answer, so quitting or a crash mid-save mustn't cost the whole conversation
+    file_utils::write_atomically(&chat.session_path, json)
+}
+
+pub fn load(path: &Path) -> io::Result<Session> {
+    let json = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// The most recently saved session, other than those `open` already.
+pub fn latest(open: &[&Path]) -> Option<PathBuf> {
+    fs::read_dir(directory())
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            !open.contains(&path.as_path()) && path.extension().is_some_and(|ext| ext == "json")
+        })
+        .max_by_key(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+}
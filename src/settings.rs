@@ -0,0 +1,97 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    app_state::PaneLayout,
+    keymap::KeySettings,
+    knowledge::KnowledgeSettings,
+    services::chat_service::{Provider, Role},
+    tracing::LogRotation,
+    usage::Price,
+};
+
+/// Settings from the config file, which is `config.toml` in the user's config directory unless another one is given.
+/// Everything is optional, and can be overridden on the command line.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub provider: Option<Provider>,
+    pub model: Option<String>,
+    // The models to pick from with /model, instead of asking the provider, which Azure can't answer
+    pub models: Vec<String>,
+    // From 0 to 2, where higher makes the answers more random
+    pub temperature: Option<f32>,
+    // The longest an answer can be, in tokens
+    pub max_tokens: Option<u32>,
+    // Ask for the token usage with every answer. Older Azure API versions reject this, so on Azure it is off unless
+    // turned on, and the usage is an estimate.
+    pub stream_usage: Option<bool>,
+    // The role the AI starts in: general, network or linux
+    pub role: Option<Role>,
+    // The shell to run in the terminal, instead of the user's default shell
+    pub shell: Option<String>,
+    // error, warn, info, debug or trace, or directives like RUST_LOG takes. RUST_LOG wins if it is set.
+    pub log_level: Option<String>,
+    // A single file to append the log to, instead of rotated files in the log directory
+    pub log_file: Option<PathBuf>,
+    pub log_dir: Option<PathBuf>,
+    pub log_rotation: Option<LogRotation>,
+    // How many rotated log files to keep
+    pub log_max_files: Option<usize>,
+    // Never log prompts, answers, keys or terminal output, even at trace level
+    pub private_logs: Option<bool>,
+    pub layout: Option<PaneLayout>,
+    // dark, light, high-contrast, or the name or path of a theme file
+    pub theme: Option<String>,
+    // Overrides whether the theme uses emoji
+    pub emoji: Option<bool>,
+    // Watch the terminal for failed commands, and offer to explain them
+    pub watch_errors: Option<bool>,
+    pub keys: KeySettings,
+    // The price of models per million tokens, like `gpt-4o = { input = 2.5, output = 10.0 }`, on top of the
+    // prices we know
+    pub prices: HashMap<String, Price>,
+    // Local docs to search for what to add to the prompt
+    pub knowledge: KnowledgeSettings,
+}
+
+/// Where the config file is, unless another one is given.
+pub fn default_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("terminal-ai-ops")
+        .join("config.toml")
+}
+
+impl Settings {
+    /// Loads the settings from `path`, or from the default path if there is none.
+    /// There doesn't have to be a config file at the default path, but there does at a path that was asked for.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let default_path = default_path();
+        let contents = match (path, fs::read_to_string(path.unwrap_or(&default_path))) {
+            (_, Ok(contents)) => contents,
+            (None, Err(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            (path, Err(err)) => {
+                return Err(format!(
+                    "Failed to read {}: {}",
+                    path.unwrap_or(&default_path).display(),
+                    err
+                ))
+            }
+        };
+        toml::from_str(&contents).map_err(|err| {
+            format!(
+                "Failed to parse {}: {}",
+                path.unwrap_or(&default_path).display(),
+                err
+            )
+        })
+    }
+}
@@ -0,0 +1,114 @@
+/// A command typed into the chat, like `/clear`, instead of a question for the AI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlashCommand {
+    Clear,
+    New,
+    Network,
+    Linux,
+    Rename,
+    Chats,
+    Resume,
+    Attach,
+    Export,
+    Continue,
+    Model,
+    Temperature,
+    MaxTokens,
+    Reindex,
+    Help,
+}
+
+impl SlashCommand {
+    pub const ALL: [SlashCommand; 15] = [
+        SlashCommand::Clear,
+        SlashCommand::New,
+        SlashCommand::Network,
+        SlashCommand::Linux,
+        SlashCommand::Rename,
+        SlashCommand::Chats,
+        SlashCommand::Resume,
+        SlashCommand::Attach,
+        SlashCommand::Export,
+        SlashCommand::Continue,
+        SlashCommand::Model,
+        SlashCommand::Temperature,
+        SlashCommand::MaxTokens,
+        SlashCommand::Reindex,
+        SlashCommand::Help,
+    ];
+
+    /// The name, which is typed after the slash.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SlashCommand::Clear => "clear",
+            SlashCommand::New => "new",
+            SlashCommand::Network => "network",
+            SlashCommand::Linux => "linux",
+            SlashCommand::Rename => "rename",
+            SlashCommand::Chats => "chats",
+            SlashCommand::Resume => "resume",
+            SlashCommand::Attach => "attach",
+            SlashCommand::Export => "export",
+            SlashCommand::Continue => "continue",
+            SlashCommand::Model => "model",
+            SlashCommand::Temperature => "temperature",
+            SlashCommand::MaxTokens => "max-tokens",
+            SlashCommand::Reindex => "reindex",
+            SlashCommand::Help => "help",
+        }
+    }
+
+    /// How to type the command, with its arguments.
+    pub fn usage(&self) -> &'static str {
+        match self {
+            SlashCommand::Export => "/export [md|json|html] [--no-terminal] [path]",
+            SlashCommand::Model => "/model [name]",
+            SlashCommand::Temperature => "/temperature [0-2|default]",
+            SlashCommand::MaxTokens => "/max-tokens [tokens|none]",
+            SlashCommand::Clear => "/clear",
+            SlashCommand::New => "/new [name]",
+            SlashCommand::Network => "/network [name]",
+            SlashCommand::Linux => "/linux [name]",
+            SlashCommand::Rename => "/rename <name>",
+            SlashCommand::Chats => "/chats",
+            SlashCommand::Resume => "/resume",
+            SlashCommand::Attach => "/attach <path>",
+            SlashCommand::Continue => "/continue",
+            SlashCommand::Reindex => "/reindex",
+            SlashCommand::Help => "/help",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            SlashCommand::Clear => "Start the chat over",
+            SlashCommand::New => "Open a new chat next to the others",
+            SlashCommand::Network => "Open a new chat with a network engineer",
+            SlashCommand::Linux => "Open a new chat with a Linux engineer",
+            SlashCommand::Rename => "Rename the chat",
+            SlashCommand::Chats => "List the open chats, to switch between them",
+            SlashCommand::Resume => "Resume the previous session in a new chat",
+            SlashCommand::Attach => "Attach a file to the next message, <TAB> completes the path",
+            SlashCommand::Export => "Export the conversation",
+            SlashCommand::Continue => "Continue the last answer where it was cut off",
+            SlashCommand::Model => "Switch to another model, or pick one from a list",
+            SlashCommand::Temperature => "Show or set how random the answers are",
+            SlashCommand::MaxTokens => "Show or set how long an answer can be",
+            SlashCommand::Reindex => {
+                "Index the docs that changed, to search them with every question"
+            }
+            SlashCommand::Help => "Show the keys and commands",
+        }
+    }
+
+    /// Splits what the user typed into a command and its arguments, if it is a command.
+    /// Anything else that starts with a slash, like a path, is left for the AI.
+    pub fn parse(input: &str) -> Option<(Self, &str)> {
+        let input = input.strip_prefix('/')?;
+        let (name, arguments) = input.split_once(' ').unwrap_or((input, ""));
+        let command = Self::ALL
+            .into_iter()
+            .find(|command| command.name() == name)?;
+        Some((command, arguments.trim()))
+    }
+}
@@ -1,90 +1,490 @@
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
+use std::error::Error;
 use std::io::Stdout;
 use std::{
-    io::{BufWriter, Write},
-    sync::Arc,
+    io::{BufWriter, ErrorKind, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
-use tokio::sync::mpsc::{self, Sender};
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::mpsc::{self, Sender, UnboundedSender};
+use tokio::sync::{Mutex, Notify, RwLock};
 
 use bytes::Bytes;
-use portable_pty::{CommandBuilder, NativePtySystem, PtySize, PtySystem};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use portable_pty::{CommandBuilder, MasterPty, NativePtySystem, PtySize, PtySystem};
 use tokio::task;
 
+use crate::app_state::PaneLayout;
+use crate::error_watch::{ErrorWatcher, Watched};
+use crate::recording::Recorder;
+use crate::services::event_service::Event;
+
+/// How many reads from the pseudo terminal can be queued up before the reader thread has to wait.
+const READ_QUEUE_SIZE: usize = 64;
+/// The most output we feed the parser while holding its lock.
+const MAX_BATCH_SIZE: usize = 256 * 1024;
+/// How much of the latest terminal output is kept around to send to the AI.
+pub const MAX_TERMINAL_CONTEXT: usize = 64 * 1024;
+/// How often throughput stats are logged while the terminal produces output.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Errors from setting up the pseudo terminal, such as failing to open it or to spawn the shell.
+pub type TerminalResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// What is done with the output of the pseudo terminal, besides parsing it and keeping the end of it as context.
+#[derive(Default)]
+pub struct OutputOptions {
+    // Records the output, also after restarting the shell
+    pub recorder: Option<Arc<Mutex<Recorder>>>,
+    // Gets a copy of the raw output
+    pub echo_sender: Option<UnboundedSender<Bytes>>,
+    // Looks for commands that failed, and sends them as `Event::CommandFailed`
+    pub watch_errors: bool,
+}
+
+/// Changes the size of the pseudo terminal while the shell runs in it.
+#[derive(Clone)]
+pub struct PtySizer(Arc<std::sync::Mutex<Box<dyn MasterPty + Send>>>);
+
+impl PtySizer {
+    /// Makes the pseudo terminal `cols` x `rows`, which tells the shell and whatever runs in it to redraw to fit.
+    pub fn resize(&self, cols: u16, rows: u16) {
+        let Ok(master) = self.0.lock() else {
+            return;
+        };
+        if let Err(err) = master.resize(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        }) {
+            tracing::warn!("Failed to resize the terminal: {:?}", err);
+        }
+    }
+}
+
+/// Spawns `shell`, or the user's shell if it is `None`, in a pseudo terminal the size of the terminal pane.
+///
+/// Returns the parser holding the screen of the pseudo terminal, a sender used to write bytes to it, and what
+/// resizes it.
+/// `terminal_updated` is notified whenever new output has been parsed, and `options` says what else is done with
+/// the output.
+/// Once the shell is running, nothing in here panics: if the shell exits an `Event::ChildExited` is sent,
+/// and any I/O error on the pseudo terminal is sent as an `Event::TerminalError`.
 pub fn new(
     terminal: &Terminal<CrosstermBackend<Stdout>>,
+    shell: Option<&str>,
+    layout: PaneLayout,
     terminal_context: Arc<Mutex<String>>,
-) -> (Arc<RwLock<vt100::Parser>>, Sender<Bytes>) {
+    event_sender: UnboundedSender<Event>,
+    terminal_updated: Arc<Notify>,
+    options: OutputOptions,
+) -> TerminalResult<(Arc<RwLock<vt100::Parser>>, Sender<Bytes>, PtySizer)> {
+    let cmd = match shell {
+        Some(shell) => CommandBuilder::new(shell),
+        None => CommandBuilder::new_default_prog(),
+    };
+    spawn(
+        cmd,
+        pane_size(terminal, layout)?,
+        terminal_context,
+        event_sender,
+        terminal_updated,
+        options,
+    )
+}
+
+/// Runs `cmd` in a pseudo terminal of `cols` x `rows`, in the current directory. Works like `new`.
+pub fn spawn(
+    mut cmd: CommandBuilder,
+    (cols, rows): (u16, u16),
+    terminal_context: Arc<Mutex<String>>,
+    event_sender: UnboundedSender<Event>,
+    terminal_updated: Arc<Notify>,
+    options: OutputOptions,
+) -> TerminalResult<(Arc<RwLock<vt100::Parser>>, Sender<Bytes>, PtySizer)> {
     let pty_system = NativePtySystem::default();
-    let cwd = std::env::current_dir().unwrap();
-    let mut cmd = CommandBuilder::new_default_prog();
+    let cwd = std::env::current_dir()?;
     cmd.cwd(cwd);
 
-    let adjusted_width = (terminal.size().unwrap().width as f32 * 0.57).floor() as u16;
-    let pair = pty_system
-        .openpty(PtySize {
-            rows: terminal.size().unwrap().height - 5,
-            cols: adjusted_width,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .unwrap();
+    let pair = pty_system.openpty(PtySize {
+        rows,
+        cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    })?;
+    let mut child = pair.slave.spawn_command(cmd)?;
+
     // Wait for the child to complete
-    task::spawn_blocking(move || {
-        let mut child = pair.slave.spawn_command(cmd).unwrap();
-        let _child_exit_status = child.wait().unwrap();
-        drop(pair.slave);
-    });
-
-    let mut reader = pair.master.try_clone_reader().unwrap();
-    let adjusted_width = (terminal.size().unwrap().width as f32 * 0.57).floor() as u16;
-    let parser = Arc::new(RwLock::new(vt100::Parser::new(
-        terminal.size().unwrap().height - 5,
-        adjusted_width,
-        0,
-    )));
+    {
+        let event_sender = event_sender.clone();
+        task::spawn_blocking(move || {
+            match child.wait() {
+                Ok(exit_status) => {
+                    tracing::info!("Shell exited: {}", exit_status);
+                    let _ = event_sender.send(Event::ChildExited(exit_status));
+                }
+                Err(err) => {
+                    tracing::warn!("Failed waiting for shell: {:?}", err);
+                    let _ = event_sender.send(Event::TerminalError(err.to_string()));
+                }
+            }
+            // Dropping the slave makes the reader below hit EOF (or EIO) once the exit has been reported
+            drop(pair.slave);
+        });
+    }
+
+    let mut reader = pair.master.try_clone_reader()?;
+    let parser = Arc::new(RwLock::new(vt100::Parser::new(rows, cols, 0)));
+
+    // Reading from the pseudo terminal blocks, so it gets a thread of its own instead of occupying a tokio worker.
+    // The channel is bounded, so a shell producing output faster than we can parse it is slowed down,
+    // instead of us buffering it all in memory.
+    let (output_sender, mut output_receiver) = mpsc::channel::<(Instant, Vec<u8>)>(READ_QUEUE_SIZE);
+    {
+        let event_sender = event_sender.clone();
+        thread::Builder::new()
+            .name("pty-reader".into())
+            .spawn(move || {
+                let mut buf = [0u8; 8192]; // Temporary buffer for each read operation
+
+                loop {
+                    let size = match reader.read(&mut buf) {
+                        Ok(size) => size,
+                        Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                        Err(err) => {
+                            tracing::warn!("Failed reading from terminal: {:?}", err);
+                            let _ = event_sender.send(Event::TerminalError(err.to_string()));
+                            break;
+                        }
+                    };
+
+                    if size == 0 {
+                        break; // Exit loop when EOF is reached
+                    }
+
+                    if output_sender
+                        .blocking_send((Instant::now(), buf[..size].to_vec()))
+                        .is_err()
+                    {
+                        break; // Nobody is processing the output anymore
+                    }
+                }
+            })?;
+    }
+
+    // Set once something has been typed into the terminal, after which the output is from commands
+    let typed = Arc::new(AtomicBool::new(false));
     {
         let parser = parser.clone();
+        let event_sender = event_sender.clone();
+        let typed = typed.clone();
+        let OutputOptions {
+            recorder,
+            echo_sender,
+            watch_errors,
+        } = options;
         task::spawn(async move {
-            let mut buf = [0u8; 8192]; // Temporary buffer for each read operation
-
-            loop {
-                let size = reader.read(&mut buf).unwrap();
+            let mut stats = ThroughputStats::new();
+            let mut error_watcher = watch_errors.then(ErrorWatcher::new);
+            let mut batch = Vec::new();
+            let mut incomplete = Vec::new();
 
-                if size == 0 {
-                    break; // Exit loop when EOF is reached
+            while let Some((read_at, chunk)) = output_receiver.recv().await {
+                // Drain whatever else is queued up, so a burst of output costs one lock instead of one per read
+                batch.extend_from_slice(&chunk);
+                while batch.len() < MAX_BATCH_SIZE {
+                    match output_receiver.try_recv() {
+                        Ok((_, chunk)) => batch.extend_from_slice(&chunk),
+                        Err(_) => break,
+                    }
                 }
 
-                // Process the current batch of data
-                let mut parser = parser.write().await;
-                parser.process(&buf[..size]);
+                let alternate_screen = {
+                    let mut parser = parser.write().await;
+                    parser.process(&batch);
+                    parser.screen().alternate_screen()
+                };
+                let output = decode_output(&mut incomplete, &batch);
+                append_terminal_context(&mut *terminal_context.lock().await, &output);
+                if let Some(error_watcher) = &mut error_watcher {
+                    if typed.load(Ordering::Relaxed) {
+                        error_watcher.typed();
+                    }
+                    for watched in error_watcher.watch(&output, alternate_screen) {
+                        let _ = event_sender.send(match watched {
+                            Watched::Failed(failure) => Event::CommandFailed(failure),
+                            Watched::Succeeded => Event::CommandSucceeded,
+                        });
+                    }
+                }
 
-                // Convert the newly read data to a String
-                let new_data = String::from_utf8_lossy(&buf[..size]);
+                terminal_updated.notify_one();
 
-                // Append the new data to terminal_context
-                let mut terminal_context = terminal_context.lock().await;
-                terminal_context.push_str(&new_data);
+                if let Some(recorder) = &recorder {
+                    if let Err(err) = recorder.lock().await.record(read_at, &batch) {
+                        tracing::warn!("Failed recording terminal output: {:?}", err);
+                    }
+                }
+                if let Some(echo_sender) = &echo_sender {
+                    let _ = echo_sender.send(Bytes::copy_from_slice(&batch));
+                }
 
-                // Update terminal context with screen contents if needed
-                // If you also want to update the screen contents, you can do it here
-                // Example: append `parser.screen().contents()` to `terminal_context` as well
+                stats.record(batch.len(), read_at.elapsed());
+                batch.clear();
             }
         });
     }
 
     let (terminal_sender, mut terminal_receiver) = mpsc::channel::<Bytes>(32);
-    let mut writer = BufWriter::new(pair.master.take_writer().unwrap());
+    let mut writer = BufWriter::new(pair.master.take_writer()?);
+    let pty_sizer = PtySizer(Arc::new(std::sync::Mutex::new(pair.master)));
 
-    // Drop writer on purpose
-    tokio::spawn(async move {
-        while let Some(bytes) = terminal_receiver.recv().await {
-            writer.write_all(&bytes).unwrap();
-            writer.flush().unwrap();
+    // Writing blocks as well, so it also gets its own thread. It holds on to the master, which is dropped once
+    // the terminal_sender is dropped, and nothing resizes it anymore.
+    let master = pty_sizer.clone();
+    thread::Builder::new()
+        .name("pty-writer".into())
+        .spawn(move || {
+            while let Some(bytes) = terminal_receiver.blocking_recv() {
+                if let Err(err) = writer.write_all(&bytes).and_then(|_| writer.flush()) {
+                    tracing::warn!("Failed writing to terminal: {:?}", err);
+                    let _ = event_sender.send(Event::TerminalError(err.to_string()));
+                    break;
+                }
+                typed.store(true, Ordering::Relaxed);
+            }
+            drop(master);
+        })?;
+
+    Ok((parser, terminal_sender, pty_sizer))
+}
+
+/// The columns and rows of the terminal pane, which is what the shell gets to draw on.
+pub fn pane_size(
+    terminal: &Terminal<CrosstermBackend<Stdout>>,
+    layout: PaneLayout,
+) -> TerminalResult<(u16, u16)> {
+    let size = terminal.size()?;
+    Ok(layout.terminal_size(size.width, size.height))
+}
+
+/// Appends output to the terminal context, only keeping the last `MAX_TERMINAL_CONTEXT` bytes of it.
+pub(crate) fn append_terminal_context(terminal_context: &mut String, new_data: &str) {
+    terminal_context.push_str(new_data);
+    if terminal_context.len() > MAX_TERMINAL_CONTEXT {
+        let mut cut = terminal_context.len() - MAX_TERMINAL_CONTEXT;
+        while !terminal_context.is_char_boundary(cut) {
+            cut += 1;
+        }
+        terminal_context.drain(..cut);
+    }
+}
+
+/// Decodes output read from the terminal, after the start of a character that `incomplete` held back from the last
+/// read. A character split between two reads is held back in turn, until the rest of it arrives, rather than being
+/// replaced. Invalid bytes are replaced.
+pub(crate) fn decode_output(incomplete: &mut Vec<u8>, output: &[u8]) -> String {
+    incomplete.extend_from_slice(output);
+    // A character is at most 4 bytes, so only the last 3 can be the start of one that isn't complete
+    let mut complete = incomplete.len();
+    for back in 1..=incomplete.len().min(3) {
+        let byte = incomplete[incomplete.len() - back];
+        if byte & 0b1100_0000 != 0b1000_0000 {
+            let length = match byte {
+                0b1100_0000..=0b1101_1111 => 2,
+                0b1110_0000..=0b1110_1111 => 3,
+                0b1111_0000..=0b1111_0111 => 4,
+                _ => 1,
+            };
+            if length > back {
+                complete = incomplete.len() - back;
+            }
+            break;
+        }
+    }
+    let rest = incomplete.split_off(complete);
+    let text = String::from_utf8_lossy(incomplete).into_owned();
+    *incomplete = rest;
+    text
+}
+
+/// Keeps track of how much terminal output we process, and how long it waited before being parsed.
+/// Logged at debug level once per `STATS_INTERVAL`, so slow rendering of heavy output can be measured.
+struct ThroughputStats {
+    started: Instant,
+    bytes: usize,
+    batches: usize,
+    max_latency: Duration,
+}
+
+impl ThroughputStats {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            bytes: 0,
+            batches: 0,
+            max_latency: Duration::ZERO,
+        }
+    }
+
+    fn record(&mut self, bytes: usize, latency: Duration) {
+        self.bytes += bytes;
+        self.batches += 1;
+        self.max_latency = self.max_latency.max(latency);
+
+        let elapsed = self.started.elapsed();
+        if elapsed >= STATS_INTERVAL {
+            tracing::debug!(
+                "Terminal output: {} bytes in {} batches over {:?} ({:.0} KiB/s), max latency {:?}",
+                self.bytes,
+                self.batches,
+                elapsed,
+                self.bytes as f64 / 1024.0 / elapsed.as_secs_f64(),
+                self.max_latency,
+            );
+            *self = Self::new();
+        }
+    }
+}
+
+/// The bytes a terminal sends to the program running in it for a key, if there are any.
+/// With `application_cursor`, which programs like vim turn on, the arrow keys send different sequences.
+pub fn encode_key(key: KeyEvent, application_cursor: bool) -> Option<Vec<u8>> {
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+    let control = key.modifiers.contains(KeyModifiers::CONTROL);
+    // Keys like the arrows carry their modifiers as a parameter, as in ESC[1;5A for <CTRL>↑
+    let modifier_parameter =
+        1 + key.modifiers.contains(KeyModifiers::SHIFT) as u8 + 2 * alt as u8 + 4 * control as u8;
+    let cursor = |letter: char| match (modifier_parameter, application_cursor) {
+        (1, true) => format!("\x1bO{}", letter).into_bytes(),
+        (1, false) => format!("\x1b[{}", letter).into_bytes(),
+        (parameter, _) => format!("\x1b[1;{}{}", parameter, letter).into_bytes(),
+    };
+    let tilde = |number: u8| match modifier_parameter {
+        1 => format!("\x1b[{}~", number).into_bytes(),
+        parameter => format!("\x1b[{};{}~", number, parameter).into_bytes(),
+    };
+
+    let bytes = match key.code {
+        KeyCode::Char(char) if control => {
+            let control_byte = match char.to_ascii_lowercase() {
+                letter @ 'a'..='z' => letter as u8 & 0x1f,
+                '@' | ' ' | '2' => 0,
+                '[' | '3' => 0x1b,
+                '\\' | '4' => 0x1c,
+                ']' | '5' => 0x1d,
+                '^' | '6' => 0x1e,
+                '_' | '-' | '7' => 0x1f,
+                '?' | '8' => 0x7f,
+                _ => return None,
+            };
+            with_alt(alt, vec![control_byte])
         }
-        drop(pair.master);
-    });
+        KeyCode::Char(char) => with_alt(alt, char.to_string().into_bytes()),
+        KeyCode::Enter => with_alt(alt, vec![b'\r']),
+        KeyCode::Tab => with_alt(alt, vec![b'\t']),
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Backspace => with_alt(alt, vec![if control { 0x08 } else { 0x7f }]),
+        KeyCode::Esc => with_alt(alt, vec![0x1b]),
+        KeyCode::Up => cursor('A'),
+        KeyCode::Down => cursor('B'),
+        KeyCode::Right => cursor('C'),
+        KeyCode::Left => cursor('D'),
+        KeyCode::Home => cursor('H'),
+        KeyCode::End => cursor('F'),
+        KeyCode::Insert => tilde(2),
+        KeyCode::Delete => tilde(3),
+        KeyCode::PageUp => tilde(5),
+        KeyCode::PageDown => tilde(6),
+        KeyCode::F(number @ 1..=4) => match modifier_parameter {
+            1 => format!("\x1bO{}", (b'P' + number - 1) as char).into_bytes(),
+            parameter => {
+                format!("\x1b[1;{}{}", parameter, (b'P' + number - 1) as char).into_bytes()
+            }
+        },
+        KeyCode::F(number @ 5..=12) => tilde([15, 17, 18, 19, 20, 21, 23, 24][number as usize - 5]),
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+/// <ALT> is sent as an escape before the key.
+fn with_alt(alt: bool, bytes: Vec<u8>) -> Vec<u8> {
+    match alt {
+        true => [vec![0x1b], bytes].concat(),
+        false => bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    (parser, terminal_sender)
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn decodes_a_character_split_between_reads() {
+        let mut incomplete = Vec::new();
+        // "é" is 0xc3 0xa9, and "€" is 0xe2 0x82 0xac
+        assert_eq!(decode_output(&mut incomplete, b"caf\xc3"), "caf");
+        assert_eq!(incomplete, b"\xc3");
+        assert_eq!(decode_output(&mut incomplete, b"\xa9 \xe2"), "é ");
+        assert_eq!(decode_output(&mut incomplete, b"\x82"), "");
+        assert_eq!(decode_output(&mut incomplete, b"\xac!"), "€!");
+        assert!(incomplete.is_empty());
+    }
+
+    #[test]
+    fn replaces_invalid_bytes() {
+        let mut incomplete = Vec::new();
+        assert_eq!(
+            decode_output(&mut incomplete, b"a\xffb\x80"),
+            "a\u{fffd}b\u{fffd}"
+        );
+        assert!(incomplete.is_empty());
+        // A start that isn't followed by the rest of its character is replaced once more output shows that
+        decode_output(&mut incomplete, b"\xe2");
+        assert_eq!(decode_output(&mut incomplete, b"x"), "\u{fffd}x");
+    }
+
+    #[test]
+    fn encodes_arrow_keys_by_cursor_mode() {
+        let up = key(KeyCode::Up, KeyModifiers::NONE);
+        assert_eq!(encode_key(up, false).as_deref(), Some(&b"\x1b[A"[..]));
+        assert_eq!(encode_key(up, true).as_deref(), Some(&b"\x1bOA"[..]));
+        let left = key(KeyCode::Left, KeyModifiers::NONE);
+        assert_eq!(encode_key(left, false).as_deref(), Some(&b"\x1b[D"[..]));
+        assert_eq!(encode_key(left, true).as_deref(), Some(&b"\x1bOD"[..]));
+
+        // With a modifier, an arrow sends the same in both modes
+        let control_right = key(KeyCode::Right, KeyModifiers::CONTROL);
+        assert_eq!(
+            encode_key(control_right, false).as_deref(),
+            Some(&b"\x1b[1;5C"[..])
+        );
+        assert_eq!(
+            encode_key(control_right, true).as_deref(),
+            Some(&b"\x1b[1;5C"[..])
+        );
+    }
+
+    #[test]
+    fn encodes_control_and_alt_keys() {
+        let control_c = key(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert_eq!(encode_key(control_c, false), Some(vec![0x03]));
+        let alt_x = key(KeyCode::Char('x'), KeyModifiers::ALT);
+        assert_eq!(encode_key(alt_x, false), Some(b"\x1bx".to_vec()));
+        assert_eq!(
+            encode_key(key(KeyCode::F(5), KeyModifiers::NONE), false),
+            Some(b"\x1b[15~".to_vec())
+        );
+    }
 }
@@ -0,0 +1,214 @@
+use std::{fs, path::PathBuf};
+
+use ratatui::style::Color;
+use serde::{Deserialize, Serialize};
+
+/// The colors of the TUI, and whether it uses emoji.
+///
+/// Besides the built-in `dark`, `light` and `high-contrast` themes, a theme can be a TOML file with any of these
+/// fields, on top of the built-in theme named by `base` (dark unless given). Colors are names like `"light-blue"`,
+/// indexes like `"42"` or RGB like `"#ff8800"`.
+#[derive(Clone, Copy, Debug, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Theme {
+    // The border of the pane that doesn't have the focus
+    pub border: Color,
+    pub focused_border: Color,
+    // The border of the popup shown when the shell has exited
+    pub error_border: Color,
+    // The chat input while we wait for the AI
+    pub disabled: Color,
+    pub throbber: Color,
+    pub user_message: Color,
+    pub assistant_header: Color,
+    pub role_background: Color,
+    pub heading: Color,
+    // Links, list markers and keys in the help
+    pub accent: Color,
+    // Rules, quotes, table and code block borders, and other things that should stay in the background
+    pub muted: Color,
+    // Inline code, and code blocks in a language we can't highlight
+    pub code: Color,
+    // The parts of highlighted code
+    pub syntax_comment: Color,
+    pub syntax_string: Color,
+    // Numbers, and constants like `true` and `None`
+    pub syntax_constant: Color,
+    pub syntax_keyword: Color,
+    // Operators, and modifiers like `pub` and `static`
+    pub syntax_operator: Color,
+    pub syntax_function: Color,
+    pub syntax_type: Color,
+    // Tags, keys and attributes, in markup and config files
+    pub syntax_tag: Color,
+    pub syntax_variable: Color,
+    // Lines added and removed in a diff
+    pub syntax_inserted: Color,
+    pub syntax_deleted: Color,
+    pub selected_code_block: Color,
+    pub footer_text: Color,
+    pub footer_background: Color,
+    // The mode, role, model and tokens on the right of the footer
+    pub footer_status: Color,
+    // Some terminals render emoji badly, or at the wrong width
+    pub emoji: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            border: Color::Reset,
+            focused_border: Color::Cyan,
+            error_border: Color::Red,
+            disabled: Color::Gray,
+            throbber: Color::Gray,
+            user_message: Color::Yellow,
+            assistant_header: Color::LightGreen,
+            role_background: Color::DarkGray,
+            heading: Color::LightMagenta,
+            accent: Color::LightBlue,
+            muted: Color::DarkGray,
+            code: Color::LightYellow,
+            syntax_comment: Color::DarkGray,
+            syntax_string: Color::Green,
+            syntax_constant: Color::Magenta,
+            syntax_keyword: Color::Blue,
+            syntax_operator: Color::LightBlue,
+            syntax_function: Color::Cyan,
+            syntax_type: Color::Yellow,
+            syntax_tag: Color::Red,
+            syntax_variable: Color::LightRed,
+            syntax_inserted: Color::Green,
+            syntax_deleted: Color::Red,
+            selected_code_block: Color::Cyan,
+            footer_text: Color::Black,
+            footer_background: Color::Gray,
+            footer_status: Color::Cyan,
+            emoji: true,
+        }
+    }
+
+    /// For terminals with a light background, where the light colors of the dark theme are hard to read.
+    pub fn light() -> Self {
+        Self {
+            border: Color::Reset,
+            focused_border: Color::Blue,
+            error_border: Color::Red,
+            disabled: Color::DarkGray,
+            throbber: Color::DarkGray,
+            user_message: Color::Indexed(94),
+            assistant_header: Color::Green,
+            role_background: Color::Gray,
+            heading: Color::Magenta,
+            accent: Color::Blue,
+            muted: Color::Gray,
+            code: Color::Indexed(130),
+            syntax_comment: Color::DarkGray,
+            syntax_string: Color::Green,
+            syntax_constant: Color::Magenta,
+            syntax_keyword: Color::Blue,
+            syntax_operator: Color::Blue,
+            syntax_function: Color::Cyan,
+            syntax_type: Color::Indexed(130),
+            syntax_tag: Color::Red,
+            syntax_variable: Color::Indexed(124),
+            syntax_inserted: Color::Green,
+            syntax_deleted: Color::Red,
+            selected_code_block: Color::Blue,
+            footer_text: Color::White,
+            footer_background: Color::DarkGray,
+            footer_status: Color::Blue,
+            emoji: true,
+        }
+    }
+
+    /// Only the brightest colors, and no emoji.
+    pub fn high_contrast() -> Self {
+        Self {
+            border: Color::White,
+            focused_border: Color::LightYellow,
+            error_border: Color::LightRed,
+            disabled: Color::White,
+            throbber: Color::White,
+            user_message: Color::LightYellow,
+            assistant_header: Color::LightGreen,
+            role_background: Color::Blue,
+            heading: Color::LightMagenta,
+            accent: Color::LightCyan,
+            muted: Color::White,
+            code: Color::LightYellow,
+            syntax_comment: Color::White,
+            syntax_string: Color::LightGreen,
+            syntax_constant: Color::LightMagenta,
+            syntax_keyword: Color::LightBlue,
+            syntax_operator: Color::LightCyan,
+            syntax_function: Color::LightCyan,
+            syntax_type: Color::LightYellow,
+            syntax_tag: Color::LightRed,
+            syntax_variable: Color::LightRed,
+            syntax_inserted: Color::LightGreen,
+            syntax_deleted: Color::LightRed,
+            selected_code_block: Color::LightYellow,
+            footer_text: Color::Black,
+            footer_background: Color::White,
+            footer_status: Color::LightYellow,
+            emoji: false,
+        }
+    }
+
+    fn built_in(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "high-contrast" => Some(Self::high_contrast()),
+            _ => None,
+        }
+    }
+
+    /// Loads a built-in theme by its name, or a theme file. A name that isn't a path is looked for in the
+    /// `themes` directory next to the config file, so `theme = "solarized"` loads `themes/solarized.toml`.
+    pub fn load(name: &str) -> Result<Self, String> {
+        if let Some(theme) = Self::built_in(name) {
+            return Ok(theme);
+        }
+        let path = match name.ends_with(".toml") || name.contains(std::path::MAIN_SEPARATOR) {
+            true => PathBuf::from(name),
+            false => directory().join(format!("{}.toml", name)),
+        };
+        let contents = fs::read_to_string(&path)
+            .map_err(|err| format!("Failed to read the theme {}: {}", path.display(), err))?;
+        Self::parse(&contents)
+            .map_err(|err| format!("Failed to parse the theme {}: {}", path.display(), err))
+    }
+
+    /// Parses a theme file, filling in what it leaves out from its base theme.
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut overrides: toml::Table = toml::from_str(contents).map_err(|err| err.to_string())?;
+        let base = match overrides.remove("base") {
+            Some(toml::Value::String(base)) => {
+                Self::built_in(&base).ok_or_else(|| format!("Unknown base theme {}", base))?
+            }
+            Some(base) => return Err(format!("The base theme should be a name, not {}", base)),
+            None => Self::dark(),
+        };
+        let mut theme = toml::Table::try_from(base).map_err(|err| err.to_string())?;
+        theme.extend(overrides);
+        theme
+            .try_into()
+            .map_err(|err: toml::de::Error| err.to_string())
+    }
+}
+
+/// Where themes are looked for by name.
+fn directory() -> PathBuf {
+    crate::settings::default_path()
+        .parent()
+        .map(|config| config.join("themes"))
+        .unwrap_or_else(|| PathBuf::from("themes"))
+}
@@ -1,29 +1,117 @@
-use std::{fs, path::PathBuf};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use tracing::Level;
-use tracing_appender::{non_blocking, non_blocking::WorkerGuard};
+use serde::Deserialize;
+use tracing_appender::{
+    non_blocking,
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-pub fn init_tracing() -> WorkerGuard {
-    let log_file_path = get_log_file_path();
-    let log_file = fs::File::create(log_file_path).unwrap();
-    let (non_blocking, guard) = non_blocking(log_file);
+use crate::{
+    debug_log::{DebugLayer, DebugLog},
+    settings::Settings,
+};
 
-    tracing_subscriber::fmt()
-        .with_writer(non_blocking)
-        .with_max_level(Level::DEBUG)
-        .init();
-    guard
+/// The target of log events that hold what the user typed, sent or got back, like prompts, answers and keys.
+/// These are logged at debug and trace level only, and never with `private_logs`.
+pub const CONTENT: &str = "terminal_ai_ops::content";
+/// How many rotated log files are kept, unless configured otherwise.
+const DEFAULT_MAX_LOG_FILES: usize = 7;
+
+/// How often to start a new log file.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
 }
 
-fn get_log_file_path() -> PathBuf {
-    let log_file_name = "terminal-ai-ops.log".to_string();
-    #[cfg(any(target_os = "macos", target_os = "linux"))]
-    {
-        PathBuf::from("/tmp/").join(log_file_name)
-    }
-    #[cfg(target_os = "windows")]
-    {
-        let temp_dir = env::var("TEMP").unwrap_or_else(|_| "C:\\Windows\\Temp".to_string());
-        PathBuf::from(temp_dir).join(log_file_name)
+/// Logs to the log directory, in files rotated as configured, or appends to the log file if one is configured.
+/// What is logged comes from `RUST_LOG` if it is set, and otherwise from the configured log level.
+/// The same records are kept in `debug_log`, for the debug pane.
+pub fn init_tracing(settings: &Settings, debug_log: Arc<DebugLog>) -> Result<WorkerGuard, String> {
+    let mut filter = match env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.is_empty() => EnvFilter::try_new(&directives)
+            .map_err(|err| format!("Invalid {}: {}", EnvFilter::DEFAULT_ENV, err))?,
+        _ => {
+            let level = settings.log_level.as_deref().unwrap_or("info");
+            EnvFilter::try_new(level).map_err(|_| format!("Unknown log level {}", level))?
+        }
+    };
+    if settings.private_logs.unwrap_or_default() {
+        filter = filter.add_directive(format!("{}=off", CONTENT).parse().unwrap());
     }
+
+    let (non_blocking, guard) = match &settings.log_file {
+        Some(log_file) => {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file)
+                .map_err(|err| {
+                    format!(
+                        "Failed to open the log file {}: {}",
+                        log_file.display(),
+                        err
+                    )
+                })?;
+            non_blocking(file)
+        }
+        None => {
+            let directory = settings.log_dir.clone().unwrap_or_else(log_directory);
+            non_blocking(appender(&directory, settings)?)
+        }
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(non_blocking)
+                .with_ansi(false),
+        )
+        .with(DebugLayer::new(debug_log))
+        .init();
+    Ok(guard)
+}
+
+fn appender(directory: &Path, settings: &Settings) -> Result<RollingFileAppender, String> {
+    let rotation = match settings.log_rotation.unwrap_or_default() {
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    fs::create_dir_all(directory)
+        .and_then(|_| {
+            RollingFileAppender::builder()
+                .rotation(rotation)
+                .filename_prefix("terminal-ai-ops")
+                .filename_suffix("log")
+                .max_log_files(settings.log_max_files.unwrap_or(DEFAULT_MAX_LOG_FILES))
+                .build(directory)
+                .map_err(std::io::Error::other)
+        })
+        .map_err(|err| {
+            format!(
+                "Failed to create the log directory {}: {}",
+                directory.display(),
+                err
+            )
+        })
+}
+
+/// Where the logs go unless configured otherwise, which is private to the user, unlike the temp directory.
+pub fn log_directory() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(env::temp_dir)
+        .join("terminal-ai-ops")
+        .join("logs")
 }
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// The tokens one answer took, and what they cost.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Usage {
+    pub model: String,
+    // The tokens sent, which is the whole conversation up to the question, and the system prompt
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    // Counted by us rather than reported by the API, which some APIs and API versions don't do when streaming
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub estimated: bool,
+    // In US dollars, at the prices when the answer came. Unknown if there was no price for the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// Adds the usage of the rest of the same answer, as when it is continued.
+    pub fn add(&mut self, usage: Usage) {
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.estimated |= usage.estimated;
+        self.cost = self.cost.zip(usage.cost).map(|(cost, more)| cost + more);
+        self.model = usage.model;
+    }
+
+    /// Like `1.2k + 321 tokens, $0.0063`, with a `~` in front of estimates.
+    pub fn describe(&self) -> String {
+        let approximately = if self.estimated { "~" } else { "" };
+        let mut description = format!(
+            "{}{} + {} tokens",
+            approximately,
+            format_count(self.prompt_tokens),
+            format_count(self.completion_tokens)
+        );
+        if let Some(cost) = self.cost {
+            description.push_str(&format!(", {}{}", approximately, format_cost(cost)));
+        }
+        description
+    }
+}
+
+/// The usage of a whole session, added up over every answer in it, including those that were regenerated or deleted.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Total {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    // Whether any of the answers were estimated
+    pub estimated: bool,
+    // The cost of the answers with a known price
+    pub cost: Option<f64>,
+}
+
+impl Total {
+    pub fn of<'a>(usage: impl IntoIterator<Item = &'a Usage>) -> Self {
+        let mut total = Self::default();
+        for usage in usage {
+            total.prompt_tokens += usage.prompt_tokens;
+            total.completion_tokens += usage.completion_tokens;
+            total.estimated |= usage.estimated;
+            if let Some(cost) = usage.cost {
+                *total.cost.get_or_insert(0.0) += cost;
+            }
+        }
+        total
+    }
+
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_tokens() == 0
+    }
+}
+
+/// What a model costs, in US dollars per million tokens.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// The price of each model, from the defaults below and the `[prices]` table of the config file.
+pub struct Prices(HashMap<String, Price>);
+
+impl Default for Prices {
+    fn default() -> Self {
+        Self::new(&HashMap::new())
+    }
+}
+
+impl Prices {
+    /// The list prices of the OpenAI models we know, changed by `configured`, as prices change and there are more
+    /// models than these.
+    pub fn new(configured: &HashMap<String, Price>) -> Self {
+        let mut prices: HashMap<String, Price> = [
+            ("gpt-4o", 2.50, 10.00),
+            ("gpt-4o-mini", 0.15, 0.60),
+            ("gpt-4-turbo", 10.00, 30.00),
+            ("gpt-4", 30.00, 60.00),
+            ("gpt-3.5-turbo", 0.50, 1.50),
+        ]
+        .into_iter()
+        .map(|(model, input, output)| (model.to_string(), Price { input, output }))
+        .collect();
+        prices.extend(configured.clone());
+        Self(prices)
+    }
+
+    /// The price of `model`. Without a price for the model itself, dated versions like `gpt-4o-2024-08-06` get the
+    /// price of the longest name they start with.
+    pub fn get(&self, model: &str) -> Option<Price> {
+        if let Some(price) = self.0.get(model) {
+            return Some(*price);
+        }
+        self.0
+            .iter()
+            .filter(|(name, _)| model.starts_with(&format!("{}-", name)))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| *price)
+    }
+
+    /// Fills in what `usage` cost, if the model has a price.
+    pub fn apply(&self, usage: &mut Usage) {
+        usage.cost = self.get(&usage.model).map(|price| {
+            (usage.prompt_tokens as f64 * price.input
+                + usage.completion_tokens as f64 * price.output)
+                / 1_000_000.0
+        });
+    }
+}
+
+/// Like `1.2k` above a thousand, to keep it short.
+pub fn format_count(count: u32) -> String {
+    match count {
+        0..=999 => count.to_string(),
+        _ => format!("{:.1}k", count as f64 / 1000.0),
+    }
+}
+
+/// Cents are too coarse for a single answer, so small amounts get more digits.
+pub fn format_cost(cost: f64) -> String {
+    match cost {
+        cost if cost < 0.01 => format!("${:.4}", cost),
+        cost => format!("${:.2}", cost),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(model: &str, cost: Option<f64>) -> Usage {
+        Usage {
+            model: model.to_string(),
+            prompt_tokens: 1000,
+            completion_tokens: 200,
+            estimated: false,
+            cost,
+        }
+    }
+
+    #[test]
+    fn prices_dated_versions_like_the_longest_model_they_start_with() {
+        let prices = Prices::default();
+        assert_eq!(prices.get("gpt-4o").unwrap().input, 2.50);
+        assert_eq!(prices.get("gpt-4o-2024-08-06").unwrap().input, 2.50);
+        assert_eq!(prices.get("gpt-4o-mini-2024-07-18").unwrap().input, 0.15);
+        assert_eq!(prices.get("gpt-4-0613").unwrap().input, 30.00);
+        // Only whole parts of the name count, `gpt-4o` isn't a version of `gpt-4`
+        assert!(prices.get("gpt-4omni").is_none());
+        assert!(prices.get("llama-3").is_none());
+    }
+
+    #[test]
+    fn configured_prices_replace_and_add_to_the_defaults() {
+        let configured = HashMap::from([
+            (
+                "gpt-4o".to_string(),
+                Price {
+                    input: 1.0,
+                    output: 2.0,
+                },
+            ),
+            (
+                "llama-3".to_string(),
+                Price {
+                    input: 0.1,
+                    output: 0.2,
+                },
+            ),
+        ]);
+        let prices = Prices::new(&configured);
+        assert_eq!(prices.get("gpt-4o-2024-08-06").unwrap().input, 1.0);
+        assert_eq!(prices.get("llama-3-70b").unwrap().output, 0.2);
+
+        let mut usage = usage("gpt-4o", None);
+        prices.apply(&mut usage);
+        assert_eq!(usage.cost, Some((1000.0 * 1.0 + 200.0 * 2.0) / 1_000_000.0));
+    }
+
+    #[test]
+    fn adds_the_usage_of_a_continued_answer() {
+        let mut first = usage("gpt-4o", Some(0.5));
+        let mut rest = usage("gpt-4o-mini", Some(0.25));
+        rest.estimated = true;
+        first.add(rest);
+        assert_eq!(first.prompt_tokens, 2000);
+        assert_eq!(first.completion_tokens, 400);
+        assert!(first.estimated);
+        assert_eq!(first.cost, Some(0.75));
+        assert_eq!(first.model, "gpt-4o-mini");
+
+        // The cost of the whole answer isn't known if that of any part of it isn't
+        first.add(usage("gpt-4o", None));
+        assert_eq!(first.cost, None);
+    }
+}